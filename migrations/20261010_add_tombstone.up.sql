@@ -0,0 +1,8 @@
+CREATE TABLE IF NOT EXISTS biominer_indexd_tombstone (
+  guid VARCHAR(64) PRIMARY KEY, -- The deleted file's global unique identifier, it will never be assigned to another file
+  baseid VARCHAR(64) NOT NULL, -- The base identifier of the deleted file
+  reason VARCHAR(255) DEFAULT NULL, -- Why the file was deleted
+  deleted_by VARCHAR(64) NOT NULL DEFAULT 'biominer-admin', -- The user who deleted the file
+  purged BOOLEAN NOT NULL DEFAULT FALSE, -- Whether the file record and all its urls, hashes, aliases and tags have been removed
+  deleted_at BIGINT NOT NULL DEFAULT EXTRACT(EPOCH from now()) * 1000 -- When the file was deleted, milliseconds since epoch
+);
@@ -0,0 +1 @@
+DROP INDEX IF EXISTS biominer_indexd_file_baseid_version_idx;
@@ -0,0 +1 @@
+CREATE UNIQUE INDEX IF NOT EXISTS biominer_indexd_file_baseid_version_idx ON biominer_indexd_file (baseid, version); -- Each version of a file is registered only once
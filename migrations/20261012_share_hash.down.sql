@@ -0,0 +1,5 @@
+-- Keep the earliest file of a shared hash, the hash rows of the other files are removed before the hash is unique again
+DELETE FROM biominer_indexd_hash h USING biominer_indexd_hash o WHERE h.hash = o.hash AND h.id > o.id;
+DROP INDEX IF EXISTS biominer_indexd_hash_hash_idx;
+ALTER TABLE biominer_indexd_hash DROP CONSTRAINT IF EXISTS biominer_indexd_hash_file_hash_key;
+ALTER TABLE biominer_indexd_hash ADD CONSTRAINT biominer_indexd_hash_hash_key UNIQUE (hash);
@@ -0,0 +1,4 @@
+-- The same content may be registered by several files, e.g. the same FASTQ in different projects, so a hash is only unique in a file
+ALTER TABLE biominer_indexd_hash DROP CONSTRAINT IF EXISTS biominer_indexd_hash_hash_key;
+ALTER TABLE biominer_indexd_hash ADD CONSTRAINT biominer_indexd_hash_file_hash_key UNIQUE (file, hash);
+CREATE INDEX IF NOT EXISTS biominer_indexd_hash_hash_idx ON biominer_indexd_hash (hash); -- Find the files by the hash
@@ -0,0 +1,11 @@
+CREATE TABLE IF NOT EXISTS biominer_indexd_status_history (
+  id BIGSERIAL PRIMARY KEY, -- The change's unique identifier
+  file VARCHAR(64) NOT NULL, -- The file's global unique identifier, the history is kept after the file is purged
+  from_status VARCHAR(16) NOT NULL, -- The status before the change
+  to_status VARCHAR(16) NOT NULL, -- The status after the change
+  actor VARCHAR(64) NOT NULL, -- The user who changed the status
+  reason VARCHAR(255) DEFAULT NULL, -- Why the status was changed, e.g. the QC report
+  created_at BIGINT NOT NULL DEFAULT EXTRACT(EPOCH from now()) * 1000 -- When the status was changed, milliseconds since epoch
+);
+
+CREATE INDEX IF NOT EXISTS biominer_indexd_status_history_file_idx ON biominer_indexd_status_history (file, created_at);
@@ -0,0 +1,2 @@
+ALTER TABLE biominer_indexd_url DROP COLUMN IF EXISTS checked_at;
+ALTER TABLE biominer_indexd_url DROP COLUMN IF EXISTS reason;
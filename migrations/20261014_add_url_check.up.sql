@@ -0,0 +1,2 @@
+ALTER TABLE biominer_indexd_url ADD COLUMN IF NOT EXISTS reason VARCHAR(255) DEFAULT NULL; -- Why the url is validated or failed, reported by the url checker
+ALTER TABLE biominer_indexd_url ADD COLUMN IF NOT EXISTS checked_at BIGINT DEFAULT NULL; -- When the url was checked, milliseconds since epoch
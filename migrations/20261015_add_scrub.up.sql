@@ -0,0 +1,9 @@
+CREATE TABLE IF NOT EXISTS biominer_indexd_scrub (
+  file VARCHAR(64) PRIMARY KEY, -- The file's global unique identifier
+  url VARCHAR(255) DEFAULT NULL, -- The url which the content is read from, NULL if no url is readable
+  result VARCHAR(16) NOT NULL, -- verified, mismatched or unreadable
+  reason VARCHAR(255) DEFAULT NULL, -- The details of the result, e.g. which hash does not match
+  scrubbed_at BIGINT NOT NULL -- When the file was scrubbed, milliseconds since epoch
+);
+
+CREATE INDEX IF NOT EXISTS biominer_indexd_scrub_scrubbed_at_idx ON biominer_indexd_scrub (scrubbed_at);
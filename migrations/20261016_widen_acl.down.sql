@@ -0,0 +1 @@
+ALTER TABLE biominer_indexd_file ALTER COLUMN acl TYPE VARCHAR(255);
@@ -0,0 +1,2 @@
+-- The acl keeps the groups and the resource paths, such as 'admin,/programs/pgx/projects/quartet', so it may be long
+ALTER TABLE biominer_indexd_file ALTER COLUMN acl TYPE TEXT;
@@ -0,0 +1 @@
+DROP TABLE IF EXISTS biominer_indexd_upload;
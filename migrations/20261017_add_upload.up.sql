@@ -0,0 +1,9 @@
+CREATE TABLE IF NOT EXISTS biominer_indexd_upload (
+  file VARCHAR(64) PRIMARY KEY, -- The file's global unique identifier, a file has at most one upload in progress
+  protocol VARCHAR(16) NOT NULL, -- s3 or minio
+  bucket VARCHAR(255) NOT NULL, -- The bucket which the object is uploaded to
+  key VARCHAR(1024) NOT NULL, -- The object name, <uuid>/<filename>, it is chosen by indexd rather than the client
+  upload_id VARCHAR(1024) DEFAULT NULL, -- The upload id of the multipart upload, NULL if the object is uploaded at once
+  created_by VARCHAR(64) NOT NULL, -- The user who started the upload
+  created_at BIGINT NOT NULL DEFAULT EXTRACT(EPOCH from now()) * 1000 -- When the upload was started, milliseconds since epoch
+);
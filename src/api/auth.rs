@@ -1,14 +1,19 @@
+use crate::util;
 use base64;
 use jsonwebtoken::{decode, errors::ErrorKind, Algorithm, DecodingKey, Validation};
 use lazy_static::lazy_static;
 use log::{debug, error, info, warn};
-use poem::Request;
-use poem_openapi::auth::Bearer;
-use poem_openapi::SecurityScheme;
+use poem::http::header::AUTHORIZATION;
+use poem::{Request, RequestBody};
+use poem_openapi::auth::{Bearer, BearerAuthorization};
+use poem_openapi::error::AuthorizationError;
+use poem_openapi::registry::{MetaSecurityScheme, Registry};
+use poem_openapi::{ApiExtractor, ApiExtractorType, ExtractParamOptions};
 use reqwest::Error as ReqwestError;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::RwLock;
 
 pub const USERNAME_PLACEHOLDER: &str = "ANONYMOUS-USER-PLACEHOLDER";
@@ -18,13 +23,33 @@ lazy_static! {
     static ref PUBLIC_KEYS: RwLock<Vec<String>> = RwLock::new(vec![]);
 }
 
+// The requests without a token are rejected unless the anonymous access is enabled.
+static ALLOW_ANONYMOUS: AtomicBool = AtomicBool::new(false);
+
+/// Enable or disable the anonymous access, the anonymous user can only access the public files.
+pub fn set_allow_anonymous(allow: bool) {
+    ALLOW_ANONYMOUS.store(allow, Ordering::Relaxed);
+}
+
+pub fn is_anonymous_allowed() -> bool {
+    ALLOW_ANONYMOUS.load(Ordering::Relaxed)
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct User {
     pub username: String,
     pub email: String,
+    #[serde(default)]
     pub organizations: Vec<i32>,
+    #[serde(default)]
     pub projects: Vec<i32>,
     pub roles: Vec<String>, // The role item must be in the following list: ["Administrator", "Premium Member", "Standard User"]. They are same as the roles on the Auth0 dashboard.
+    // The granted resource paths, such as /programs/pgx, which grant their children too.
+    #[serde(default)]
+    pub resources: Vec<String>,
+    // The groups and resource paths whose files the user can change, the read grants above do not allow changes.
+    #[serde(default)]
+    pub write_grants: Vec<String>,
 }
 
 impl User {
@@ -36,6 +61,8 @@ impl User {
             organizations: vec![-1],
             projects: vec![-1],
             roles: roles,
+            resources: vec![],
+            write_grants: vec![],
         }
     }
 
@@ -47,6 +74,14 @@ impl User {
         self.projects = projects;
     }
 
+    fn add_resources(&mut self, resources: Vec<String>) {
+        self.resources = resources;
+    }
+
+    fn add_write_grants(&mut self, write_grants: Vec<String>) {
+        self.write_grants = write_grants;
+    }
+
     fn add_roles(&mut self, roles: Vec<String>) {
         self.roles = roles;
     }
@@ -62,6 +97,73 @@ impl User {
     pub fn is_standard_user(&self) -> bool {
         self.roles.contains(&"Standard User".to_string())
     }
+
+    pub fn anonymous() -> Self {
+        User::new(USERNAME_PLACEHOLDER, EMAIL_PLACEHOLDER, vec![])
+    }
+
+    /// The user of a request without a token, it cannot change anything.
+    pub fn is_anonymous(&self) -> bool {
+        self.username == USERNAME_PLACEHOLDER
+    }
+
+    /// The groups of the user which are checked against the acl of a file: the roles, `organization:<id>`,
+    /// `project:<id>` and the resource paths. The placeholder ids of the old tokens are skipped.
+    pub fn groups(&self) -> Vec<String> {
+        let organizations = self
+            .organizations
+            .iter()
+            .filter(|id| **id >= 0)
+            .map(|id| format!("organization:{}", id));
+        let projects = self
+            .projects
+            .iter()
+            .filter(|id| **id >= 0)
+            .map(|id| format!("project:{}", id));
+
+        self.roles
+            .iter()
+            .cloned()
+            .chain(organizations)
+            .chain(projects)
+            .chain(self.resources.iter().cloned())
+            .collect()
+    }
+
+    /// The groups to filter the files by, None means all the files are accessible, such as for an administrator.
+    pub fn auth_groups(&self) -> Option<String> {
+        if self.is_admin() {
+            None
+        } else {
+            Some(self.groups().join(","))
+        }
+    }
+
+    /// Whether the user can access a file with the acl, a file without acl is public.
+    pub fn can_access(&self, acl: Option<&str>) -> bool {
+        match (acl, self.auth_groups()) {
+            (None, _) | (_, None) => true,
+            (Some(_), Some(groups)) if groups.is_empty() => false,
+            (Some(acl), Some(groups)) => util::has_permission(&groups, acl),
+        }
+    }
+
+    /// Whether the user can change a file of the uploader with the acl: the uploader, an administrator, or a user
+    /// whose write grants match the acl. A file without acl can only be changed by its uploader or an administrator.
+    pub fn can_write(&self, uploader: &str, acl: Option<&str>) -> bool {
+        if self.is_anonymous() {
+            return false;
+        }
+        if self.is_admin() || self.username == uploader {
+            return true;
+        }
+        match acl {
+            Some(acl) if !self.write_grants.is_empty() => {
+                util::has_permission(&self.write_grants.join(","), acl)
+            }
+            _ => false,
+        }
+    }
 }
 
 fn get_username_from_claims(claims: &Claims) -> Option<String> {
@@ -124,6 +226,51 @@ struct Claims {
 }
 
 impl Claims {
+    // The organizations and projects are custom claims like the roles, such as "https://drugs.3steps.cn/projects".
+    fn get_ids(&self, suffix: &str) -> Option<Vec<i32>> {
+        self.extra.iter().find_map(|(key, value)| {
+            if key.ends_with(suffix) {
+                value.as_array().map(|arr| {
+                    arr.iter()
+                        .filter_map(|item| item.as_i64().map(|id| id as i32))
+                        .collect::<Vec<i32>>()
+                })
+            } else {
+                None
+            }
+        })
+    }
+
+    pub fn get_organizations(&self) -> Option<Vec<i32>> {
+        self.get_ids("/organizations")
+    }
+
+    pub fn get_projects(&self) -> Option<Vec<i32>> {
+        self.get_ids("/projects")
+    }
+
+    fn get_strings(&self, suffix: &str) -> Option<Vec<String>> {
+        self.extra.iter().find_map(|(key, value)| {
+            if key.ends_with(suffix) {
+                value.as_array().map(|arr| {
+                    arr.iter()
+                        .filter_map(|item| item.as_str().map(|s| s.to_owned()))
+                        .collect::<Vec<String>>()
+                })
+            } else {
+                None
+            }
+        })
+    }
+
+    pub fn get_resources(&self) -> Option<Vec<String>> {
+        self.get_strings("/resources")
+    }
+
+    pub fn get_write_grants(&self) -> Option<Vec<String>> {
+        self.get_strings("/write_grants")
+    }
+
     pub fn get_roles(&self) -> Option<Vec<String>> {
         // 1. 找到那个以 "/roles" 结尾的 key
         //    因为域名前缀可能变，比如 "https://drugs.3steps.cn/roles" 或其他
@@ -268,13 +415,59 @@ fn detect_kid(token_str: &str) -> Option<String> {
     }
 }
 
-#[derive(SecurityScheme)]
-#[oai(type = "bearer", checker = "jwt_token_checker")]
+/// The bearer token of the user, the request without a token is served as the anonymous user if it is allowed.
+///
+/// It is the same as `#[derive(SecurityScheme)]` with `checker = "jwt_token_checker"`, except that the token
+/// is optional for the anonymous access.
 pub struct CustomSecurityScheme(pub User);
 
+#[poem::async_trait]
+impl<'a> ApiExtractor<'a> for CustomSecurityScheme {
+    const TYPE: ApiExtractorType = ApiExtractorType::SecurityScheme;
+
+    type ParamType = ();
+    type ParamRawType = ();
+
+    fn register(registry: &mut Registry) {
+        registry.create_security_scheme(
+            "CustomSecurityScheme",
+            MetaSecurityScheme {
+                ty: "http",
+                description: Some("A JWT token, it can be omitted if the anonymous access is allowed."),
+                name: None,
+                key_in: None,
+                scheme: Some("bearer"),
+                bearer_format: Some("JWT"),
+                flows: None,
+                openid_connect_url: None,
+            },
+        );
+    }
+
+    fn security_scheme() -> Option<&'static str> {
+        Some("CustomSecurityScheme")
+    }
+
+    async fn from_request(
+        req: &'a Request,
+        _body: &mut RequestBody,
+        _param_opts: ExtractParamOptions<Self::ParamType>,
+    ) -> poem::Result<Self> {
+        if !req.headers().contains_key(AUTHORIZATION) && is_anonymous_allowed() {
+            return Ok(Self(User::anonymous()));
+        }
+
+        let bearer = <Bearer as BearerAuthorization>::from_request(req)?;
+        match jwt_token_checker(req, bearer).await {
+            Some(user) => Ok(Self(user)),
+            None => Err(AuthorizationError.into()),
+        }
+    }
+}
+
 async fn jwt_token_checker(_: &Request, bearer: Bearer) -> Option<User> {
     // Get jwt_secret_key from environment variable
-    let default_user = Some(User::new(USERNAME_PLACEHOLDER, EMAIL_PLACEHOLDER, vec![]));
+    let default_user = Some(User::anonymous());
 
     let jwt_secret_key = match std::env::var("JWT_SECRET_KEY") {
         Ok(key) => key,
@@ -288,7 +481,12 @@ async fn jwt_token_checker(_: &Request, bearer: Bearer) -> Option<User> {
 
     let token_str = bearer.token;
     if jwt_secret_key.is_empty() && jwt_client_id.is_empty() {
-        warn!("You don't set JWT_SECRET_KEY and JWT_CLIENT_ID environment variable, so we will skip JWT verification, but users also need to set the Authorization header to access the API.");
+        if !is_anonymous_allowed() {
+            error!("You don't set JWT_SECRET_KEY and JWT_CLIENT_ID environment variable and the anonymous access is not allowed, so the token cannot be verified.");
+            return None;
+        }
+
+        warn!("You don't set JWT_SECRET_KEY and JWT_CLIENT_ID environment variable, so we will skip JWT verification and serve the request as the anonymous user.");
         return default_user;
     } else {
         debug!("JWT_SECRET_KEY: {}", jwt_secret_key);
@@ -361,7 +559,20 @@ async fn jwt_token_checker(_: &Request, bearer: Bearer) -> Option<User> {
 
                     info!("Claims: {:?}, username: {}, email: {}, roles: {:?}", claims, username, email, roles);
 
-                    return Some(User::new(&username, email, roles));
+                    let mut user = User::new(&username, email, roles);
+                    if let Some(organizations) = claims.get_organizations() {
+                        user.add_organizations(organizations);
+                    }
+                    if let Some(projects) = claims.get_projects() {
+                        user.add_projects(projects);
+                    }
+                    if let Some(resources) = claims.get_resources() {
+                        user.add_resources(resources);
+                    }
+                    if let Some(write_grants) = claims.get_write_grants() {
+                        user.add_write_grants(write_grants);
+                    }
+                    Some(user)
                 }
                 Err(err) => {
                     error!("Error: {}", err);
@@ -410,4 +621,85 @@ mod tests {
             .unwrap();
         assert_eq!(validated_claims.standard_claims.name, "Craig Yang");
     }
+
+    #[test]
+    fn test_user_groups() {
+        let mut user = User::new("test_user", "test@example.com", vec!["Standard User".to_string()]);
+        assert_eq!(user.groups(), vec!["Standard User"]);
+        assert!(user.can_access(None));
+        assert!(user.can_access(Some("admin,Standard User")));
+        assert!(!user.can_access(Some("project:1")));
+
+        user.add_organizations(vec![2]);
+        user.add_projects(vec![1, 3]);
+        assert_eq!(
+            user.groups(),
+            vec!["Standard User", "organization:2", "project:1", "project:3"]
+        );
+        assert!(user.can_access(Some("project:1")));
+
+        // A resource path grants its children
+        user.add_resources(vec!["/programs/pgx".to_string()]);
+        assert!(user.can_access(Some("/programs/pgx/projects/quartet")));
+        assert!(!user.can_access(Some("/programs/tcga")));
+
+        // The anonymous user can only access the public files
+        let anonymous = User::anonymous();
+        assert!(anonymous.groups().is_empty());
+        assert_eq!(anonymous.auth_groups().as_deref(), Some(""));
+        assert!(anonymous.can_access(None));
+        assert!(!anonymous.can_access(Some("")));
+
+        // The administrator can access all the files
+        user.add_roles(vec!["Administrator".to_string()]);
+        assert_eq!(user.auth_groups(), None);
+        assert!(user.can_access(Some("project:4")));
+    }
+
+    #[test]
+    fn test_can_write() {
+        let mut user = User::new("test_user", "test@example.com", vec!["Standard User".to_string()]);
+        user.add_resources(vec!["/programs/pgx".to_string()]);
+        assert!(user.can_write("test_user", None));
+        assert!(user.can_write("test_user", Some("project:1")));
+
+        // A read grant does not allow changes
+        assert!(user.can_access(Some("/programs/pgx/projects/quartet")));
+        assert!(!user.can_write("another_user", Some("/programs/pgx/projects/quartet")));
+        assert!(!user.can_write("another_user", None));
+
+        user.add_write_grants(vec!["/programs/pgx".to_string()]);
+        assert!(user.can_write("another_user", Some("/programs/pgx/projects/quartet")));
+        assert!(!user.can_write("another_user", Some("/programs/tcga")));
+        assert!(!user.can_write("another_user", None));
+
+        assert!(!User::anonymous().can_write(USERNAME_PLACEHOLDER, None));
+
+        user.add_roles(vec!["Administrator".to_string()]);
+        assert!(user.can_write("another_user", None));
+    }
+
+    #[test]
+    fn test_hs256_token() {
+        let claims = serde_json::json!({
+            "username": "test_user",
+            "email": "test@example.com",
+            "projects": [1],
+            "roles": ["Standard User"],
+            "exp": 10000000000i64,
+        });
+        let token = jsonwebtoken::encode(
+            &jsonwebtoken::Header::new(Algorithm::HS256),
+            &claims,
+            &jsonwebtoken::EncodingKey::from_secret(b"secret"),
+        )
+        .unwrap();
+
+        // The token without organizations is still valid
+        let user = validate_token_with_hs256(&token, "secret").unwrap();
+        assert_eq!(user.username, "test_user");
+        assert_eq!(user.groups(), vec!["Standard User", "project:1"]);
+
+        assert!(validate_token_with_hs256(&token, "another secret").is_err());
+    }
 }
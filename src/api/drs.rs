@@ -0,0 +1,559 @@
+//! The GA4GH Data Repository Service (DRS) v1 api on top of the indexed files.
+//!
+//! A DRS object is a file, its id is the uuid of the guid because a DRS id cannot contain a slash. Every repo
+//! which the file has been released on is an access method, whose access id is the protocol of the repo, such as
+//! node or s3. See https://ga4gh.github.io/data-repository-service-schemas/preview/release/drs-1.2.0/docs/
+use crate::api::auth::{CustomSecurityScheme, User};
+use crate::api::route::{check_access, sign_file_data, SignFailure};
+use crate::model::datafile::{Config, File, Hash, URL};
+use crate::repo_config::{which_protocol, RepoConfig};
+use crate::util;
+use log::info;
+use poem::http::header::AUTHORIZATION;
+use poem::web::Data;
+use poem::Request;
+use poem_openapi::{param::Path, payload::Json, ApiResponse, Object, OpenApi, Tags};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+const DRS_PREFIX: &str = "/ga4gh/drs/v1";
+
+#[derive(Tags)]
+enum DrsApiTags {
+    Objects,
+    Service,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Object)]
+pub struct DrsError {
+    pub msg: String,
+    pub status_code: u16,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Object)]
+pub struct Checksum {
+    pub checksum: String,
+    // md5, sha1, sha-256, sha-512 or etag
+    #[oai(rename = "type")]
+    #[serde(rename = "type")]
+    pub checksum_type: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Object)]
+pub struct AccessUrl {
+    pub url: String,
+    // The headers to send with the request, such as "Authorization: Bearer token"
+    pub headers: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Object)]
+#[oai(skip_serializing_if_is_none)]
+pub struct AccessMethod {
+    #[oai(rename = "type")]
+    #[serde(rename = "type")]
+    pub access_type: String,
+    // The protocol of the repo, such as node or s3
+    pub access_id: Option<String>,
+    pub region: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Object)]
+#[oai(skip_serializing_if_is_none)]
+pub struct DrsObject {
+    pub id: String,
+    pub name: String,
+    // drs://<host>/<id>
+    pub self_uri: String,
+    pub size: i64,
+    pub created_time: String,
+    pub updated_time: String,
+    pub version: String,
+    pub checksums: Vec<Checksum>,
+    pub access_methods: Vec<AccessMethod>,
+    pub aliases: Vec<String>,
+    pub description: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Object)]
+pub struct ServiceType {
+    pub group: String,
+    pub artifact: String,
+    pub version: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Object)]
+pub struct Organization {
+    pub name: String,
+    pub url: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Object)]
+pub struct ServiceInfo {
+    pub id: String,
+    pub name: String,
+    #[oai(rename = "type")]
+    #[serde(rename = "type")]
+    pub service_type: ServiceType,
+    pub description: String,
+    pub organization: Organization,
+    pub version: String,
+}
+
+#[derive(ApiResponse)]
+enum GetServiceInfoResponse {
+    #[oai(status = 200)]
+    Ok(Json<ServiceInfo>),
+}
+
+#[derive(ApiResponse)]
+#[allow(clippy::large_enum_variant)]
+enum GetObjectResponse {
+    #[oai(status = 200)]
+    Ok(Json<DrsObject>),
+
+    #[oai(status = 401)]
+    Unauthorized(Json<DrsError>),
+
+    // The user has signed in, but the file is not granted to the user.
+    #[oai(status = 403)]
+    Forbidden(Json<DrsError>),
+
+    #[oai(status = 404)]
+    NotFound(Json<DrsError>),
+
+    #[oai(status = 500)]
+    InternalError(Json<DrsError>),
+}
+
+#[derive(ApiResponse)]
+enum GetAccessUrlResponse {
+    #[oai(status = 200)]
+    Ok(Json<AccessUrl>),
+
+    #[oai(status = 401)]
+    Unauthorized(Json<DrsError>),
+
+    // The user has signed in, but the file is not granted to the user.
+    #[oai(status = 403)]
+    Forbidden(Json<DrsError>),
+
+    #[oai(status = 404)]
+    NotFound(Json<DrsError>),
+
+    #[oai(status = 500)]
+    InternalError(Json<DrsError>),
+}
+
+fn drs_error(msg: String, status_code: u16) -> Json<DrsError> {
+    Json(DrsError { msg, status_code })
+}
+
+impl GetObjectResponse {
+    /// The anonymous users are asked to sign in, the others are forbidden.
+    fn from_failure(failure: SignFailure, user: &User) -> Self {
+        match failure {
+            SignFailure::Unauthorized(msg) if user.is_anonymous() => {
+                GetObjectResponse::Unauthorized(drs_error(msg, 401))
+            }
+            SignFailure::Unauthorized(msg) => GetObjectResponse::Forbidden(drs_error(msg, 403)),
+            SignFailure::NotFound(msg) | SignFailure::Gone(msg) | SignFailure::NotOnRepo(msg) => {
+                GetObjectResponse::NotFound(drs_error(msg, 404))
+            }
+            SignFailure::NotReleased(msg) | SignFailure::Internal(msg) => {
+                GetObjectResponse::InternalError(drs_error(msg, 500))
+            }
+        }
+    }
+}
+
+impl GetAccessUrlResponse {
+    /// The same as `GetObjectResponse::from_failure`.
+    fn from_failure(failure: SignFailure, user: &User) -> Self {
+        match failure {
+            SignFailure::Unauthorized(msg) if user.is_anonymous() => {
+                GetAccessUrlResponse::Unauthorized(drs_error(msg, 401))
+            }
+            SignFailure::Unauthorized(msg) => GetAccessUrlResponse::Forbidden(drs_error(msg, 403)),
+            SignFailure::NotFound(msg) | SignFailure::Gone(msg) | SignFailure::NotOnRepo(msg) => {
+                GetAccessUrlResponse::NotFound(drs_error(msg, 404))
+            }
+            SignFailure::NotReleased(msg) | SignFailure::Internal(msg) => {
+                GetAccessUrlResponse::InternalError(drs_error(msg, 500))
+            }
+        }
+    }
+}
+
+/// The id of the file in a DRS object id, which may be a drs uri, such as drs://<host>/<id>.
+pub fn file_id_of(id: &str) -> &str {
+    match id.strip_prefix("drs://") {
+        Some(rest) => rest.split_once('/').map(|(_, id)| id).unwrap_or(rest),
+        None => id,
+    }
+}
+
+/// The DRS id of the file, the uuid in biominer.<registry_id>/<uuid>.
+pub fn drs_id(guid: &str) -> &str {
+    guid.rsplit('/').next().unwrap_or(guid)
+}
+
+/// The checksum types of DRS follow the IANA hash function textual names.
+fn checksum_type(hash_type: &str) -> String {
+    match hash_type {
+        "sha256" => "sha-256".to_string(),
+        "sha512" => "sha-512".to_string(),
+        _ => hash_type.to_string(),
+    }
+}
+
+/// Every repo which can sign the urls of the file is an access method, the failed urls are skipped if the
+/// selection policy excludes them.
+///
+/// The type of a method is the scheme of its access url: the signed url if it is a plain GET, otherwise the
+/// download endpoint of this service, whose scheme is the scheme of the request.
+async fn access_methods(file: &File, config: &RepoConfig, scheme: &str) -> Vec<AccessMethod> {
+    let urls: Vec<URL> = match &file.urls {
+        Some(urls) => serde_json::from_value(urls.clone()).unwrap_or_default(),
+        None => vec![],
+    };
+
+    let mut methods: Vec<AccessMethod> = vec![];
+    for url in urls.iter() {
+        if config.policy.exclude_failed && url.status == "failed" {
+            continue;
+        }
+
+        let protocol = match which_protocol(&url.url) {
+            Some(protocol) => protocol,
+            None => continue,
+        };
+        if methods.iter().any(|m| m.access_id.as_deref() == Some(protocol)) {
+            continue;
+        }
+
+        if let Some(signer) = config.fetch_config(protocol, &url.get_identity()) {
+            let sign = match signer.sign(&url.url).await {
+                Ok(sign) => sign,
+                Err(_) => continue,
+            };
+            let access_type = if sign.method.eq_ignore_ascii_case("GET") && sign.form.is_empty() {
+                scheme_of(&sign.baseurl).unwrap_or(scheme)
+            } else {
+                scheme
+            };
+            methods.push(AccessMethod {
+                access_type: access_type.to_lowercase(),
+                access_id: Some(protocol.to_string()),
+                region: signer
+                    .region()
+                    .or_else(|| config.policy.regions.get(protocol).cloned()),
+            });
+        }
+    }
+
+    methods
+}
+
+/// The scheme of the url, such as https or file.
+fn scheme_of(url: &str) -> Option<&str> {
+    url.split_once("://").map(|(scheme, _)| scheme)
+}
+
+async fn drs_object(file: &File, config: &RepoConfig, host: &str, scheme: &str) -> DrsObject {
+    let hashes: Vec<Hash> = match &file.hashes {
+        Some(hashes) => serde_json::from_value(hashes.clone()).unwrap_or_default(),
+        None => vec![],
+    };
+    let aliases: Vec<String> = match &file.aliases {
+        Some(aliases) => serde_json::from_value::<Vec<serde_json::Value>>(aliases.clone())
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|alias| alias["name"].as_str().map(|name| name.to_string()))
+            .collect(),
+        None => vec![],
+    };
+    let id = drs_id(&file.guid);
+
+    DrsObject {
+        id: id.to_string(),
+        name: file.filename.clone(),
+        self_uri: format!("drs://{}/{}", host, id),
+        size: file.size,
+        created_time: util::to_rfc3339(file.created_at),
+        updated_time: util::to_rfc3339(file.updated_at),
+        version: file.rev.clone(),
+        checksums: hashes
+            .iter()
+            .map(|hash| Checksum {
+                checksum: hash.hash.clone(),
+                checksum_type: checksum_type(&hash.hash_type),
+            })
+            .collect(),
+        access_methods: access_methods(file, config, scheme).await,
+        aliases,
+        description: None,
+    }
+}
+
+/// The host which the clients connect to, it is the host in the self uri of the objects.
+fn host_of(req: &Request) -> String {
+    req.header("X-Forwarded-Host")
+        .or_else(|| req.header("Host"))
+        .unwrap_or("localhost")
+        .to_string()
+}
+
+/// The scheme which the clients connect with.
+fn request_scheme_of(req: &Request) -> &str {
+    req.header("X-Forwarded-Proto").unwrap_or("http")
+}
+
+/// The url of the service before the DRS prefix, such as http://<host>/<base_path>.
+fn base_url_of(req: &Request) -> String {
+    let scheme = request_scheme_of(req);
+    let path = req.original_uri().path();
+    let base = match path.find(DRS_PREFIX) {
+        Some(index) => &path[..index],
+        None => "",
+    };
+    format!("{}://{}{}", scheme, host_of(req), base)
+}
+
+pub struct DrsApi;
+
+#[OpenApi(prefix_path = "/ga4gh/drs/v1")]
+impl DrsApi {
+    /// Call `/ga4gh/drs/v1/service-info` to get the information of the DRS service.
+    #[oai(
+        path = "/service-info",
+        method = "get",
+        tag = "DrsApiTags::Service",
+        operation_id = "getServiceInfo"
+    )]
+    async fn service_info(&self, config: Data<&Arc<Config>>) -> GetServiceInfoResponse {
+        GetServiceInfoResponse::Ok(Json(ServiceInfo {
+            id: format!("biominer.{}", config.registry_id),
+            name: "BioMiner Indexd".to_string(),
+            service_type: ServiceType {
+                group: "org.ga4gh".to_string(),
+                artifact: "drs".to_string(),
+                version: "1.2.0".to_string(),
+            },
+            description: "A hash-based data indexing and tracking service.".to_string(),
+            organization: Organization {
+                name: "BioMiner".to_string(),
+                url: "https://github.com/yjcyxky/biominer-indexd".to_string(),
+            },
+            version: env!("CARGO_PKG_VERSION").to_string(),
+        }))
+    }
+
+    /// Call `/ga4gh/drs/v1/objects/:object_id` to get the DRS object of a file.
+    ///
+    /// The object id is the id of the file, or a guid, hash, alias or drs uri with the slashes encoded as %2F.
+    #[oai(
+        path = "/objects/:object_id",
+        method = "get",
+        tag = "DrsApiTags::Objects",
+        operation_id = "getObject"
+    )]
+    async fn get_object(
+        &self,
+        req: &Request,
+        pool: Data<&Arc<sqlx::PgPool>>,
+        config: Data<&Arc<RepoConfig>>,
+        object_id: Path<String>,
+        auth: CustomSecurityScheme,
+    ) -> GetObjectResponse {
+        info!("Get the DRS object {:?}", object_id.0);
+
+        let id = util::decode_path_param(&object_id.0);
+        let file = match File::resolve(&pool, file_id_of(&id)).await {
+            Ok(file) => file,
+            Err(e) => return GetObjectResponse::NotFound(drs_error(e.to_string(), 404)),
+        };
+
+        if let Err(failure) = check_access(&file, &auth.0) {
+            return GetObjectResponse::from_failure(failure, &auth.0);
+        }
+
+        GetObjectResponse::Ok(Json(
+            drs_object(&file, &config, &host_of(req), request_scheme_of(req)).await,
+        ))
+    }
+
+    /// Call `/ga4gh/drs/v1/objects/:object_id/access/:access_id` to sign the url of the file on a repo.
+    ///
+    /// A repo which only accepts a POST request is downloaded through `/api/v1/files/:id/download`.
+    #[oai(
+        path = "/objects/:object_id/access/:access_id",
+        method = "get",
+        tag = "DrsApiTags::Objects",
+        operation_id = "getAccessURL"
+    )]
+    async fn get_access_url(
+        &self,
+        req: &Request,
+        pool: Data<&Arc<sqlx::PgPool>>,
+        config: Data<&Arc<RepoConfig>>,
+        object_id: Path<String>,
+        access_id: Path<String>,
+        auth: CustomSecurityScheme,
+    ) -> GetAccessUrlResponse {
+        info!("Get the access url of the DRS object {:?} on {}", object_id.0, access_id.0);
+
+        let id = util::decode_path_param(&object_id.0);
+        let file = match File::resolve(&pool, file_id_of(&id)).await {
+            Ok(file) => file,
+            Err(e) => return GetAccessUrlResponse::NotFound(drs_error(e.to_string(), 404)),
+        };
+
+        let sign = match sign_file_data(
+            &file,
+            &config,
+            Some(&access_id.0),
+            None,
+            None,
+            &auth.0,
+        )
+        .await
+        {
+            Ok(sign) => sign.sign,
+            Err(failure) => return GetAccessUrlResponse::from_failure(failure, &auth.0),
+        };
+
+        let access_url = if sign.method.eq_ignore_ascii_case("GET") && sign.form.is_empty() {
+            AccessUrl {
+                url: sign.url(),
+                headers: sign
+                    .headers
+                    .iter()
+                    .map(|header| format!("{}: {}", header.name, header.value))
+                    .collect(),
+            }
+        } else {
+            AccessUrl {
+                url: format!(
+                    "{}/api/v1/files/{}/download?which_repo={}&proxy=true",
+                    base_url_of(req),
+                    drs_id(&file.guid),
+                    access_id.0
+                ),
+                // The download endpoint checks the token of the user again
+                headers: match req.headers().get(AUTHORIZATION).and_then(|v| v.to_str().ok()) {
+                    Some(token) => vec![format!("Authorization: {}", token)],
+                    None => vec![],
+                },
+            }
+        };
+
+        GetAccessUrlResponse::Ok(Json(access_url))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_id_of() {
+        let uuid = "3ec4d151-061b-4bcb-ad3a-425c712bfc88";
+        assert_eq!(file_id_of(uuid), uuid);
+        assert_eq!(file_id_of(&format!("drs://indexd.example.com/{}", uuid)), uuid);
+        assert_eq!(
+            file_id_of(&format!("drs://localhost:3000/biominer.fudan-pgx/{}", uuid)),
+            format!("biominer.fudan-pgx/{}", uuid)
+        );
+        assert_eq!(drs_id(&format!("biominer.fudan-pgx/{}", uuid)), uuid);
+    }
+
+    #[test]
+    fn test_from_failure() {
+        let user: User = serde_json::from_value(serde_json::json!({
+            "username": "test", "email": "test@example.com", "roles": [], "resources": []
+        }))
+        .unwrap();
+        let failure = || SignFailure::Unauthorized("private".to_string());
+
+        assert!(matches!(
+            GetObjectResponse::from_failure(failure(), &User::anonymous()),
+            GetObjectResponse::Unauthorized(_)
+        ));
+        assert!(matches!(
+            GetObjectResponse::from_failure(failure(), &user),
+            GetObjectResponse::Forbidden(_)
+        ));
+        assert!(matches!(
+            GetAccessUrlResponse::from_failure(failure(), &user),
+            GetAccessUrlResponse::Forbidden(_)
+        ));
+        assert!(matches!(
+            GetAccessUrlResponse::from_failure(SignFailure::Gone("deleted".to_string()), &user),
+            GetAccessUrlResponse::NotFound(_)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_drs_object() {
+        let root = tempfile::tempdir().unwrap();
+        let config = RepoConfig::read_config_data(&format!(
+            r#"{{
+                "oss": [{{
+                    "access_key": "ossadmin",
+                    "access_secret": "ossadmin",
+                    "endpoint": "https://oss-cn-hangzhou.aliyuncs.com",
+                    "bucket": "test"
+                }}],
+                "http": [{{
+                    "base_url": "https://example.com",
+                    "account_name": "test",
+                    "password": "test",
+                    "project_id": "test"
+                }}],
+                "local": [{{
+                    "root": "{}",
+                    "secret": "please-change-the-secret",
+                    "download_url": "http://localhost:3000/api/v1/download/local"
+                }}]
+            }}"#,
+            root.path().display()
+        ))
+        .unwrap();
+
+        let mut file = File::new("a.txt", 12, "test", "fudan-pgx-000001");
+        file.created_at = 0;
+        file.hashes = Some(serde_json::json!([
+            {"id": 1, "hash_type": "md5", "hash": "d41d8cd98f00b204e9800998ecf8427e", "file": null},
+            {"id": 2, "hash_type": "sha256", "hash": "e3b0c442", "file": null}
+        ]));
+        file.aliases = Some(serde_json::json!([{"id": 1, "name": "a", "file": null}]));
+        let url = |id: i64, url: &str, status: &str| {
+            serde_json::json!({
+                "id": id, "url": url, "created_at": 0, "status": status, "uploader": "test",
+                "file": null, "reason": null, "checked_at": null
+            })
+        };
+        file.urls = Some(serde_json::json!([
+            url(1, "oss://test/a.txt", "validated"),
+            url(2, "oss://test/b.txt", "validated"),
+            url(3, "s3://test/a.txt", "validated"),
+            url(4, &format!("file://{}/a.txt", root.path().display()), "validated"),
+            url(5, "https://example.com/a.txt", "failed"),
+        ]));
+
+        let object = drs_object(&file, &config, "localhost:3000", "https").await;
+        assert_eq!(object.self_uri, format!("drs://localhost:3000/{}", object.id));
+        assert_eq!(object.created_time, "1970-01-01T00:00:00Z");
+        assert_eq!(object.checksums[1].checksum_type, "sha-256");
+        assert_eq!(object.aliases, vec!["a".to_string()]);
+
+        // One access method for each repo, the s3 repo has no credentials and the http url is failed
+        assert_eq!(object.access_methods.len(), 2);
+        assert_eq!(object.access_methods[0].access_id.as_deref(), Some("oss"));
+        assert_eq!(object.access_methods[0].access_type, "https");
+        assert_eq!(object.access_methods[0].region.as_deref(), Some("cn-hangzhou"));
+        // The local repo is downloaded from its http download url
+        assert_eq!(object.access_methods[1].access_id.as_deref(), Some("file"));
+        assert_eq!(object.access_methods[1].access_type, "http");
+    }
+}
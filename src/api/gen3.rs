@@ -0,0 +1,1139 @@
+//! A facade of the uc-cdis indexd api, so gen3-client, gen3sdk and the scripts of a Gen3 commons can use indexd.
+//!
+//! A Gen3 record is a file: the did is the guid, the acl ["*"] means a public file, the metadata are the tags and
+//! the authz are the resource paths in the acl of the file. A did of another commons, such as dg.4503/<uuid>, keeps its uuid and is
+//! registered as an alias of the file, so the records of an old commons are found by their dids after migrating.
+use crate::api::auth::{CustomSecurityScheme, User};
+use crate::model::datafile::{
+    fetch_aliases, fetch_sorted_guid_page, load_files_by_guids, Config, File, FileError,
+    FileRegistration, FileStatus, FileUpdate, Hash, QueryFilter, Tag, URL,
+};
+use crate::repo_config::RepoConfig;
+use crate::util;
+use lazy_static::lazy_static;
+use log::info;
+use poem::web::Data;
+use poem::Request;
+use poem_openapi::{param::Path, param::Query, payload::Json, ApiResponse, Object, OpenApi, Tags};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+const PRIVATE_RECORD_MESSAGE: &str = "The record is private and you do not have permission to access.";
+const ANONYMOUS_WRITE_MESSAGE: &str = "Please sign in to change the records.";
+
+lazy_static! {
+    // /index/<prefix>/<uuid>, the did is not encoded by the gen3 clients
+    static ref DID_PATH: Regex = Regex::new(concat!(
+        r"^(?P<head>.*/index/[^/]+)/",
+        r"(?P<uuid>[0-9a-fA-F]{8}-[0-9a-fA-F]{4}-[0-9a-fA-F]{4}-[0-9a-fA-F]{4}-[0-9a-fA-F]{12})",
+        r"(?P<tail>/[^/]*)?$"
+    ))
+    .unwrap();
+}
+
+#[derive(Tags)]
+enum Gen3ApiTags {
+    Index,
+    Alias,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Object)]
+pub struct Gen3Error {
+    pub error: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, Object)]
+pub struct Gen3Record {
+    pub did: String,
+    pub baseid: String,
+    pub rev: String,
+    // Always object, the bundles are not supported
+    pub form: String,
+    pub size: i64,
+    pub file_name: Option<String>,
+    pub version: Option<String>,
+    pub uploader: Option<String>,
+    pub urls: Vec<String>,
+    pub urls_metadata: BTreeMap<String, BTreeMap<String, String>>,
+    // Such as {"md5": "..."}
+    pub hashes: BTreeMap<String, String>,
+    pub acl: Vec<String>,
+    pub authz: Vec<String>,
+    pub metadata: BTreeMap<String, String>,
+    pub created_date: String,
+    pub updated_date: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, Object)]
+pub struct Gen3RecordList {
+    pub records: Vec<Gen3Record>,
+    pub limit: u64,
+    pub start: Option<String>,
+    pub page: Option<u64>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, Object)]
+pub struct Gen3CreateRecord {
+    // A did of another commons, the uuid of it is kept
+    pub did: Option<String>,
+    // The record is a new version of the records with the baseid if they exist
+    pub baseid: Option<String>,
+    pub form: Option<String>,
+    pub size: u64,
+    pub file_name: Option<String>,
+    pub uploader: Option<String>,
+    pub urls: Option<Vec<String>>,
+    pub hashes: BTreeMap<String, String>,
+    pub acl: Option<Vec<String>>,
+    pub authz: Option<Vec<String>>,
+    pub metadata: Option<BTreeMap<String, String>>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, Object)]
+pub struct Gen3UpdateRecord {
+    pub file_name: Option<String>,
+    // All the urls of the record
+    pub urls: Option<Vec<String>>,
+    pub acl: Option<Vec<String>>,
+    pub authz: Option<Vec<String>>,
+    pub metadata: Option<BTreeMap<String, String>>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, Object)]
+pub struct Gen3RecordRev {
+    pub did: String,
+    pub baseid: String,
+    pub rev: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, Object)]
+pub struct Gen3AliasList {
+    pub aliases: Vec<String>,
+    pub limit: u64,
+    pub start: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, Object)]
+pub struct Gen3Alias {
+    pub name: String,
+    // The rev of the file
+    pub rev: String,
+    pub size: i64,
+    pub hashes: BTreeMap<String, String>,
+    // public or private
+    pub release: String,
+    pub metadata: BTreeMap<String, String>,
+    pub host_authorities: Vec<String>,
+    pub keeper_authority: Option<String>,
+}
+
+fn gen3_error(error: String) -> Json<Gen3Error> {
+    Json(Gen3Error { error })
+}
+
+#[derive(ApiResponse)]
+#[allow(clippy::large_enum_variant)]
+enum GetRecordResponse {
+    #[oai(status = 200)]
+    Ok(Json<Gen3Record>),
+
+    #[oai(status = 401)]
+    Unauthorized(Json<Gen3Error>),
+
+    #[oai(status = 404)]
+    NotFound(Json<Gen3Error>),
+
+    // The record has been deleted, only its tombstone is kept.
+    #[oai(status = 410)]
+    Gone(Json<Gen3Error>),
+}
+
+#[derive(ApiResponse)]
+enum GetRecordsResponse {
+    #[oai(status = 200)]
+    Ok(Json<Gen3RecordList>),
+
+    #[oai(status = 400)]
+    BadRequest(Json<Gen3Error>),
+
+    #[oai(status = 500)]
+    InternalError(Json<Gen3Error>),
+}
+
+#[derive(ApiResponse)]
+enum GetVersionsResponse {
+    #[oai(status = 200)]
+    Ok(Json<BTreeMap<String, Gen3Record>>),
+
+    #[oai(status = 404)]
+    NotFound(Json<Gen3Error>),
+
+    #[oai(status = 500)]
+    InternalError(Json<Gen3Error>),
+}
+
+#[derive(ApiResponse)]
+enum PostBulkDocumentsResponse {
+    #[oai(status = 200)]
+    Ok(Json<Vec<Gen3Record>>),
+
+    #[oai(status = 400)]
+    BadRequest(Json<Gen3Error>),
+}
+
+/// The response of the endpoints which change a record, the rev is the new rev of the file.
+#[derive(ApiResponse)]
+enum WriteRecordResponse {
+    #[oai(status = 200)]
+    Ok(Json<Gen3RecordRev>),
+
+    #[oai(status = 400)]
+    BadRequest(Json<Gen3Error>),
+
+    /// The caller is anonymous or cannot access the record.
+    #[oai(status = 401)]
+    Unauthorized(Json<Gen3Error>),
+
+    /// Only the administrators can change the acl and the authz.
+    #[oai(status = 403)]
+    Forbidden(Json<Gen3Error>),
+
+    #[oai(status = 404)]
+    NotFound(Json<Gen3Error>),
+
+    /// The rev is stale, or the hashes or the urls have been registered by other records.
+    #[oai(status = 409)]
+    Conflict(Json<Gen3Error>),
+
+    #[oai(status = 500)]
+    InternalError(Json<Gen3Error>),
+}
+
+impl WriteRecordResponse {
+    fn ok(file: &File, rev: String) -> Self {
+        WriteRecordResponse::Ok(Json(Gen3RecordRev {
+            did: file.guid.clone(),
+            baseid: file.baseid.clone(),
+            rev,
+        }))
+    }
+
+    fn from_error(e: anyhow::Error) -> Self {
+        match e.downcast_ref::<FileError>() {
+            Some(FileError::NotFound { .. })
+            | Some(FileError::Deleted { .. })
+            | Some(FileError::NoSuchItem { .. }) => {
+                WriteRecordResponse::NotFound(gen3_error(e.to_string()))
+            }
+            Some(FileError::RevMismatch { .. })
+            | Some(FileError::Registered { .. })
+            | Some(FileError::InvalidTransition { .. }) => {
+                WriteRecordResponse::Conflict(gen3_error(e.to_string()))
+            }
+            _ => WriteRecordResponse::InternalError(gen3_error(e.to_string())),
+        }
+    }
+
+    /// The anonymous callers cannot change the records, the others can only change the records which they can write, see `User::can_write`.
+    fn check_writable(user: &User, file: Option<&File>) -> Result<(), Self> {
+        if user.is_anonymous() {
+            return Err(WriteRecordResponse::Unauthorized(gen3_error(
+                ANONYMOUS_WRITE_MESSAGE.to_string(),
+            )));
+        }
+        match file {
+            Some(file) if !user.can_write(&file.uploader, file.acl.as_deref()) => Err(
+                WriteRecordResponse::Unauthorized(gen3_error(PRIVATE_RECORD_MESSAGE.to_string())),
+            ),
+            _ => Ok(()),
+        }
+    }
+
+    /// Only the administrators can change the acl or the authz of a record.
+    fn check_acl_change(user: &User, record: &Gen3UpdateRecord) -> Result<(), Self> {
+        if (record.acl.is_some() || record.authz.is_some()) && !user.is_admin() {
+            return Err(WriteRecordResponse::Forbidden(gen3_error(
+                "Only administrators can change the acl.".to_string(),
+            )));
+        }
+        Ok(())
+    }
+}
+
+#[derive(ApiResponse)]
+enum GetAliasesResponse {
+    #[oai(status = 200)]
+    Ok(Json<Gen3AliasList>),
+
+    #[oai(status = 500)]
+    InternalError(Json<Gen3Error>),
+}
+
+#[derive(ApiResponse)]
+enum GetAliasResponse {
+    #[oai(status = 200)]
+    Ok(Json<Gen3Alias>),
+
+    #[oai(status = 401)]
+    Unauthorized(Json<Gen3Error>),
+
+    #[oai(status = 404)]
+    NotFound(Json<Gen3Error>),
+
+    // The record has been deleted, only its tombstone is kept.
+    #[oai(status = 410)]
+    Gone(Json<Gen3Error>),
+}
+
+/// Encode the slash of the did in /index/<prefix>/<uuid>, so the did is one path param.
+pub fn encode_did_path(path: &str) -> Option<String> {
+    let captures = DID_PATH.captures(path)?;
+    Some(format!(
+        "{}%2F{}{}",
+        &captures["head"],
+        &captures["uuid"],
+        captures
+            .name("tail")
+            .map(|tail| tail.as_str())
+            .unwrap_or_default()
+    ))
+}
+
+/// A middleware which lets the gen3 clients request /index/<prefix>/<uuid> without encoding the did.
+pub async fn encode_did(mut req: Request) -> poem::Result<Request> {
+    if let Some(path) = encode_did_path(req.uri().path()) {
+        let uri = match req.uri().query() {
+            Some(query) => format!("{}?{}", path, query),
+            None => path,
+        };
+        if let Ok(uri) = uri.parse() {
+            *req.uri_mut() = uri;
+        }
+    }
+    Ok(req)
+}
+
+/// The hash types of Gen3 are md5, sha1, sha256, sha512, crc and etag.
+fn gen3_hash_type(hash_type: &str) -> &str {
+    match hash_type {
+        "crc32" => "crc",
+        _ => hash_type,
+    }
+}
+
+fn hash_type_of(gen3_hash_type: &str) -> &str {
+    match gen3_hash_type {
+        "crc" => "crc32",
+        _ => gen3_hash_type,
+    }
+}
+
+/// The gen3 acl and authz of the file, the acl of a public file is ["*"].
+fn gen3_acl(acl: Option<&str>) -> (Vec<String>, Vec<String>) {
+    match acl {
+        Some(acl) => util::split_acl(acl),
+        None => (vec!["*".to_string()], vec![]),
+    }
+}
+
+/// The acl of the gen3 acl and authz, the file is public if the acl has "*" and there is no authz.
+fn acl_of(gen3_acl: &[String], authz: &[String]) -> Result<Option<String>, String> {
+    if gen3_acl.iter().any(|group| group == "*") {
+        return util::join_acl(&[], authz);
+    }
+    util::join_acl(gen3_acl, authz)
+}
+
+fn check_urls(urls: &[String], repo_config: &RepoConfig) -> Result<(), String> {
+    urls.iter().try_for_each(|url| repo_config.check_url(url))
+}
+
+/// The tags of the metadata.
+fn tags_of(metadata: Option<&BTreeMap<String, String>>) -> Result<Vec<(String, String)>, String> {
+    let mut tags: Vec<(String, String)> = vec![];
+    for (field_name, field_value) in metadata.into_iter().flatten() {
+        if field_name.is_empty() {
+            return Err(format!("Invalid metadata key: {:?}.", field_name));
+        }
+        tags.push((field_name.clone(), field_value.clone()));
+    }
+    Ok(tags)
+}
+
+pub fn to_record(file: &File) -> Gen3Record {
+    let urls: Vec<URL> = match &file.urls {
+        Some(urls) => serde_json::from_value(urls.clone()).unwrap_or_default(),
+        None => vec![],
+    };
+    let hashes: Vec<Hash> = match &file.hashes {
+        Some(hashes) => serde_json::from_value(hashes.clone()).unwrap_or_default(),
+        None => vec![],
+    };
+    let tags: Vec<Tag> = match &file.tags {
+        Some(tags) => serde_json::from_value(tags.clone()).unwrap_or_default(),
+        None => vec![],
+    };
+
+    let (acl, authz) = gen3_acl(file.acl.as_deref());
+
+    Gen3Record {
+        did: file.guid.clone(),
+        baseid: file.baseid.clone(),
+        rev: file.rev.clone(),
+        form: "object".to_string(),
+        size: file.size,
+        file_name: Some(file.filename.clone()),
+        version: Some(file.version.to_string()),
+        uploader: Some(file.uploader.clone()),
+        urls: urls.iter().map(|url| url.url.clone()).collect(),
+        urls_metadata: urls
+            .iter()
+            .map(|url| (url.url.clone(), BTreeMap::new()))
+            .collect(),
+        hashes: hashes
+            .iter()
+            .map(|hash| {
+                (
+                    gen3_hash_type(&hash.hash_type).to_string(),
+                    hash.hash.clone(),
+                )
+            })
+            .collect(),
+        acl,
+        authz,
+        metadata: tags
+            .iter()
+            .map(|tag| (tag.field_name.clone(), tag.field_value.clone()))
+            .collect(),
+        created_date: util::to_rfc3339(file.created_at),
+        updated_date: util::to_rfc3339(file.updated_at),
+    }
+}
+
+impl Gen3CreateRecord {
+    /// The registration of the record, the md5 is the primary hash if the record has it.
+    pub fn to_registration(
+        &self,
+        registry_id: &str,
+        repo_config: &RepoConfig,
+    ) -> Result<FileRegistration, String> {
+        if self.form.as_deref().is_some_and(|form| form != "object") {
+            return Err("Only the records of the object form are supported.".to_string());
+        }
+
+        let mut hashes = vec![];
+        for (gen3_hash_type, hash) in self.hashes.iter() {
+            let hash_type = util::check_hash(hash, Some(hash_type_of(gen3_hash_type)))?;
+            hashes.push((hash_type.to_string(), hash.clone()));
+        }
+        // The md5 goes first
+        hashes.sort_by_key(|(hash_type, _)| hash_type != "md5");
+        if hashes.is_empty() {
+            return Err("At least one hash of the record is required.".to_string());
+        }
+
+        let urls = self.urls.clone().unwrap_or_default();
+        check_urls(&urls, repo_config)?;
+
+        let uploader = self.uploader.as_deref().unwrap_or("biominer-admin");
+        let filename = self.file_name.as_deref().unwrap_or_default();
+        let mut file = File::new(filename, self.size as i64, uploader, registry_id);
+        file.acl = acl_of(
+            self.acl.as_deref().unwrap_or_default(),
+            self.authz.as_deref().unwrap_or_default(),
+        )?;
+
+        let mut aliases = vec![];
+        if let Some(did) = &self.did {
+            // Keep the uuid of the did, so the record is found by it
+            if let Some(uuid) = did
+                .rsplit('/')
+                .next()
+                .and_then(|id| uuid::Uuid::parse_str(id).ok())
+            {
+                file.guid = format!("biominer.{}/{}", registry_id, uuid);
+            }
+            if did != &file.guid {
+                aliases.push(did.clone());
+            }
+        }
+
+        let (hash_type, hash) = hashes.remove(0);
+        let mut registration = FileRegistration::new(file, &hash);
+        registration.hash_type = hash_type;
+        registration.hashes = hashes;
+        registration.urls = urls;
+        registration.aliases = aliases;
+        registration.tags = tags_of(self.metadata.as_ref())?;
+        Ok(registration)
+    }
+}
+
+impl Gen3UpdateRecord {
+    /// The update of the file, the acl or the authz which is not given is kept.
+    pub fn to_update(&self, file: &File, repo_config: &RepoConfig) -> Result<FileUpdate, String> {
+        if let Some(urls) = &self.urls {
+            check_urls(urls, repo_config)?;
+        }
+
+        let acl = match (&self.acl, &self.authz) {
+            (None, None) => None,
+            (acl, authz) => {
+                let (current_acl, current_authz) = gen3_acl(file.acl.as_deref());
+                Some(acl_of(
+                    acl.as_ref().unwrap_or(&current_acl),
+                    authz.as_ref().unwrap_or(&current_authz),
+                )?)
+            }
+        };
+
+        Ok(FileUpdate {
+            filename: self.file_name.clone(),
+            acl,
+            urls: self.urls.clone(),
+            tags: tags_of(self.metadata.as_ref())?,
+        })
+    }
+}
+
+/// Find the record by its did, a did of another commons is found by its uuid or as an alias.
+async fn find_record(pool: &sqlx::PgPool, did: &str) -> Result<File, anyhow::Error> {
+    let did = util::decode_path_param(did);
+    match did
+        .rsplit('/')
+        .next()
+        .and_then(|id| uuid::Uuid::parse_str(id).ok())
+    {
+        Some(uuid) if did.len() == 36 || did.starts_with("biominer.") => {
+            File::get_file(pool, &uuid).await
+        }
+        Some(uuid) => match File::get_file_with_alias(pool, &did).await {
+            Ok(file) => Ok(file),
+            Err(_) => File::get_file(pool, &uuid).await,
+        },
+        None => File::get_file_with_alias(pool, &did).await,
+    }
+}
+
+/// Whether the record has been deleted, the deleted records are kept as tombstones and not served.
+fn is_deleted(file: &File) -> bool {
+    file.status == FileStatus::Deleted.as_str()
+}
+
+fn deleted_message(file: &File) -> String {
+    format!("The record {} has been deleted.", file.guid)
+}
+
+/// The uuid of the guid, the files are changed by their uuids.
+fn uuid_of(file: &File) -> Result<uuid::Uuid, anyhow::Error> {
+    let id = file.guid.rsplit('/').next().unwrap_or_default();
+    Ok(uuid::Uuid::parse_str(id)?)
+}
+
+/// The rev is required to change a record, the same as Gen3.
+fn required_rev(rev: Option<String>) -> Result<String, WriteRecordResponse> {
+    match rev {
+        Some(rev) if !rev.is_empty() => Ok(rev),
+        _ => Err(WriteRecordResponse::BadRequest(gen3_error(
+            "The rev of the record is required.".to_string(),
+        ))),
+    }
+}
+
+pub struct Gen3Api;
+
+#[OpenApi]
+impl Gen3Api {
+    /// Call `/index/` to list the records, the records are ordered by did.
+    ///
+    /// The page starts after the `start` did, or at the `page` (from 0) if `start` is not set.
+    #[oai(
+        path = "/index/",
+        method = "get",
+        tag = "Gen3ApiTags::Index",
+        operation_id = "listGen3Records"
+    )]
+    #[allow(clippy::too_many_arguments)]
+    async fn list_records(
+        &self,
+        pool: Data<&Arc<sqlx::PgPool>>,
+        /// The number of the records, 100 by default and 1024 at most.
+        limit: Query<Option<u64>>,
+        start: Query<Option<String>>,
+        page: Query<Option<u64>>,
+        /// Such as md5:d41d8cd98f00b204e9800998ecf8427e.
+        hash: Query<Option<String>>,
+        url: Query<Option<String>>,
+        /// A part of the file name.
+        file_name: Query<Option<String>>,
+        uploader: Query<Option<String>>,
+        auth: CustomSecurityScheme,
+    ) -> GetRecordsResponse {
+        let limit = limit.0.unwrap_or(100).clamp(1, 1024);
+        let hash = match hash.0.as_deref().map(|hash| hash.split_once(':')) {
+            Some(Some((_, hash))) => hash.to_string(),
+            Some(None) => {
+                return GetRecordsResponse::BadRequest(gen3_error(
+                    "The hash must be <hash_type>:<hash>.".to_string(),
+                ))
+            }
+            None => "".to_string(),
+        };
+
+        let url = url.0.unwrap_or_default();
+        let file_name = file_name.0.unwrap_or_default();
+        let uploader = uploader.0.unwrap_or_default();
+        let auth_groups = auth.0.auth_groups();
+        let filter = QueryFilter::new("", &file_name, "", "", &uploader, &hash, "", &url, "", "")
+            .with_guid_after(start.0.as_deref())
+            .with_auth_groups(auth_groups.as_deref());
+        let page_no = match start.0 {
+            Some(_) => 1,
+            None => page.0.unwrap_or(0) + 1,
+        };
+
+        let guids = match fetch_sorted_guid_page(&pool, &filter, page_no, limit).await {
+            Ok((guids, _)) => guids,
+            Err(e) => return GetRecordsResponse::InternalError(gen3_error(e.to_string())),
+        };
+        let mut files = match load_files_by_guids(&pool, &guids, true, true, true).await {
+            Ok(files) => files,
+            Err(e) => return GetRecordsResponse::InternalError(gen3_error(e.to_string())),
+        };
+        files.sort_by(|a, b| a.guid.cmp(&b.guid));
+
+        GetRecordsResponse::Ok(Json(Gen3RecordList {
+            records: files.iter().map(to_record).collect(),
+            limit,
+            start: start.0,
+            page: page.0,
+        }))
+    }
+
+    /// Call `/index/` to create a record, the did of another commons is kept as an alias.
+    #[oai(
+        path = "/index/",
+        method = "post",
+        tag = "Gen3ApiTags::Index",
+        operation_id = "createGen3Record"
+    )]
+    async fn create_record(
+        &self,
+        pool: Data<&Arc<sqlx::PgPool>>,
+        config: Data<&Arc<Config>>,
+        repo_config: Data<&Arc<RepoConfig>>,
+        record: Json<Gen3CreateRecord>,
+        auth: CustomSecurityScheme,
+    ) -> WriteRecordResponse {
+        info!("Creating the gen3 record: {:?}", record.0);
+        if let Err(response) = WriteRecordResponse::check_writable(&auth.0, None) {
+            return response;
+        }
+        let mut registration = match record.to_registration(&config.registry_id, &repo_config) {
+            Ok(registration) => registration,
+            Err(msg) => return WriteRecordResponse::BadRequest(gen3_error(msg)),
+        };
+        registration.file.uploader = auth.0.username.clone();
+
+        // A new version of the records with the baseid
+        if let Some(baseid) = &record.baseid {
+            match File::get_latest_version(&pool, baseid).await {
+                Ok(latest) => {
+                    if let Err(response) =
+                        WriteRecordResponse::check_writable(&auth.0, Some(&latest))
+                    {
+                        return response;
+                    }
+                    let result = match uuid_of(&latest) {
+                        Ok(id) => registration.add_version(&pool, &id).await,
+                        Err(e) => Err(e),
+                    };
+                    return match result {
+                        Ok(_) => WriteRecordResponse::ok(
+                            &registration.file,
+                            registration.file.rev.clone(),
+                        ),
+                        Err(e) => WriteRecordResponse::from_error(e),
+                    };
+                }
+                Err(_) => registration.file.baseid = baseid.clone(),
+            }
+        }
+
+        let writable = |uploader: &str, acl: Option<&str>| auth.0.can_write(uploader, acl);
+        match registration.add_with_policy(&pool, "error", &writable).await {
+            Ok(_) => WriteRecordResponse::ok(&registration.file, registration.file.rev.clone()),
+            Err(e) => WriteRecordResponse::from_error(e),
+        }
+    }
+
+    /// Call `/index/:guid` to get the record, the slash of the did needs no encoding.
+    #[oai(
+        path = "/index/:guid",
+        method = "get",
+        tag = "Gen3ApiTags::Index",
+        operation_id = "getGen3Record"
+    )]
+    async fn get_record(
+        &self,
+        pool: Data<&Arc<sqlx::PgPool>>,
+        guid: Path<String>,
+        auth: CustomSecurityScheme,
+    ) -> GetRecordResponse {
+        match find_record(&pool, &guid.0).await {
+            Ok(file) if is_deleted(&file) => GetRecordResponse::Gone(gen3_error(deleted_message(&file))),
+            Ok(file) if !auth.0.can_access(file.acl.as_deref()) => {
+                GetRecordResponse::Unauthorized(gen3_error(PRIVATE_RECORD_MESSAGE.to_string()))
+            }
+            Ok(file) => GetRecordResponse::Ok(Json(to_record(&file))),
+            Err(e) => GetRecordResponse::NotFound(gen3_error(e.to_string())),
+        }
+    }
+
+    /// Call `/index/:guid` to create a new version of the record.
+    #[oai(
+        path = "/index/:guid",
+        method = "post",
+        tag = "Gen3ApiTags::Index",
+        operation_id = "createGen3RecordVersion"
+    )]
+    async fn create_record_version(
+        &self,
+        pool: Data<&Arc<sqlx::PgPool>>,
+        config: Data<&Arc<Config>>,
+        repo_config: Data<&Arc<RepoConfig>>,
+        guid: Path<String>,
+        record: Json<Gen3CreateRecord>,
+        auth: CustomSecurityScheme,
+    ) -> WriteRecordResponse {
+        let file = match find_record(&pool, &guid.0).await {
+            Ok(file) => file,
+            Err(e) => return WriteRecordResponse::NotFound(gen3_error(e.to_string())),
+        };
+        if let Err(response) = WriteRecordResponse::check_writable(&auth.0, Some(&file)) {
+            return response;
+        }
+
+        let mut registration = match record.to_registration(&config.registry_id, &repo_config) {
+            Ok(registration) => registration,
+            Err(msg) => return WriteRecordResponse::BadRequest(gen3_error(msg)),
+        };
+        registration.file.uploader = auth.0.username.clone();
+
+        let result = match uuid_of(&file) {
+            Ok(id) => registration.add_version(&pool, &id).await,
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(_) => WriteRecordResponse::ok(&registration.file, registration.file.rev.clone()),
+            Err(e) => WriteRecordResponse::from_error(e),
+        }
+    }
+
+    /// Call `/index/:guid` to update the record, the `rev` must be the current rev of the record.
+    /// Only the administrators can change the acl and the authz, the same as `/api/v1/files/:id/acl`.
+    #[oai(
+        path = "/index/:guid",
+        method = "put",
+        tag = "Gen3ApiTags::Index",
+        operation_id = "updateGen3Record"
+    )]
+    async fn update_record(
+        &self,
+        pool: Data<&Arc<sqlx::PgPool>>,
+        repo_config: Data<&Arc<RepoConfig>>,
+        guid: Path<String>,
+        rev: Query<Option<String>>,
+        record: Json<Gen3UpdateRecord>,
+        auth: CustomSecurityScheme,
+    ) -> WriteRecordResponse {
+        let rev = match required_rev(rev.0) {
+            Ok(rev) => rev,
+            Err(response) => return response,
+        };
+        let file = match find_record(&pool, &guid.0).await {
+            Ok(file) => file,
+            Err(e) => return WriteRecordResponse::NotFound(gen3_error(e.to_string())),
+        };
+        if let Err(response) = WriteRecordResponse::check_writable(&auth.0, Some(&file)) {
+            return response;
+        }
+        if let Err(response) = WriteRecordResponse::check_acl_change(&auth.0, &record) {
+            return response;
+        }
+
+        let update = match record.to_update(&file, &repo_config) {
+            Ok(update) => update,
+            Err(msg) => return WriteRecordResponse::BadRequest(gen3_error(msg)),
+        };
+
+        let result = match uuid_of(&file) {
+            Ok(id) => File::update(&pool, &id, &update, &auth.0.username, Some(&rev)).await,
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(new_rev) => WriteRecordResponse::ok(&file, new_rev),
+            Err(e) => WriteRecordResponse::from_error(e),
+        }
+    }
+
+    /// Call `/index/:guid` to delete the record, the `rev` must be the current rev of the record.
+    #[oai(
+        path = "/index/:guid",
+        method = "delete",
+        tag = "Gen3ApiTags::Index",
+        operation_id = "deleteGen3Record"
+    )]
+    async fn delete_record(
+        &self,
+        pool: Data<&Arc<sqlx::PgPool>>,
+        guid: Path<String>,
+        rev: Query<Option<String>>,
+        auth: CustomSecurityScheme,
+    ) -> WriteRecordResponse {
+        let rev = match required_rev(rev.0) {
+            Ok(rev) => rev,
+            Err(response) => return response,
+        };
+
+        let file = match find_record(&pool, &guid.0).await {
+            Ok(file) => file,
+            Err(e) => return WriteRecordResponse::NotFound(gen3_error(e.to_string())),
+        };
+        if let Err(response) = WriteRecordResponse::check_writable(&auth.0, Some(&file)) {
+            return response;
+        }
+
+        let reason = Some("Deleted by the gen3 api");
+        let result = match uuid_of(&file) {
+            Ok(id) => {
+                File::update_status(
+                    &pool,
+                    &id,
+                    FileStatus::Deleted,
+                    &auth.0.username,
+                    reason,
+                    Some(&rev),
+                )
+                .await
+            }
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(new_rev) => WriteRecordResponse::ok(&file, new_rev),
+            Err(e) => WriteRecordResponse::from_error(e),
+        }
+    }
+
+    /// Call `/index/:guid/versions` to get all the versions of the record, keyed by their order from 0.
+    #[oai(
+        path = "/index/:guid/versions",
+        method = "get",
+        tag = "Gen3ApiTags::Index",
+        operation_id = "getGen3RecordVersions"
+    )]
+    async fn get_record_versions(
+        &self,
+        pool: Data<&Arc<sqlx::PgPool>>,
+        guid: Path<String>,
+        auth: CustomSecurityScheme,
+    ) -> GetVersionsResponse {
+        let file = match find_record(&pool, &guid.0).await {
+            Ok(file) => file,
+            Err(e) => return GetVersionsResponse::NotFound(gen3_error(e.to_string())),
+        };
+
+        match File::get_versions(&pool, &file.baseid).await {
+            Ok(files) => GetVersionsResponse::Ok(Json(
+                files
+                    .iter()
+                    .filter(|file| auth.0.can_access(file.acl.as_deref()))
+                    .enumerate()
+                    .map(|(idx, file)| (idx.to_string(), to_record(file)))
+                    .collect(),
+            )),
+            Err(e) => GetVersionsResponse::InternalError(gen3_error(e.to_string())),
+        }
+    }
+
+    /// Call `/bulk/documents` to get the records of the dids, the dids which are not found, deleted or private are skipped.
+    #[oai(
+        path = "/bulk/documents",
+        method = "post",
+        tag = "Gen3ApiTags::Index",
+        operation_id = "getGen3Documents"
+    )]
+    async fn get_documents(
+        &self,
+        pool: Data<&Arc<sqlx::PgPool>>,
+        dids: Json<Vec<String>>,
+        auth: CustomSecurityScheme,
+    ) -> PostBulkDocumentsResponse {
+        if dids.len() > 1024 {
+            return PostBulkDocumentsResponse::BadRequest(gen3_error(
+                "At most 1024 dids are allowed in a request.".to_string(),
+            ));
+        }
+
+        let mut records = vec![];
+        for did in dids.iter() {
+            if let Ok(file) = find_record(&pool, did).await {
+                if !is_deleted(&file) && auth.0.can_access(file.acl.as_deref()) {
+                    records.push(to_record(&file));
+                }
+            }
+        }
+        PostBulkDocumentsResponse::Ok(Json(records))
+    }
+
+    /// Call `/alias/` to list the aliases, ordered by name. The aliases of the private files are only listed for the granted users.
+    #[oai(
+        path = "/alias/",
+        method = "get",
+        tag = "Gen3ApiTags::Alias",
+        operation_id = "listGen3Aliases"
+    )]
+    async fn list_aliases(
+        &self,
+        pool: Data<&Arc<sqlx::PgPool>>,
+        /// The number of the aliases, 100 by default and 1024 at most.
+        limit: Query<Option<u64>>,
+        start: Query<Option<String>>,
+        auth: CustomSecurityScheme,
+    ) -> GetAliasesResponse {
+        let limit = limit.0.unwrap_or(100).clamp(1, 1024);
+        let auth_groups = auth.0.auth_groups();
+        match fetch_aliases(&pool, start.0.as_deref(), limit, auth_groups.as_deref()).await {
+            Ok(aliases) => GetAliasesResponse::Ok(Json(Gen3AliasList {
+                aliases,
+                limit,
+                start: start.0,
+            })),
+            Err(e) => GetAliasesResponse::InternalError(gen3_error(e.to_string())),
+        }
+    }
+
+    /// Call `/alias/:name` to get the alias record, it describes the file of the alias.
+    #[oai(
+        path = "/alias/:name",
+        method = "get",
+        tag = "Gen3ApiTags::Alias",
+        operation_id = "getGen3Alias"
+    )]
+    async fn get_alias(
+        &self,
+        pool: Data<&Arc<sqlx::PgPool>>,
+        name: Path<String>,
+        auth: CustomSecurityScheme,
+    ) -> GetAliasResponse {
+        let name = util::decode_path_param(&name.0);
+        let file = match File::get_file_with_alias(&pool, &name).await {
+            Ok(file) => file,
+            Err(e) => return GetAliasResponse::NotFound(gen3_error(e.to_string())),
+        };
+        if is_deleted(&file) {
+            return GetAliasResponse::Gone(gen3_error(deleted_message(&file)));
+        }
+        if !auth.0.can_access(file.acl.as_deref()) {
+            return GetAliasResponse::Unauthorized(gen3_error(PRIVATE_RECORD_MESSAGE.to_string()));
+        }
+
+        let record = to_record(&file);
+        GetAliasResponse::Ok(Json(Gen3Alias {
+            name,
+            rev: record.rev,
+            size: record.size,
+            hashes: record.hashes,
+            release: file.access.clone(),
+            metadata: record.metadata,
+            host_authorities: vec![],
+            keeper_authority: None,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_did_path() {
+        let uuid = "3ec4d151-061b-4bcb-ad3a-425c712bfc88";
+        assert_eq!(
+            encode_did_path(&format!("/index/dg.4503/{}", uuid)),
+            Some(format!("/index/dg.4503%2F{}", uuid))
+        );
+        assert_eq!(
+            encode_did_path(&format!("/base/index/biominer.fudan-pgx/{}/versions", uuid)),
+            Some(format!(
+                "/base/index/biominer.fudan-pgx%2F{}/versions",
+                uuid
+            ))
+        );
+        assert_eq!(encode_did_path(&format!("/index/{}", uuid)), None);
+        assert_eq!(encode_did_path(&format!("/index/{}/versions", uuid)), None);
+    }
+
+    #[test]
+    fn test_to_registration() {
+        let root = tempfile::tempdir().unwrap();
+        let repo_config = RepoConfig::read_config_data(&format!(
+            r#"{{"local": [{{"root": "{}", "secret": "secret", "download_url": "http://localhost:3000/api/v1/download/local"}}]}}"#,
+            root.path().display()
+        ))
+        .unwrap();
+        let uuid = "3ec4d151-061b-4bcb-ad3a-425c712bfc88";
+        let mut record = Gen3CreateRecord {
+            did: Some(format!("dg.4503/{}", uuid)),
+            size: 12,
+            file_name: Some("a.txt".to_string()),
+            urls: Some(vec!["s3://test/a.txt".to_string()]),
+            hashes: [
+                ("crc".to_string(), "a9421b04".to_string()),
+                (
+                    "md5".to_string(),
+                    "d41d8cd98f00b204e9800998ecf8427e".to_string(),
+                ),
+            ]
+            .into_iter()
+            .collect(),
+            acl: Some(vec!["*".to_string()]),
+            authz: Some(vec!["/programs/test".to_string()]),
+            ..Default::default()
+        };
+
+        let registration = record.to_registration("fudan-pgx-000001", &repo_config).unwrap();
+        assert_eq!(
+            registration.file.guid,
+            format!("biominer.fudan-pgx-000001/{}", uuid)
+        );
+        assert_eq!(registration.aliases, vec![format!("dg.4503/{}", uuid)]);
+        assert_eq!(registration.hash_type, "md5");
+        assert_eq!(
+            registration.hashes,
+            vec![("crc32".to_string(), "a9421b04".to_string())]
+        );
+        assert_eq!(registration.file.acl.as_deref(), Some("/programs/test"));
+        assert!(registration.tags.is_empty());
+
+        record.acl = Some(vec!["admin".to_string(), "fudan-pgx".to_string()]);
+        let registration = record.to_registration("fudan-pgx-000001", &repo_config).unwrap();
+        assert_eq!(
+            registration.file.acl.as_deref(),
+            Some("admin,fudan-pgx,/programs/test")
+        );
+
+        record.acl = Some(vec!["*".to_string()]);
+        record.authz = None;
+        let registration = record.to_registration("fudan-pgx-000001", &repo_config).unwrap();
+        assert_eq!(registration.file.acl, None);
+
+        record.urls = Some(vec!["ftp://example.com/a.txt".to_string()]);
+        assert!(record.to_registration("fudan-pgx-000001", &repo_config).is_err());
+        // Only the local files under the root can be registered
+        record.urls = Some(vec![format!("file://{}/a.txt", root.path().display())]);
+        assert!(record.to_registration("fudan-pgx-000001", &repo_config).is_ok());
+        record.urls = Some(vec!["file:///etc/passwd".to_string()]);
+        assert!(record.to_registration("fudan-pgx-000001", &repo_config).is_err());
+        record.urls = None;
+        record.hashes = BTreeMap::new();
+        assert!(record.to_registration("fudan-pgx-000001", &repo_config).is_err());
+    }
+
+    #[test]
+    fn test_to_record() {
+        let mut file = File::new("a.txt", 12, "test", "fudan-pgx-000001");
+        file.created_at = 0;
+        file.acl = Some("admin,fudan-pgx,/programs/a,/programs/b".to_string());
+        file.hashes = Some(serde_json::json!([
+            {"id": 1, "hash_type": "md5", "hash": "d41d8cd98f00b204e9800998ecf8427e", "file": null},
+            {"id": 2, "hash_type": "crc32", "hash": "a9421b04", "file": null}
+        ]));
+        file.tags = Some(serde_json::json!([
+            {"id": 1, "field_name": "project", "field_value": "test", "file": null}
+        ]));
+
+        let record = to_record(&file);
+        assert_eq!(record.did, file.guid);
+        assert_eq!(record.hashes["crc"], "a9421b04");
+        assert_eq!(record.acl, vec!["admin", "fudan-pgx"]);
+        assert_eq!(record.authz, vec!["/programs/a", "/programs/b"]);
+        assert_eq!(record.metadata.len(), 1);
+        assert_eq!(record.metadata["project"], "test");
+        assert_eq!(record.created_date, "1970-01-01T00:00:00Z");
+        assert!(record.urls.is_empty());
+
+        // The authz which is not given is kept
+        let update = Gen3UpdateRecord {
+            acl: Some(vec!["*".to_string()]),
+            ..Default::default()
+        };
+        let repo_config = RepoConfig::read_config_data("{}").unwrap();
+        let update = update.to_update(&file, &repo_config).unwrap();
+        assert_eq!(update.acl, Some(Some("/programs/a,/programs/b".to_string())));
+        assert_eq!(to_record(&File::new("b.txt", 1, "test", "fudan-pgx-000001")).acl, vec!["*"]);
+    }
+
+    #[test]
+    fn test_check_writable() {
+        let user: User = serde_json::from_value(serde_json::json!({
+            "username": "test", "email": "test@example.com", "roles": [], "resources": ["/programs/a"]
+        }))
+        .unwrap();
+        let mut file = File::new("a.txt", 12, "other", "fudan-pgx-000001");
+        assert!(WriteRecordResponse::check_writable(&user, None).is_ok());
+        assert!(matches!(
+            WriteRecordResponse::check_writable(&user, Some(&file)),
+            Err(WriteRecordResponse::Unauthorized(_))
+        ));
+
+        // A read grant does not allow changes, only the uploader or a write grant does
+        file.acl = Some("/programs/a".to_string());
+        assert!(matches!(
+            WriteRecordResponse::check_writable(&user, Some(&file)),
+            Err(WriteRecordResponse::Unauthorized(_))
+        ));
+        file.uploader = "test".to_string();
+        assert!(WriteRecordResponse::check_writable(&user, Some(&file)).is_ok());
+
+        let writer: User = serde_json::from_value(serde_json::json!({
+            "username": "writer", "email": "writer@example.com", "roles": [], "resources": [],
+            "write_grants": ["/programs/a"]
+        }))
+        .unwrap();
+        assert!(WriteRecordResponse::check_writable(&writer, Some(&file)).is_ok());
+        file.acl = Some("/programs/b".to_string());
+        assert!(matches!(
+            WriteRecordResponse::check_writable(&writer, Some(&file)),
+            Err(WriteRecordResponse::Unauthorized(_))
+        ));
+
+        // The anonymous callers cannot create a record either
+        assert!(matches!(
+            WriteRecordResponse::check_writable(&User::anonymous(), None),
+            Err(WriteRecordResponse::Unauthorized(_))
+        ));
+
+        // Only the administrators can change the acl
+        let update = Gen3UpdateRecord {
+            file_name: Some("b.txt".to_string()),
+            ..Default::default()
+        };
+        assert!(WriteRecordResponse::check_acl_change(&user, &update).is_ok());
+        let update = Gen3UpdateRecord {
+            authz: Some(vec!["/programs/b".to_string()]),
+            ..Default::default()
+        };
+        assert!(matches!(
+            WriteRecordResponse::check_acl_change(&user, &update),
+            Err(WriteRecordResponse::Forbidden(_))
+        ));
+        let admin: User = serde_json::from_value(serde_json::json!({
+            "username": "admin", "email": "admin@example.com", "roles": ["Administrator"]
+        }))
+        .unwrap();
+        assert!(WriteRecordResponse::check_acl_change(&admin, &update).is_ok());
+    }
+}
@@ -1,2 +1,4 @@
 pub mod route;
 pub mod auth;
+pub mod drs;
+pub mod gen3;
@@ -1,26 +1,33 @@
+use crate::api::auth::{CustomSecurityScheme, User};
 use crate::model::data_dictionary::DataDictionary;
 use crate::model::datafile::{
-    Config, File, FileStatResponse, FileTagsResponse, Hash, QueryFilter, RecordResponse, URL,
+    update_acl, Config, File, FileError, FileRegistration, FileStatResponse, FileStatus,
+    FileTagsResponse, FileUpdate, Hash, QueryFilter, RecordResponse, StatusHistory,
+    ON_CONFLICT_POLICIES, URL,
 };
 use crate::model::data_table::DataFileTable;
 use crate::model::dataset::{DatasetDataResponse, Datasets, DatasetsResponse};
+use crate::model::upload::{self, PendingUpload, Upload, UploadError};
 use crate::model::util::to_hashmap;
 use crate::query_builder::query_plan::QueryPlan;
 use crate::query_builder::where_builder::ComposeQuery;
-use crate::repo_config::{RepoConfig, SignData};
+use crate::repo_config::oss::content_disposition;
+use crate::repo_config::{RepoConfig, SelectionError, SignData};
 use crate::util;
 use log::{debug, info, warn};
 use poem::web::Data;
+use poem::Body;
 use poem_openapi::{
     param::Header,
     param::Path,
     param::Query,
-    payload::{Json, PlainText},
-    ApiResponse, Object, OpenApi, Tags,
+    payload::{Binary, Html, Json, PlainText},
+    ApiRequest, ApiResponse, Object, OpenApi, Tags,
 };
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
 
 #[derive(Tags)]
 enum FileApiTags {
@@ -150,9 +157,15 @@ enum GetFileResponse {
     #[oai(status = 200)]
     Ok(Json<File>),
 
+    #[oai(status = 401)]
+    Unauthorized(PlainText<String>),
+
     #[oai(status = 404)]
     NotFound(PlainText<String>),
 
+    #[oai(status = 410)]
+    Gone(PlainText<String>),
+
     #[oai(status = 500)]
     InternalError(PlainText<String>),
 }
@@ -171,156 +184,993 @@ enum PostSignResponse {
     #[oai(status = 401)]
     Unauthorized(PlainText<String>),
 
+    #[oai(status = 410)]
+    Gone(PlainText<String>),
+
     #[oai(status = 500)]
     InternalError(PlainText<String>),
 }
 
 #[derive(ApiResponse)]
-enum GetTagsResponse {
+enum GetDownloadResponse {
     #[oai(status = 200)]
-    Ok(Json<FileTagsResponse>),
+    Ok(
+        Binary<Body>,
+        #[oai(header = "Content-Length")] u64,
+        #[oai(header = "Accept-Ranges")] String,
+    ),
+
+    #[oai(status = 206)]
+    PartialContent(
+        Binary<Body>,
+        #[oai(header = "Content-Length")] u64,
+        #[oai(header = "Content-Range")] String,
+    ),
+
+    #[oai(status = 403)]
+    Forbidden(PlainText<String>),
 
-    #[oai(status = 500)]
-    InternalError(PlainText<String>),
-}
+    #[oai(status = 404)]
+    NotFound(PlainText<String>),
 
-#[derive(ApiResponse)]
-enum GetStatResponse {
-    #[oai(status = 200)]
-    Ok(Json<FileStatResponse>),
+    #[oai(status = 416)]
+    RangeNotSatisfiable(PlainText<String>, #[oai(header = "Content-Range")] String),
 
     #[oai(status = 500)]
     InternalError(PlainText<String>),
 }
 
 #[derive(ApiResponse)]
-enum PutResponse {
+enum PostUploadResponse {
     #[oai(status = 201)]
-    Ok(Json<MessageResponse>),
+    Ok(Json<UploadResponse>),
 
     #[oai(status = 400)]
     BadRequest(PlainText<String>),
-}
 
-#[derive(ApiResponse)]
-enum PostResponse {
-    #[oai(status = 201)]
-    Ok(Json<GuidResponse>),
+    #[oai(status = 401)]
+    Unauthorized(PlainText<String>),
 
-    #[oai(status = 400)]
-    BadRequest(PlainText<String>),
-}
+    #[oai(status = 403)]
+    Forbidden(PlainText<String>),
 
-#[derive(ApiResponse)]
-enum GetDatasetsResponse {
-    #[oai(status = 200)]
-    Ok(Json<DatasetsResponse>),
+    #[oai(status = 404)]
+    NotFound(PlainText<String>),
+
+    #[oai(status = 410)]
+    Gone(PlainText<String>),
 
     #[oai(status = 500)]
     InternalError(PlainText<String>),
-
-    #[oai(status = 400)]
-    BadRequest(PlainText<String>),
-
-    #[oai(status = 404)]
-    NotFound(PlainText<String>),
 }
 
 #[derive(ApiResponse)]
-enum GetDatasetMetadataDictionaryResponse {
+enum GetFileDownloadResponse {
+    #[oai(status = 302)]
+    Found(#[oai(header = "Location")] String),
+
+    /// A page which submits the signed form, the repos like node only accept a POST request.
     #[oai(status = 200)]
-    Ok(Json<DataDictionary>),
+    Form(Html<String>),
+
+    #[oai(status = 200)]
+    Proxied(
+        Binary<Body>,
+        #[oai(header = "Content-Type")] String,
+        #[oai(header = "Content-Disposition")] String,
+    ),
+
+    #[oai(status = 401)]
+    Unauthorized(PlainText<String>),
 
     #[oai(status = 404)]
     NotFound(PlainText<String>),
 
+    #[oai(status = 410)]
+    Gone(PlainText<String>),
+
     #[oai(status = 500)]
     InternalError(PlainText<String>),
 
-    #[oai(status = 400)]
-    BadRequest(PlainText<String>),
+    #[oai(status = 502)]
+    BadGateway(PlainText<String>),
+}
+
+/// Why a file cannot be signed, shared by the single and the bulk signing endpoints.
+pub(crate) enum SignFailure {
+    NotFound(String),
+    Unauthorized(String),
+    Gone(String),
+    // The file has no url on the requested repo.
+    NotOnRepo(String),
+    // The file has a url on the requested repo, but the repo is not configured.
+    NotReleased(String),
+    Internal(String),
+}
+
+impl SignFailure {
+    fn code(&self) -> &'static str {
+        match self {
+            SignFailure::NotFound(_) => "not_found",
+            SignFailure::Unauthorized(_) => "unauthorized",
+            SignFailure::Gone(_) => "gone",
+            SignFailure::NotOnRepo(_) | SignFailure::NotReleased(_) => "not_released",
+            SignFailure::Internal(_) => "failed",
+        }
+    }
+
+    fn msg(self) -> String {
+        match self {
+            SignFailure::NotFound(msg)
+            | SignFailure::Unauthorized(msg)
+            | SignFailure::Gone(msg)
+            | SignFailure::NotOnRepo(msg)
+            | SignFailure::NotReleased(msg)
+            | SignFailure::Internal(msg) => msg,
+        }
+    }
+}
+
+impl From<SignFailure> for PostSignResponse {
+    fn from(failure: SignFailure) -> Self {
+        match failure {
+            SignFailure::NotFound(msg) | SignFailure::NotOnRepo(msg) => {
+                PostSignResponse::NotFound(PlainText(msg))
+            }
+            SignFailure::Unauthorized(msg) => PostSignResponse::Unauthorized(PlainText(msg)),
+            SignFailure::Gone(msg) => PostSignResponse::Gone(PlainText(msg)),
+            SignFailure::NotReleased(msg) | SignFailure::Internal(msg) => {
+                PostSignResponse::InternalError(PlainText(msg))
+            }
+        }
+    }
+}
+
+/// The acl of the groups and the resource paths, the resource paths can also be given in the acl. None means a public file.
+fn acl_of(acl: Option<&str>, authz: Option<&[String]>) -> Result<Option<String>, String> {
+    let (groups, mut paths) = match acl {
+        Some(acl) if acl.split(',').any(|group| group.trim().is_empty()) => {
+            return Err(format!("Invalid acl: {:?}.", acl));
+        }
+        Some(acl) => util::split_acl(acl),
+        None => (vec![], vec![]),
+    };
+    paths.extend(authz.unwrap_or_default().iter().cloned());
+    util::join_acl(&groups, &paths)
+}
+
+const PRIVATE_FILE_MESSAGE: &str = "The data is private and you do not have permission to access.";
+
+/// Whether the file can be accessed, the groups of the user are checked against the acl of a private file.
+pub(crate) fn check_access(file: &File, user: &User) -> Result<(), SignFailure> {
+    if file.status == "deleted" {
+        return Err(SignFailure::Gone(format!(
+            "The file {} has been deleted.",
+            file.guid
+        )));
+    }
+
+    if file.access == "private" && !user.can_access(file.acl.as_deref()) {
+        return Err(SignFailure::Unauthorized(PRIVATE_FILE_MESSAGE.to_string()));
+    }
+
+    Ok(())
+}
+
+const ANONYMOUS_WRITE_MESSAGE: &str = "Please sign in to change the files.";
+const DENIED_WRITE_MESSAGE: &str = "You do not have permission to change the file.";
+
+/// Why the user cannot change the file.
+enum WriteFailure {
+    Anonymous,
+    Denied,
+    NotFound(String),
+}
+
+/// The file which the user is going to change, the anonymous users cannot change any file and the others can
+/// only change the files which they can write, see `User::can_write`. A deleted file is returned, the model rejects the changes of it.
+async fn writable_file(
+    pool: &sqlx::PgPool,
+    id: &uuid::Uuid,
+    user: &User,
+) -> Result<File, WriteFailure> {
+    if user.is_anonymous() {
+        return Err(WriteFailure::Anonymous);
+    }
+
+    let file = File::get_file(pool, id)
+        .await
+        .map_err(|e| WriteFailure::NotFound(e.to_string()))?;
+    if !user.can_write(&file.uploader, file.acl.as_deref()) {
+        return Err(WriteFailure::Denied);
+    }
+
+    Ok(file)
+}
+
+/// Sign the url of the file on the repo, the file must be accessible, see `check_access`.
+/// The url is chosen by the selection policy of the config when the repo is not given.
+/// The hash of the preferred type is listed first, a missing one is reported in `missing_hash_type`.
+pub(crate) async fn sign_file_data(
+    file: &File,
+    config: &RepoConfig,
+    which_repo: Option<&str>,
+    region: Option<&str>,
+    hash_type: Option<&str>,
+    user: &User,
+) -> Result<SignResponse, SignFailure> {
+    check_access(file, user)?;
+
+    let mut hashes: Vec<Hash> = match &file.hashes {
+        Some(hashes) => serde_json::from_value(hashes.clone()).unwrap(),
+        None => {
+            return Err(SignFailure::Internal(
+                "The data has no hashes, please contact the administrator for more details."
+                    .to_string(),
+            ));
+        }
+    };
+
+    let missing_hash_type = match hash_type {
+        Some(hash_type) if hashes.iter().any(|h| h.hash_type == hash_type) => {
+            hashes.sort_by_key(|h| h.hash_type != hash_type);
+            None
+        }
+        Some(hash_type) => {
+            warn!("The file {} has no {} hash.", file.guid, hash_type);
+            Some(hash_type.to_string())
+        }
+        None => None,
+    };
+
+    let urls: Vec<URL> = match &file.urls {
+        Some(urls) => serde_json::from_value(urls.clone()).unwrap(),
+        None => vec![],
+    };
+
+    match config.select(&urls, which_repo, region) {
+        Ok(selection) => match selection.config.sign_as(&selection.url, &file.filename).await {
+            Ok(sign) => Ok(SignResponse {
+                sign,
+                size: file.size as u64,
+                hashes,
+                missing_hash_type,
+                filename: file.filename.clone(),
+                which_repo: selection.protocol,
+                reason: selection.reason,
+            }),
+            Err(e) => {
+                warn!("Cannot sign the url {} of the file {}: {}", selection.url, file.guid, e);
+                Err(SignFailure::Internal(format!(
+                    "Cannot sign the data on the {} repo, please contact the administrator for more details.",
+                    selection.protocol
+                )))
+            }
+        },
+        Err(e @ SelectionError::NotOnRepo { .. }) => Err(SignFailure::NotOnRepo(e.to_string())),
+        Err(e @ SelectionError::NotReleased { .. }) => {
+            warn!("Cannot sign the file {}: {}", file.guid, e);
+            Err(SignFailure::NotReleased(e.to_string()))
+        }
+    }
+}
+
+/// The preferred hash type must be one of the supported hash types.
+fn check_hash_type(hash_type: Option<&str>) -> Result<(), String> {
+    match hash_type {
+        Some(hash_type) if !util::HASH_TYPES.contains(&hash_type) => Err(format!(
+            "Invalid hash type: {}, only {} are supported.",
+            hash_type,
+            util::HASH_TYPES.join(", ")
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Resolve and sign every id in order, a failure is reported on its own item and never aborts the others.
+async fn sign_many(
+    pool: &sqlx::PgPool,
+    config: &RepoConfig,
+    params: &SignFiles,
+    user: &User,
+) -> Vec<BulkSignResult> {
+    let mut results = Vec::with_capacity(params.ids.len());
+    for id in params.ids.iter() {
+        let signed = match File::resolve(pool, id).await {
+            Ok(file) => sign_file_data(
+                &file,
+                config,
+                params.which_repo.as_deref(),
+                params.region.as_deref(),
+                params.hash_type.as_deref(),
+                user,
+            )
+                .await
+                .map(|sign| (file.guid.clone(), sign))
+                .map_err(|failure| (Some(file.guid), failure)),
+            Err(e) => Err((None, SignFailure::NotFound(e.to_string()))),
+        };
+
+        results.push(match signed {
+            Ok((guid, sign)) => BulkSignResult {
+                id: id.clone(),
+                guid: Some(guid),
+                sign: Some(sign),
+                error: None,
+            },
+            Err((guid, failure)) => BulkSignResult {
+                id: id.clone(),
+                guid,
+                sign: None,
+                error: Some(BulkSignError {
+                    code: failure.code().to_string(),
+                    msg: failure.msg(),
+                }),
+            },
+        });
+    }
+
+    results
+}
+
+impl From<SignFailure> for PostUploadResponse {
+    fn from(failure: SignFailure) -> Self {
+        match failure {
+            SignFailure::NotFound(msg) | SignFailure::NotOnRepo(msg) => {
+                PostUploadResponse::NotFound(PlainText(msg))
+            }
+            SignFailure::Unauthorized(msg) => PostUploadResponse::Forbidden(PlainText(msg)),
+            SignFailure::Gone(msg) => PostUploadResponse::Gone(PlainText(msg)),
+            SignFailure::NotReleased(msg) | SignFailure::Internal(msg) => {
+                PostUploadResponse::InternalError(PlainText(msg))
+            }
+        }
+    }
+}
+
+impl From<WriteFailure> for PostUploadResponse {
+    fn from(failure: WriteFailure) -> Self {
+        match failure {
+            WriteFailure::Anonymous => {
+                PostUploadResponse::Unauthorized(PlainText(ANONYMOUS_WRITE_MESSAGE.to_string()))
+            }
+            WriteFailure::Denied => {
+                PostUploadResponse::Forbidden(PlainText(DENIED_WRITE_MESSAGE.to_string()))
+            }
+            WriteFailure::NotFound(msg) => PostUploadResponse::NotFound(PlainText(msg)),
+        }
+    }
+}
+
+impl From<WriteFailure> for PutResponse {
+    fn from(failure: WriteFailure) -> Self {
+        match failure {
+            WriteFailure::Anonymous => {
+                PutResponse::Unauthorized(PlainText(ANONYMOUS_WRITE_MESSAGE.to_string()))
+            }
+            WriteFailure::Denied => {
+                PutResponse::Forbidden(PlainText(DENIED_WRITE_MESSAGE.to_string()))
+            }
+            WriteFailure::NotFound(msg) => PutResponse::NotFound(PlainText(msg)),
+        }
+    }
+}
+
+impl From<WriteFailure> for DeleteResponse {
+    fn from(failure: WriteFailure) -> Self {
+        match failure {
+            WriteFailure::Anonymous => {
+                DeleteResponse::Unauthorized(PlainText(ANONYMOUS_WRITE_MESSAGE.to_string()))
+            }
+            WriteFailure::Denied => {
+                DeleteResponse::Forbidden(PlainText(DENIED_WRITE_MESSAGE.to_string()))
+            }
+            WriteFailure::NotFound(msg) => DeleteResponse::NotFound(PlainText(msg)),
+        }
+    }
+}
+
+impl From<WriteFailure> for PostVersionResponse {
+    fn from(failure: WriteFailure) -> Self {
+        match failure {
+            WriteFailure::Anonymous => {
+                PostVersionResponse::Unauthorized(PlainText(ANONYMOUS_WRITE_MESSAGE.to_string()))
+            }
+            WriteFailure::Denied => {
+                PostVersionResponse::Forbidden(PlainText(DENIED_WRITE_MESSAGE.to_string()))
+            }
+            WriteFailure::NotFound(msg) => PostVersionResponse::NotFound(PlainText(msg)),
+        }
+    }
+}
+
+impl From<SignFailure> for GetFileDownloadResponse {
+    fn from(failure: SignFailure) -> Self {
+        match failure {
+            SignFailure::NotFound(msg) | SignFailure::NotOnRepo(msg) => {
+                GetFileDownloadResponse::NotFound(PlainText(msg))
+            }
+            SignFailure::Unauthorized(msg) => GetFileDownloadResponse::Unauthorized(PlainText(msg)),
+            SignFailure::Gone(msg) => GetFileDownloadResponse::Gone(PlainText(msg)),
+            SignFailure::NotReleased(msg) | SignFailure::Internal(msg) => {
+                GetFileDownloadResponse::InternalError(PlainText(msg))
+            }
+        }
+    }
+}
+
+/// How a signed request is handed to a client which only follows the links, such as `curl -L` or a genome browser.
+#[derive(Debug, PartialEq)]
+pub(crate) enum Delivery {
+    // A plain GET request, the client is redirected to the signed url
+    Redirect(String),
+    // A form POST, the browser submits it from a html page
+    Form(String),
+    // The request needs the headers which a client cannot be told to send, so indexd sends it
+    Proxy,
+}
+
+pub(crate) fn delivery_of(sign: &SignData, proxy: bool) -> Delivery {
+    let method = sign.method.to_uppercase();
+    let form_header = |name: &str, value: &str| {
+        name.eq_ignore_ascii_case("Content-Type") && value == "application/x-www-form-urlencoded"
+    };
+
+    if proxy {
+        Delivery::Proxy
+    } else if method == "GET" && sign.form.is_empty() && sign.headers.is_empty() {
+        Delivery::Redirect(sign.url())
+    } else if method == "POST" && sign.headers.iter().all(|h| form_header(&h.name, &h.value)) {
+        Delivery::Form(auto_submit_form(sign))
+    } else {
+        Delivery::Proxy
+    }
+}
+
+fn escape_html(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+/// A html page which submits the signed form as soon as it is loaded, the button is for the browsers without javascript.
+fn auto_submit_form(sign: &SignData) -> String {
+    let fields = sign
+        .form
+        .iter()
+        .map(|field| {
+            format!(
+                "<input type=\"hidden\" name=\"{}\" value=\"{}\">",
+                escape_html(&field.name),
+                escape_html(&field.value)
+            )
+        })
+        .collect::<Vec<String>>()
+        .join("\n");
+
+    format!(
+        "<!DOCTYPE html>\n<html>\n<body onload=\"document.forms[0].submit()\">\n\
+         <form method=\"post\" action=\"{}\">\n{}\n<button type=\"submit\">Download</button>\n\
+         </form>\n</body>\n</html>\n",
+        escape_html(&sign.url()),
+        fields
+    )
+}
+
+/// Send the signed request and stream the response back, the status of the repo is reported as a bad gateway.
+async fn proxy_download(sign: &SignResponse) -> GetFileDownloadResponse {
+    let client = reqwest::Client::new();
+    let response = match sign.sign.request(&client).send().await {
+        Ok(response) => response,
+        Err(e) => {
+            warn!("Cannot download the file {} from {}: {}", sign.filename, sign.which_repo, e);
+            return GetFileDownloadResponse::BadGateway(PlainText(format!(
+                "Cannot download the data from the {} repo.",
+                sign.which_repo
+            )));
+        }
+    };
+
+    if !response.status().is_success() {
+        return GetFileDownloadResponse::BadGateway(PlainText(format!(
+            "The {} repo responded with {} when downloading the data.",
+            sign.which_repo,
+            response.status()
+        )));
+    }
+
+    let content_type = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("application/octet-stream")
+        .to_string();
+    GetFileDownloadResponse::Proxied(
+        Binary(Body::from_bytes_stream(response.bytes_stream())),
+        content_type,
+        content_disposition(&sign.filename),
+    )
+}
+
+/// An aria2 input file, see the "Input File" section of `man aria2c`.
+///
+/// aria2 can only download with GET, so the files which need a POST request and the failed ones are left as comments.
+fn aria2_manifest(results: &[BulkSignResult]) -> String {
+    let mut lines = vec![];
+    for result in results {
+        let sign = match (&result.sign, &result.error) {
+            (Some(sign), _) => sign,
+            (None, Some(error)) => {
+                lines.push(format!("# {}: {} ({})", result.id, error.msg, error.code));
+                continue;
+            }
+            (None, None) => continue,
+        };
+
+        if sign.sign.method != "GET" || !sign.sign.form.is_empty() {
+            lines.push(format!(
+                "# {}: {} request is not supported by aria2, use the tsv manifest instead.",
+                result.id, sign.sign.method
+            ));
+            continue;
+        }
+
+        lines.push(sign.sign.url());
+        lines.push(format!("  out={}", sign.filename));
+        for header in sign.sign.headers.iter() {
+            lines.push(format!("  header={}: {}", header.name, header.value));
+        }
+
+        // aria2 verifies the download with the strongest hash it supports.
+        let checksum = ["sha512", "sha256", "sha1", "md5"].iter().find_map(|hash_type| {
+            sign.hashes
+                .iter()
+                .find(|hash| &hash.hash_type == hash_type)
+                .map(|hash| (hash_type.replace("sha", "sha-"), &hash.hash))
+        });
+        if let Some((hash_type, hash)) = checksum {
+            lines.push(format!("  checksum={}={}", hash_type, hash));
+        }
+    }
+
+    lines.push("".to_string());
+    lines.join("\n")
+}
+
+/// A tab separated manifest for biopoem, one file per row with the failed ones keeping their error.
+fn tsv_manifest(results: &[BulkSignResult]) -> String {
+    let clean = |value: &str| value.replace(['\t', '\n', '\r'], " ");
+    let mut lines = vec![[
+        "id", "guid", "filename", "size", "md5sum", "method", "url", "headers", "data", "expires_at",
+        "error",
+    ]
+    .join("\t")];
+
+    for result in results {
+        let guid = result.guid.clone().unwrap_or_default();
+        let row = match (&result.sign, &result.error) {
+            (Some(sign), _) => {
+                let md5sum = sign
+                    .hashes
+                    .iter()
+                    .find(|hash| hash.hash_type == "md5")
+                    .map(|hash| hash.hash.clone())
+                    .unwrap_or_default();
+                vec![
+                    result.id.clone(),
+                    guid,
+                    sign.filename.clone(),
+                    sign.size.to_string(),
+                    md5sum,
+                    sign.sign.method.clone(),
+                    sign.sign.url(),
+                    sign.sign
+                        .headers
+                        .iter()
+                        .map(|header| format!("{}: {}", header.name, header.value))
+                        .collect::<Vec<_>>()
+                        .join(";"),
+                    sign.sign.form_body(),
+                    sign.sign.expires_at.map(|t| t.to_string()).unwrap_or_default(),
+                    "".to_string(),
+                ]
+            }
+            (None, error) => {
+                let error = error
+                    .as_ref()
+                    .map(|error| format!("{}: {}", error.code, error.msg))
+                    .unwrap_or_default();
+                let mut row = vec![result.id.clone(), guid];
+                row.extend(vec!["".to_string(); 8]);
+                row.push(error);
+                row
+            }
+        };
+
+        lines.push(row.iter().map(|value| clean(value)).collect::<Vec<_>>().join("\t"));
+    }
+
+    lines.push("".to_string());
+    lines.join("\n")
 }
 
 #[derive(ApiResponse)]
-enum GetDatasetDataFileTablesResponse {
+enum PostBulkSignResponse {
     #[oai(status = 200)]
-    Ok(Json<Vec<DataFileTable>>),
+    Ok(Json<BulkSignResponse>),
 
-    #[oai(status = 404)]
-    NotFound(PlainText<String>),
+    #[oai(status = 400)]
+    BadRequest(PlainText<String>),
+}
 
-    #[oai(status = 500)]
-    InternalError(PlainText<String>),
+#[derive(ApiResponse)]
+enum PostSignManifestResponse {
+    #[oai(status = 200)]
+    Ok(PlainText<String>),
 
     #[oai(status = 400)]
     BadRequest(PlainText<String>),
 }
 
 #[derive(ApiResponse)]
-enum GetDatasetDataResponse {
+enum GetTagsResponse {
     #[oai(status = 200)]
-    Ok(Json<DatasetDataResponse>),
-
-    #[oai(status = 404)]
-    NotFound(PlainText<String>),
+    Ok(Json<FileTagsResponse>),
 
     #[oai(status = 500)]
     InternalError(PlainText<String>),
-
-    #[oai(status = 400)]
-    BadRequest(PlainText<String>),
 }
 
 #[derive(ApiResponse)]
-enum GetDatasetDatafilesResponse {
+enum GetStatResponse {
     #[oai(status = 200)]
-    Ok(Json<Vec<HashMap<String, serde_json::Value>>>),
-
-    #[oai(status = 404)]
-    NotFound(PlainText<String>),
+    Ok(Json<FileStatResponse>),
 
     #[oai(status = 500)]
     InternalError(PlainText<String>),
+}
+
+#[derive(ApiResponse)]
+enum PutResponse {
+    /// The ETag header is the new rev of the file.
+    #[oai(status = 201)]
+    Ok(Json<MessageResponse>, #[oai(header = "ETag")] String),
 
     #[oai(status = 400)]
     BadRequest(PlainText<String>),
-}
 
-#[derive(ApiResponse)]
-enum GetDatasetLicenseResponse {
-    #[oai(status = 200)]
-    Ok(Json<String>),
+    #[oai(status = 401)]
+    Unauthorized(PlainText<String>),
+
+    #[oai(status = 403)]
+    Forbidden(PlainText<String>),
 
     #[oai(status = 404)]
     NotFound(PlainText<String>),
 
+    #[oai(status = 409)]
+    Conflict(PlainText<String>),
+
+    #[oai(status = 410)]
+    Gone(PlainText<String>),
+
+    #[oai(status = 412)]
+    PreconditionFailed(PlainText<String>),
+}
+
+impl PutResponse {
+    fn ok(rev: String) -> Self {
+        PutResponse::Ok(
+            Json(MessageResponse {
+                msg: "Success".to_string(),
+            }),
+            format!("\"{}\"", rev),
+        )
+    }
+
+    fn from_error(e: anyhow::Error) -> Self {
+        match e.downcast_ref::<FileError>() {
+            Some(FileError::NotFound { .. }) => PutResponse::NotFound(PlainText(e.to_string())),
+            Some(FileError::Deleted { .. }) => PutResponse::Gone(PlainText(e.to_string())),
+            Some(FileError::RevMismatch { .. }) => {
+                PutResponse::PreconditionFailed(PlainText(e.to_string()))
+            }
+            Some(FileError::InvalidTransition { .. }) | Some(FileError::Registered { .. }) => {
+                PutResponse::Conflict(PlainText(e.to_string()))
+            }
+            _ if matches!(e.downcast_ref::<UploadError>(), Some(UploadError::Mismatch { .. })) => {
+                PutResponse::Conflict(PlainText(e.to_string()))
+            }
+            _ if matches!(e.downcast_ref::<UploadError>(), Some(UploadError::NotStarted { .. })) => {
+                PutResponse::NotFound(PlainText(e.to_string()))
+            }
+            _ => PutResponse::BadRequest(PlainText(e.to_string())),
+        }
+    }
+}
+
+#[derive(ApiResponse)]
+enum PutAclResponse {
+    #[oai(status = 200)]
+    Ok(Json<UpdateAclResponse>),
+
     #[oai(status = 400)]
     BadRequest(PlainText<String>),
 
+    #[oai(status = 403)]
+    Forbidden(PlainText<String>),
+
     #[oai(status = 500)]
     InternalError(PlainText<String>),
 }
 
 #[derive(ApiResponse)]
-enum GetDatasetReadmeResponse {
+enum DeleteResponse {
+    /// The ETag header is the new rev of the file, it is absent when the whole file is deleted.
     #[oai(status = 200)]
-    Ok(Json<String>),
+    Ok(Json<MessageResponse>, #[oai(header = "ETag")] Option<String>),
+
+    #[oai(status = 400)]
+    BadRequest(PlainText<String>),
+
+    #[oai(status = 401)]
+    Unauthorized(PlainText<String>),
+
+    #[oai(status = 403)]
+    Forbidden(PlainText<String>),
 
     #[oai(status = 404)]
     NotFound(PlainText<String>),
 
-    #[oai(status = 400)]
-    BadRequest(PlainText<String>),
+    #[oai(status = 410)]
+    Gone(PlainText<String>),
 
-    #[oai(status = 500)]
-    InternalError(PlainText<String>),
+    #[oai(status = 412)]
+    PreconditionFailed(PlainText<String>),
 }
 
-pub struct BioMinerIndexdApi;
-
+impl DeleteResponse {
+    fn ok(rev: Option<String>) -> Self {
+        DeleteResponse::Ok(
+            Json(MessageResponse {
+                msg: "Success".to_string(),
+            }),
+            rev.map(|rev| format!("\"{}\"", rev)),
+        )
+    }
+
+    fn from_error(e: anyhow::Error) -> Self {
+        match e.downcast_ref::<FileError>() {
+            Some(FileError::NotFound { .. }) | Some(FileError::NoSuchItem { .. }) => {
+                DeleteResponse::NotFound(PlainText(e.to_string()))
+            }
+            Some(FileError::Deleted { .. }) => DeleteResponse::Gone(PlainText(e.to_string())),
+            Some(FileError::RevMismatch { .. }) => {
+                DeleteResponse::PreconditionFailed(PlainText(e.to_string()))
+            }
+            _ => DeleteResponse::BadRequest(PlainText(e.to_string())),
+        }
+    }
+}
+
+/// Get the expected rev from the `If-Match` header or the `rev` query param, the header takes precedence.
+/// `If-Match: *` means no precondition.
+fn expected_rev(if_match: Option<String>, rev: Option<String>) -> Option<String> {
+    let rev = match if_match {
+        Some(etag) => etag,
+        None => rev?,
+    };
+    let rev = rev.trim().trim_start_matches("W/").trim_matches('"');
+
+    if rev.is_empty() || rev == "*" {
+        None
+    } else {
+        Some(rev.to_string())
+    }
+}
+
+#[derive(ApiResponse)]
+enum PostResponse {
+    #[oai(status = 201)]
+    Ok(Json<GuidResponse>),
+
+    /// The content has been registered by the file, and `on_conflict` is `return_existing`.
+    #[oai(status = 200)]
+    Existing(Json<GuidResponse>),
+
+    #[oai(status = 400)]
+    BadRequest(PlainText<String>),
+
+    #[oai(status = 401)]
+    Unauthorized(PlainText<String>),
+
+    #[oai(status = 409)]
+    Conflict(PlainText<String>),
+}
+
+#[derive(ApiRequest)]
+enum BulkCreateFiles {
+    Json(Json<Vec<CreateFile>>),
+
+    /// One file per line.
+    #[oai(content_type = "application/x-ndjson")]
+    Ndjson(PlainText<String>),
+}
+
+#[derive(ApiResponse)]
+enum PostBulkResponse {
+    #[oai(status = 200)]
+    Ok(Json<BulkCreateResponse>),
+
+    #[oai(status = 400)]
+    BadRequest(PlainText<String>),
+
+    #[oai(status = 401)]
+    Unauthorized(PlainText<String>),
+}
+
+#[derive(ApiResponse)]
+enum PostVersionResponse {
+    #[oai(status = 201)]
+    Ok(Json<FileVersionResponse>),
+
+    #[oai(status = 400)]
+    BadRequest(PlainText<String>),
+
+    #[oai(status = 401)]
+    Unauthorized(PlainText<String>),
+
+    #[oai(status = 403)]
+    Forbidden(PlainText<String>),
+
+    #[oai(status = 404)]
+    NotFound(PlainText<String>),
+
+    #[oai(status = 410)]
+    Gone(PlainText<String>),
+}
+
+#[derive(ApiResponse)]
+enum GetStatusHistoryResponse {
+    #[oai(status = 200)]
+    Ok(Json<Vec<StatusHistory>>),
+
+    #[oai(status = 401)]
+    Unauthorized(PlainText<String>),
+
+    #[oai(status = 404)]
+    NotFound(PlainText<String>),
+
+    #[oai(status = 500)]
+    InternalError(PlainText<String>),
+}
+
+#[derive(ApiResponse)]
+enum GetVersionsResponse {
+    #[oai(status = 200)]
+    Ok(Json<FileVersionsResponse>),
+
+    #[oai(status = 404)]
+    NotFound(PlainText<String>),
+
+    #[oai(status = 500)]
+    InternalError(PlainText<String>),
+}
+
+#[derive(ApiResponse)]
+enum GetDatasetsResponse {
+    #[oai(status = 200)]
+    Ok(Json<DatasetsResponse>),
+
+    #[oai(status = 500)]
+    InternalError(PlainText<String>),
+
+    #[oai(status = 400)]
+    BadRequest(PlainText<String>),
+
+    #[oai(status = 404)]
+    NotFound(PlainText<String>),
+}
+
+#[derive(ApiResponse)]
+enum GetDatasetMetadataDictionaryResponse {
+    #[oai(status = 200)]
+    Ok(Json<DataDictionary>),
+
+    #[oai(status = 404)]
+    NotFound(PlainText<String>),
+
+    #[oai(status = 500)]
+    InternalError(PlainText<String>),
+
+    #[oai(status = 400)]
+    BadRequest(PlainText<String>),
+}
+
+#[derive(ApiResponse)]
+enum GetDatasetDataFileTablesResponse {
+    #[oai(status = 200)]
+    Ok(Json<Vec<DataFileTable>>),
+
+    #[oai(status = 404)]
+    NotFound(PlainText<String>),
+
+    #[oai(status = 500)]
+    InternalError(PlainText<String>),
+
+    #[oai(status = 400)]
+    BadRequest(PlainText<String>),
+}
+
+#[derive(ApiResponse)]
+enum GetDatasetDataResponse {
+    #[oai(status = 200)]
+    Ok(Json<DatasetDataResponse>),
+
+    #[oai(status = 404)]
+    NotFound(PlainText<String>),
+
+    #[oai(status = 500)]
+    InternalError(PlainText<String>),
+
+    #[oai(status = 400)]
+    BadRequest(PlainText<String>),
+}
+
+#[derive(ApiResponse)]
+enum GetDatasetDatafilesResponse {
+    #[oai(status = 200)]
+    Ok(Json<Vec<HashMap<String, serde_json::Value>>>),
+
+    #[oai(status = 404)]
+    NotFound(PlainText<String>),
+
+    #[oai(status = 500)]
+    InternalError(PlainText<String>),
+
+    #[oai(status = 400)]
+    BadRequest(PlainText<String>),
+}
+
+#[derive(ApiResponse)]
+enum GetDatasetLicenseResponse {
+    #[oai(status = 200)]
+    Ok(Json<String>),
+
+    #[oai(status = 404)]
+    NotFound(PlainText<String>),
+
+    #[oai(status = 400)]
+    BadRequest(PlainText<String>),
+
+    #[oai(status = 500)]
+    InternalError(PlainText<String>),
+}
+
+#[derive(ApiResponse)]
+enum GetDatasetReadmeResponse {
+    #[oai(status = 200)]
+    Ok(Json<String>),
+
+    #[oai(status = 404)]
+    NotFound(PlainText<String>),
+
+    #[oai(status = 400)]
+    BadRequest(PlainText<String>),
+
+    #[oai(status = 500)]
+    InternalError(PlainText<String>),
+}
+
+pub struct BioMinerIndexdApi;
+
 #[OpenApi(prefix_path = "/api/v1")]
 impl BioMinerIndexdApi {
-    /// Call `/api/v1/files` to create a file instance.
+    /// Call `/api/v1/files` to create a file instance. The `on_conflict` decides what to do when the content has been registered by another file,
+    /// the file must be writable by the caller, otherwise it is a conflict without the guid of the file.
     #[oai(
         path = "/files",
         method = "post",
@@ -331,50 +1181,182 @@ impl BioMinerIndexdApi {
         &self,
         pool: Data<&Arc<sqlx::PgPool>>,
         config: Data<&Arc<Config>>,
+        repo_config: Data<&Arc<RepoConfig>>,
         params: Json<CreateFile>,
+        /// error, return_existing or new_version, the default is error.
+        on_conflict: Query<Option<String>>,
+        auth: CustomSecurityScheme,
     ) -> PostResponse {
         let pool = pool.clone();
-        info!("Creating file with params: {:?}", params);
+        let user = auth.0;
+        info!(
+            "Creating file by {} with params: {:?}",
+            user.username, params
+        );
+        let on_conflict = on_conflict.0.unwrap_or_else(|| "error".to_string());
 
-        let registry_id = &config.registry_id;
-        let filename = match &params.filename {
-            Some(filename) => filename,
-            None => "",
-        };
-        let uploader = match &params.uploader {
-            Some(uploader) => uploader,
-            None => "biominer-admin",
-        };
-        let size = params.size as i64;
+        if user.is_anonymous() {
+            return PostResponse::Unauthorized(PlainText(ANONYMOUS_WRITE_MESSAGE.to_string()));
+        }
 
-        let hash = &params.md5sum;
-        if !util::validate_hash(hash, "md5") {
-            return PostResponse::BadRequest(PlainText("Invalid hash value.".to_string()));
-        };
+        if !ON_CONFLICT_POLICIES.contains(&on_conflict.as_str()) {
+            return PostResponse::BadRequest(PlainText(format!(
+                "Invalid on_conflict: {}, only {} are supported.",
+                on_conflict,
+                ON_CONFLICT_POLICIES.join(", ")
+            )));
+        }
 
-        let url = match &params.url {
-            Some(url) => {
-                if util::which_protocol(url).is_none() {
-                    return PostResponse::BadRequest(PlainText(
-                        "Invalid url protocol.".to_string(),
-                    ));
-                } else {
-                    Some(url.as_str())
+        if let Err(msg) = params.validate(&repo_config) {
+            return PostResponse::BadRequest(PlainText(msg));
+        }
+
+        let mut registration = params.to_registration(&config.registry_id, "", &user.username);
+        let writable = |uploader: &str, acl: Option<&str>| user.can_write(uploader, acl);
+        match registration.add_with_policy(&pool, &on_conflict, &writable).await {
+            Ok((guid, true)) => PostResponse::Ok(Json(GuidResponse { guid })),
+            Ok((guid, false)) => PostResponse::Existing(Json(GuidResponse { guid })),
+            Err(e) => match e.downcast_ref::<FileError>() {
+                Some(FileError::Registered { .. }) => {
+                    PostResponse::Conflict(PlainText(e.to_string()))
                 }
-            }
-            _ => None,
+                _ => PostResponse::BadRequest(PlainText(e.to_string())),
+            },
+        }
+    }
+
+    /// Call `/api/v1/files/bulk` to create files in bulk. The body is a json array or a ndjson stream (`application/x-ndjson`) of files, each file gets a guid or an error in the response.
+    /// The `on_conflict` decides what to do when the content of a file has been registered by another file, the same as `/api/v1/files`.
+    #[oai(
+        path = "/files/bulk",
+        method = "post",
+        tag = "FileApiTags::Files",
+        operation_id = "createFiles"
+    )]
+    #[allow(clippy::too_many_arguments)]
+    async fn create_files(
+        &self,
+        pool: Data<&Arc<sqlx::PgPool>>,
+        config: Data<&Arc<Config>>,
+        repo_config: Data<&Arc<RepoConfig>>,
+        body: BulkCreateFiles,
+        batch_size: Query<Option<usize>>,
+        /// error, return_existing or new_version, the default is error.
+        on_conflict: Query<Option<String>>,
+        auth: CustomSecurityScheme,
+    ) -> PostBulkResponse {
+        let pool = pool.clone();
+        let user = auth.0;
+        let batch_size = batch_size.0.unwrap_or(1000);
+        let on_conflict = on_conflict.0.unwrap_or_else(|| "error".to_string());
+
+        if user.is_anonymous() {
+            return PostBulkResponse::Unauthorized(PlainText(ANONYMOUS_WRITE_MESSAGE.to_string()));
+        }
+
+        if !ON_CONFLICT_POLICIES.contains(&on_conflict.as_str()) {
+            return PostBulkResponse::BadRequest(PlainText(format!(
+                "Invalid on_conflict: {}, only {} are supported.",
+                on_conflict,
+                ON_CONFLICT_POLICIES.join(", ")
+            )));
+        }
+
+        let records: Vec<Result<CreateFile, String>> = match body {
+            BulkCreateFiles::Json(records) => records.0.into_iter().map(Ok).collect(),
+            BulkCreateFiles::Ndjson(text) => text
+                .0
+                .lines()
+                .filter(|line| !line.trim().is_empty())
+                .map(|line| serde_json::from_str::<CreateFile>(line).map_err(|e| e.to_string()))
+                .collect(),
         };
+        info!(
+            "Creating {} files in bulk by {}.",
+            records.len(),
+            user.username
+        );
 
-        let alias = match &params.alias {
-            Some(alias) => Some(alias.as_str()),
-            None => None,
+        let mut results: Vec<BulkCreateResult> = Vec::with_capacity(records.len());
+        let mut registrations: Vec<FileRegistration> = vec![];
+        let mut indexes: Vec<usize> = vec![];
+        // The hashes, urls and aliases must be unique in the whole request.
+        let mut seen: HashSet<String> = HashSet::new();
+        for (idx, record) in records.into_iter().enumerate() {
+            let error = match record {
+                Err(msg) => Some(BulkCreateError::new("invalid", msg)),
+                Ok(record) => match record.validate(&repo_config) {
+                    Err(msg) => Some(BulkCreateError::new("invalid", msg)),
+                    Ok(()) => {
+                        let registration =
+                            record.to_registration(&config.registry_id, "", &user.username);
+                        let keys: Vec<String> = std::iter::once(&registration.hash)
+                            .chain(registration.hashes.iter().map(|(_, h)| h))
+                            .map(|h| format!("hash {}", h))
+                            .chain(registration.urls.iter().map(|u| format!("url {}", u)))
+                            .chain(registration.aliases.iter().map(|a| format!("alias {}", a)))
+                            .collect();
+
+                        match keys.iter().find(|key| seen.contains(*key)) {
+                            Some(key) => Some(BulkCreateError::new(
+                                "duplicated",
+                                format!("The {} is duplicated in the request.", key),
+                            )),
+                            None => {
+                                seen.extend(keys);
+                                registrations.push(registration);
+                                indexes.push(idx);
+                                None
+                            }
+                        }
+                    }
+                },
+            };
+
+            results.push(BulkCreateResult {
+                index: idx as u64,
+                guid: None,
+                existing: false,
+                error,
+            });
+        }
+
+        let writable = |uploader: &str, acl: Option<&str>| user.can_write(uploader, acl);
+        let outcomes = match FileRegistration::add_bulk(
+            &pool,
+            &registrations,
+            batch_size,
+            &on_conflict,
+            &writable,
+        )
+        .await
+        {
+            Ok(outcomes) => outcomes,
+            Err(e) => return PostBulkResponse::BadRequest(PlainText(e.to_string())),
         };
 
-        let mut file = File::new(filename, size, uploader, registry_id);
-        match file.add(&pool, &hash, url, alias).await {
-            Ok(()) => PostResponse::Ok(Json(GuidResponse { guid: file.guid })),
-            Err(e) => PostResponse::BadRequest(PlainText(e.to_string())),
+        for (idx, outcome) in indexes.into_iter().zip(outcomes) {
+            match outcome {
+                Ok((guid, created)) => {
+                    results[idx].guid = Some(guid);
+                    results[idx].existing = !created;
+                }
+                Err(e) => {
+                    let code = match e.downcast_ref::<FileError>() {
+                        Some(FileError::Registered { .. }) => "conflict",
+                        _ => "failed",
+                    };
+                    results[idx].error = Some(BulkCreateError::new(code, e.to_string()));
+                }
+            }
         }
+
+        let failed = results.iter().filter(|r| r.error.is_some()).count() as u64;
+        PostBulkResponse::Ok(Json(BulkCreateResponse {
+            total: results.len() as u64,
+            failed,
+            results,
+        }))
     }
 
     /// Call `/api/v1/files` with query params to fetch files.
@@ -402,6 +1384,7 @@ impl BioMinerIndexdApi {
         contain_alias: Query<Option<bool>>,
         contain_url: Query<Option<bool>>,
         contain_tag: Query<Option<bool>>,
+        auth: CustomSecurityScheme,
     ) -> GetRecordsResponse<File> {
         let pool = pool.clone();
         let page = page.unwrap_or_else(|| 1);
@@ -457,6 +1440,8 @@ impl BioMinerIndexdApi {
             guid, filename, baseid, status, uploader, hash, alias, url, page, page_size
         );
 
+        // The private files are hidden from the users without permission.
+        let auth_groups = auth.0.auth_groups();
         let files = RecordResponse::<File>::query_files(
             &pool,
             QueryFilter::new(
@@ -470,7 +1455,8 @@ impl BioMinerIndexdApi {
                 &url,
                 &field_name,
                 &field_value,
-            ),
+            )
+            .with_auth_groups(auth_groups.as_deref()),
             page,
             page_size,
             contain_alias,
@@ -480,330 +1466,1195 @@ impl BioMinerIndexdApi {
         .await
         .unwrap();
 
-        debug!("Files: {:?}", files);
-        GetRecordsResponse::ok(files)
+        debug!("Files: {:?}", files);
+        GetRecordsResponse::ok(files)
+    }
+
+    /// Call `/api/v1/files/:id` to fetch the file.
+    #[oai(
+        path = "/files/:id",
+        method = "get",
+        tag = "FileApiTags::File",
+        operation_id = "getFile"
+    )]
+    async fn get_file(
+        &self,
+        pool: Data<&sqlx::PgPool>,
+        id: Path<uuid::Uuid>,
+        auth: CustomSecurityScheme,
+    ) -> GetFileResponse {
+        let pool = pool.clone();
+        let guid = id.0.to_string();
+        info!("Get file ({:?}) with params", guid);
+
+        match File::get_file(&pool, &id).await {
+            Ok(file) if !auth.0.can_access(file.acl.as_deref()) => {
+                GetFileResponse::Unauthorized(PlainText(PRIVATE_FILE_MESSAGE.to_string()))
+            }
+            Ok(file) => GetFileResponse::Ok(Json(file)),
+            Err(e) => match File::get_tombstone(&pool, &id).await {
+                Ok(Some(tombstone)) => GetFileResponse::Gone(PlainText(format!(
+                    "The file {} has been purged by {} at {}.",
+                    tombstone.guid, tombstone.deleted_by, tombstone.deleted_at
+                ))),
+                _ => GetFileResponse::NotFound(PlainText(e.to_string())),
+            },
+        }
+    }
+
+    /// Call `/api/v1/files/:id/versions` to register a new version of the file. The new file inherits the baseid and gets an incremented version.
+    #[oai(
+        path = "/files/:id/versions",
+        method = "post",
+        tag = "FileApiTags::File",
+        operation_id = "createFileVersion"
+    )]
+    async fn create_file_version(
+        &self,
+        pool: Data<&Arc<sqlx::PgPool>>,
+        config: Data<&Arc<Config>>,
+        repo_config: Data<&Arc<RepoConfig>>,
+        id: Path<uuid::Uuid>,
+        params: Json<CreateFile>,
+        auth: CustomSecurityScheme,
+    ) -> PostVersionResponse {
+        let pool = pool.clone();
+        let user = auth.0;
+        info!(
+            "Creating a new version of file ({:?}) by {} with params: {:?}",
+            id.0, user.username, params
+        );
+
+        if let Err(msg) = params.validate(&repo_config) {
+            return PostVersionResponse::BadRequest(PlainText(msg));
+        }
+
+        let file = match writable_file(&pool, &id, &user).await {
+            Ok(file) => file,
+            Err(failure) => return failure.into(),
+        };
+
+        // The new version keeps the filename of the file by default.
+        let filename = params.filename.clone().unwrap_or(file.filename);
+        let mut registration =
+            params.to_registration(&config.registry_id, &filename, &user.username);
+        match registration.add_version(&pool, &id).await {
+            Ok(()) => PostVersionResponse::Ok(Json(FileVersionResponse {
+                guid: registration.file.guid,
+                baseid: registration.file.baseid,
+                version: registration.file.version,
+            })),
+            Err(e) => match e.downcast_ref::<FileError>() {
+                Some(FileError::NotFound { .. }) => {
+                    PostVersionResponse::NotFound(PlainText(e.to_string()))
+                }
+                Some(FileError::Deleted { .. }) => {
+                    PostVersionResponse::Gone(PlainText(e.to_string()))
+                }
+                _ => PostVersionResponse::BadRequest(PlainText(e.to_string())),
+            },
+        }
+    }
+
+    /// Call `/api/v1/baseids/:baseid` to list all versions of the file, ordered by version.
+    #[oai(
+        path = "/baseids/:baseid",
+        method = "get",
+        tag = "FileApiTags::Files",
+        operation_id = "getFileVersions"
+    )]
+    async fn get_file_versions(
+        &self,
+        pool: Data<&Arc<sqlx::PgPool>>,
+        baseid: Path<String>,
+        auth: CustomSecurityScheme,
+    ) -> GetVersionsResponse {
+        let pool = pool.clone();
+        info!("Get all versions of file ({:?})", baseid.0);
+
+        let mut versions = match File::get_versions(&pool, &baseid.0).await {
+            Ok(versions) => versions,
+            Err(e) => return GetVersionsResponse::InternalError(PlainText(e.to_string())),
+        };
+        // The private versions are hidden from the users without permission.
+        versions.retain(|file| auth.0.can_access(file.acl.as_deref()));
+
+        if versions.is_empty() {
+            return GetVersionsResponse::NotFound(PlainText(format!(
+                "Cannot find the file with baseid {}",
+                baseid.0
+            )));
+        }
+
+        let latest = versions
+            .iter()
+            .rev()
+            .find(|file| file.status != "deleted")
+            .map(|file| file.guid.clone());
+
+        GetVersionsResponse::Ok(Json(FileVersionsResponse {
+            baseid: baseid.0,
+            latest,
+            versions,
+        }))
+    }
+
+    /// Call `/api/v1/baseids/:baseid/latest` to fetch the latest version of the file.
+    #[oai(
+        path = "/baseids/:baseid/latest",
+        method = "get",
+        tag = "FileApiTags::File",
+        operation_id = "getLatestFileVersion"
+    )]
+    async fn get_latest_file_version(
+        &self,
+        pool: Data<&Arc<sqlx::PgPool>>,
+        baseid: Path<String>,
+        auth: CustomSecurityScheme,
+    ) -> GetFileResponse {
+        let pool = pool.clone();
+        info!("Get the latest version of file ({:?})", baseid.0);
+
+        match File::get_latest_version(&pool, &baseid.0).await {
+            Ok(file) if !auth.0.can_access(file.acl.as_deref()) => {
+                GetFileResponse::Unauthorized(PlainText(PRIVATE_FILE_MESSAGE.to_string()))
+            }
+            Ok(file) => GetFileResponse::Ok(Json(file)),
+            Err(e) => GetFileResponse::NotFound(PlainText(e.to_string())),
+        }
+    }
+
+    /// Call `/api/v1/files/sign` to sign many files at once, the files can be identified by guids, hashes or aliases.
+    #[oai(
+        path = "/files/sign",
+        method = "post",
+        tag = "FileApiTags::Files",
+        operation_id = "signFiles"
+    )]
+    async fn sign_files(
+        &self,
+        pool: Data<&Arc<sqlx::PgPool>>,
+        config: Data<&Arc<RepoConfig>>,
+        params: Json<SignFiles>,
+        auth: CustomSecurityScheme,
+    ) -> PostBulkSignResponse {
+        let pool = pool.clone();
+        let config_arc = config.clone();
+
+        if params.ids.is_empty() {
+            return PostBulkSignResponse::BadRequest(PlainText("No files to sign.".to_string()));
+        }
+
+        if let Err(msg) = check_hash_type(params.hash_type.as_deref()) {
+            return PostBulkSignResponse::BadRequest(PlainText(msg));
+        }
+
+        info!("Sign {} files", params.ids.len());
+
+        let results = sign_many(&pool, &config_arc, &params, &auth.0).await;
+        let failed = results.iter().filter(|r| r.error.is_some()).count() as u64;
+
+        PostBulkSignResponse::Ok(Json(BulkSignResponse {
+            total: results.len() as u64,
+            failed,
+            results,
+        }))
+    }
+
+    /// Call `/api/v1/files/sign/manifest` to sign many files and get a download manifest for aria2 (`aria2c -i`) or biopoem.
+    #[oai(
+        path = "/files/sign/manifest",
+        method = "post",
+        tag = "FileApiTags::Files",
+        operation_id = "signFilesManifest"
+    )]
+    async fn sign_files_manifest(
+        &self,
+        pool: Data<&Arc<sqlx::PgPool>>,
+        config: Data<&Arc<RepoConfig>>,
+        params: Json<SignFiles>,
+        /// aria2 or tsv, the default is aria2.
+        format: Query<Option<String>>,
+        auth: CustomSecurityScheme,
+    ) -> PostSignManifestResponse {
+        let pool = pool.clone();
+        let config_arc = config.clone();
+        let format = format.0.unwrap_or_else(|| "aria2".to_string());
+
+        if format != "aria2" && format != "tsv" {
+            return PostSignManifestResponse::BadRequest(PlainText(format!(
+                "Invalid format: {}, only aria2 and tsv are supported.",
+                format
+            )));
+        }
+
+        if params.ids.is_empty() {
+            return PostSignManifestResponse::BadRequest(PlainText(
+                "No files to sign.".to_string(),
+            ));
+        }
+
+        if let Err(msg) = check_hash_type(params.hash_type.as_deref()) {
+            return PostSignManifestResponse::BadRequest(PlainText(msg));
+        }
+
+        info!("Sign {} files as {} manifest", params.ids.len(), format);
+
+        let results = sign_many(&pool, &config_arc, &params, &auth.0).await;
+        let manifest = if format == "tsv" {
+            tsv_manifest(&results)
+        } else {
+            aria2_manifest(&results)
+        };
+
+        PostSignManifestResponse::Ok(PlainText(manifest))
+    }
+
+    /// Call `/api/v1/files/hash/:hash` to sign the file and get the downloading link.
+    #[oai(
+        path = "/files/hash/:hash",
+        method = "post",
+        tag = "FileApiTags::File",
+        operation_id = "signFileWithHash"
+    )]
+    async fn sign_file_with_hash(
+        &self,
+        pool: Data<&Arc<sqlx::PgPool>>,
+        config: Data<&Arc<RepoConfig>>,
+        hash: Path<String>,
+        /// node, gsa, s3, oss, minio, file or http, the url is chosen by the selection policy if it is not set.
+        which_repo: Query<Option<String>>,
+        /// The region of the client, such as cn-hangzhou, the repos in the region are preferred.
+        region: Query<Option<String>>,
+        /// The preferred hash type, such as sha256, it is listed first in the hashes.
+        hash_type: Query<Option<String>>,
+        auth: CustomSecurityScheme,
+    ) -> PostSignResponse {
+        let pool = pool.clone();
+        let config_arc = config.clone();
+        let hash = hash.0.to_string();
+
+        match util::which_hash_type(&hash) {
+            Some(_) => {}
+            None => {
+                return PostSignResponse::BadRequest(PlainText("Invalid hash type.".to_string()));
+            }
+        };
+
+        if let Err(msg) = check_hash_type(hash_type.0.as_deref()) {
+            return PostSignResponse::BadRequest(PlainText(msg));
+        }
+
+        info!("Sign file with {:?}", hash);
+
+        match File::get_file_with_hash(&pool, &hash).await {
+            Ok(file) => {
+                match sign_file_data(
+                    &file,
+                    &config_arc,
+                    which_repo.0.as_deref(),
+                    region.0.as_deref(),
+                    hash_type.0.as_deref(),
+                    &auth.0,
+                )
+                .await
+                {
+                    Ok(sign_response) => PostSignResponse::Ok(Json(sign_response)),
+                    Err(failure) => failure.into(),
+                }
+            }
+            Err(e) => PostSignResponse::NotFound(PlainText(e.to_string())),
+        }
+    }
+
+    /// Call `/api/v1/files/:id` to sign the file and get the downloading link.
+    #[oai(
+        path = "/files/:id",
+        method = "post",
+        tag = "FileApiTags::File",
+        operation_id = "signFile"
+    )]
+    async fn sign_file(
+        &self,
+        pool: Data<&Arc<sqlx::PgPool>>,
+        config: Data<&Arc<RepoConfig>>,
+        id: Path<uuid::Uuid>,
+        /// node, gsa, s3, oss, minio, file or http, the url is chosen by the selection policy if it is not set.
+        which_repo: Query<Option<String>>,
+        /// The region of the client, such as cn-hangzhou, the repos in the region are preferred.
+        region: Query<Option<String>>,
+        /// The preferred hash type, such as sha256, it is listed first in the hashes.
+        hash_type: Query<Option<String>>,
+        auth: CustomSecurityScheme,
+    ) -> PostSignResponse {
+        let pool = pool.clone();
+        let config_arc = config.clone();
+        let guid = id.0.to_string();
+
+        if let Err(msg) = check_hash_type(hash_type.0.as_deref()) {
+            return PostSignResponse::BadRequest(PlainText(msg));
+        }
+
+        info!("Sign file {:?}", guid);
+
+        match File::get_file(&pool, &id).await {
+            Ok(file) => {
+                match sign_file_data(
+                    &file,
+                    &config_arc,
+                    which_repo.0.as_deref(),
+                    region.0.as_deref(),
+                    hash_type.0.as_deref(),
+                    &auth.0,
+                )
+                .await
+                {
+                    Ok(sign_response) => PostSignResponse::Ok(Json(sign_response)),
+                    Err(failure) => failure.into(),
+                }
+            }
+            Err(e) => PostSignResponse::NotFound(PlainText(e.to_string())),
+        }
+    }
+
+    /// Call `/api/v1/files/:id/download` to download the file by its guid, hash or alias.
+    ///
+    /// The client is redirected to the signed url, so `curl -L` and the genome browsers can use the ids directly.
+    /// The slash in a guid must be encoded as %2F. A repo which only accepts a POST request gets a page which
+    /// submits the form, set `proxy` to let indexd download it instead.
+    #[oai(
+        path = "/files/:id/download",
+        method = "get",
+        tag = "FileApiTags::File",
+        operation_id = "downloadFile"
+    )]
+    #[allow(clippy::too_many_arguments)]
+    async fn download_file(
+        &self,
+        pool: Data<&Arc<sqlx::PgPool>>,
+        config: Data<&Arc<RepoConfig>>,
+        id: Path<String>,
+        /// node, gsa, s3, oss, minio, file or http, the url is chosen by the selection policy if it is not set.
+        which_repo: Query<Option<String>>,
+        /// The region of the client, such as cn-hangzhou, the repos in the region are preferred.
+        region: Query<Option<String>>,
+        /// Stream the file through indexd instead of redirecting to the repo.
+        proxy: Query<Option<bool>>,
+        auth: CustomSecurityScheme,
+    ) -> GetFileDownloadResponse {
+        let pool = pool.clone();
+        let id = util::decode_path_param(&id.0);
+
+        info!("Download file {:?}", id);
+
+        let file = match File::resolve(&pool, &id).await {
+            Ok(file) => file,
+            Err(e) => return GetFileDownloadResponse::NotFound(PlainText(e.to_string())),
+        };
+
+        let sign = match sign_file_data(
+            &file,
+            &config,
+            which_repo.0.as_deref(),
+            region.0.as_deref(),
+            None,
+            &auth.0,
+        )
+        .await
+        {
+            Ok(sign) => sign,
+            Err(failure) => return failure.into(),
+        };
+
+        match delivery_of(&sign.sign, proxy.0.unwrap_or(false)) {
+            Delivery::Redirect(url) => GetFileDownloadResponse::Found(url),
+            Delivery::Form(page) => GetFileDownloadResponse::Form(Html(page)),
+            Delivery::Proxy => proxy_download(&sign).await,
+        }
+    }
+
+    /// Call `/api/v1/files/:id/upload` to get the presigned urls to upload the content of the file to a minio or s3 bucket.
+    ///
+    /// The file must have been registered. Call `/api/v1/files/:id/upload/complete` after the upload.
+    #[oai(
+        path = "/files/:id/upload",
+        method = "post",
+        tag = "FileApiTags::File",
+        operation_id = "createUpload"
+    )]
+    async fn create_upload(
+        &self,
+        pool: Data<&Arc<sqlx::PgPool>>,
+        config: Data<&Arc<RepoConfig>>,
+        id: Path<uuid::Uuid>,
+        params: Json<CreateUpload>,
+        auth: CustomSecurityScheme,
+    ) -> PostUploadResponse {
+        let pool = pool.clone();
+        info!(
+            "Creating the upload of file ({:?}) by {} with params: {:?}",
+            id.0, auth.0.username, params
+        );
+
+        let which_repo = params.which_repo.clone().unwrap_or_else(|| "minio".to_string());
+        let s3 = match config.fetch_s3(&which_repo, params.bucket.as_deref()) {
+            Some(s3) => s3,
+            None => {
+                return PostUploadResponse::BadRequest(PlainText(format!(
+                    "No {} repo is configured for the bucket {:?}.",
+                    which_repo, params.bucket
+                )));
+            }
+        };
+
+        let file = match writable_file(&pool, &id.0, &auth.0).await {
+            Ok(file) => file,
+            Err(failure) => return failure.into(),
+        };
+        // A deleted file gets no content
+        if let Err(failure) = check_access(&file, &auth.0) {
+            return failure.into();
+        }
+
+        // The key is chosen here, so a client can't overwrite the objects of the other files.
+        let key = format!("{}/{}", id.0, file.filename);
+        let upload = Upload {
+            protocol: &which_repo,
+            config: s3,
+            key: &key,
+        };
+
+        let (upload_id, parts) = match params.parts.unwrap_or(1) {
+            1 => (None, vec![(1, upload.presign_put())]),
+            n => {
+                let client = reqwest::Client::new();
+                let upload_id = match upload.create_multipart(&client).await {
+                    Ok(upload_id) => upload_id,
+                    Err(e) => return PostUploadResponse::InternalError(PlainText(e.to_string())),
+                };
+                let parts = (1..=n)
+                    .map(|number| (number, upload.presign_part(&upload_id, number)))
+                    .collect();
+                (Some(upload_id), parts)
+            }
+        };
+
+        let mut signed_parts = vec![];
+        for (part_number, sign) in parts {
+            match sign {
+                Ok(sign) => signed_parts.push(UploadPart { part_number, sign }),
+                Err(e) => return PostUploadResponse::InternalError(PlainText(e.to_string())),
+            }
+        }
+
+        let pending = PendingUpload::new(&file.guid, &upload, upload_id.as_deref(), &auth.0.username);
+        if let Err(e) = pending.save(&pool).await {
+            return PostUploadResponse::InternalError(PlainText(e.to_string()));
+        }
+
+        PostUploadResponse::Ok(Json(UploadResponse {
+            which_repo: which_repo.clone(),
+            bucket: s3.bucket.clone(),
+            url: upload.url(),
+            key,
+            upload_id,
+            parts: signed_parts,
+        }))
+    }
+
+    /// Call `/api/v1/files/:id/upload/complete` to finalize the upload, the object is checked against the size and the md5 of the file.
+    ///
+    /// Then the url is attached to the file and both of them are marked as validated.
+    #[oai(
+        path = "/files/:id/upload/complete",
+        method = "post",
+        tag = "FileApiTags::File",
+        operation_id = "completeUpload"
+    )]
+    async fn complete_upload(
+        &self,
+        pool: Data<&Arc<sqlx::PgPool>>,
+        config: Data<&Arc<RepoConfig>>,
+        id: Path<uuid::Uuid>,
+        params: Json<CompleteUpload>,
+        auth: CustomSecurityScheme,
+    ) -> PutResponse {
+        let pool = pool.clone();
+        let user = auth.0;
+        info!(
+            "Completing the upload of file ({:?}) by {} with params: {:?}",
+            id.0, user.username, params
+        );
+
+        let file = match writable_file(&pool, &id.0, &user).await {
+            Ok(file) => file,
+            Err(failure) => return failure.into(),
+        };
+
+        // Only the object and the upload id given by `/api/v1/files/:id/upload` can be attached to the file.
+        let key = params.key.trim_start_matches('/');
+        let pending =
+            match PendingUpload::get(&pool, &file.guid, key, params.upload_id.as_deref()).await {
+                Ok(pending) => pending,
+                Err(e) => return PutResponse::from_error(e),
+            };
+        let s3 = match config.fetch_s3(&pending.protocol, Some(&pending.bucket)) {
+            Some(s3) => s3,
+            None => {
+                return PutResponse::BadRequest(PlainText(format!(
+                    "No {} repo is configured for the bucket {}.",
+                    pending.protocol, pending.bucket
+                )));
+            }
+        };
+
+        let upload = Upload {
+            protocol: &pending.protocol,
+            config: s3,
+            key: &pending.key,
+        };
+        let client = reqwest::Client::new();
+        match upload::finalize_upload(
+            &pool,
+            &client,
+            &upload,
+            &id.0,
+            pending.upload_id.as_deref(),
+            &user.username,
+        )
+        .await
+        {
+            Ok((_, rev)) => {
+                if let Err(e) = PendingUpload::remove(&pool, &file.guid).await {
+                    warn!("Failed to remove the upload of the file {}: {}", file.guid, e);
+                }
+                PutResponse::ok(rev)
+            }
+            Err(e) => PutResponse::from_error(e),
+        }
+    }
+
+    /// Call `/api/v1/download/local` to download the file of a local repo with the signed link, a single byte range is supported.
+    #[oai(
+        path = "/download/local",
+        method = "get",
+        tag = "FileApiTags::File",
+        operation_id = "downloadLocalFile"
+    )]
+    async fn download_local_file(
+        &self,
+        config: Data<&Arc<RepoConfig>>,
+        /// The path of the file, such as /data/biominer/a.bam.
+        path: Query<String>,
+        /// When the link expires, seconds since epoch.
+        expires: Query<i64>,
+        signature: Query<String>,
+        #[oai(name = "Range")] range: Header<Option<String>>,
+    ) -> GetDownloadResponse {
+        let local = match config.fetch_local(&path) {
+            Some(local) => local,
+            None => {
+                return GetDownloadResponse::NotFound(PlainText(format!(
+                    "No local repo is configured for {}.",
+                    path.0
+                )));
+            }
+        };
+
+        let file_path = match local.verify(&path, expires.0, &signature) {
+            Ok(file_path) => file_path,
+            Err(msg) => return GetDownloadResponse::Forbidden(PlainText(msg)),
+        };
+
+        let mut file = match tokio::fs::File::open(&file_path).await {
+            Ok(file) => file,
+            Err(e) => {
+                return GetDownloadResponse::NotFound(PlainText(format!(
+                    "Cannot open the file {}: {}",
+                    path.0, e
+                )));
+            }
+        };
+        let size = match file.metadata().await {
+            Ok(metadata) if metadata.is_file() => metadata.len(),
+            _ => {
+                return GetDownloadResponse::NotFound(PlainText(format!(
+                    "{} is not a file.",
+                    path.0
+                )));
+            }
+        };
+
+        let range = match range.0.as_deref().map(|range| util::parse_range(range, size)) {
+            Some(Ok(range)) => range,
+            Some(Err(msg)) => {
+                return GetDownloadResponse::RangeNotSatisfiable(
+                    PlainText(msg),
+                    format!("bytes */{}", size),
+                );
+            }
+            None => None,
+        };
+
+        info!("Download the file {} with the range {:?}", path.0, range);
+        match range {
+            Some((start, end)) => {
+                if let Err(e) = file.seek(std::io::SeekFrom::Start(start)).await {
+                    return GetDownloadResponse::InternalError(PlainText(e.to_string()));
+                }
+                let length = end - start + 1;
+                GetDownloadResponse::PartialContent(
+                    Binary(Body::from_async_read(file.take(length))),
+                    length,
+                    format!("bytes {}-{}/{}", start, end, size),
+                )
+            }
+            None => GetDownloadResponse::Ok(
+                Binary(Body::from_async_read(file)),
+                size,
+                "bytes".to_string(),
+            ),
+        }
+    }
+
+    /// Call `/api/v1/files/:id/url` to add url for the file.
+    #[oai(
+        path = "/files/:id/url",
+        method = "put",
+        tag = "FileApiTags::File",
+        operation_id = "addUrlToFile"
+    )]
+    #[allow(clippy::too_many_arguments)]
+    async fn add_url(
+        &self,
+        pool: Data<&Arc<sqlx::PgPool>>,
+        repo_config: Data<&Arc<RepoConfig>>,
+        id: Path<uuid::Uuid>,
+        params: Json<AddFileUrl>,
+        rev: Query<Option<String>>,
+        #[oai(name = "If-Match")] if_match: Header<Option<String>>,
+        auth: CustomSecurityScheme,
+    ) -> PutResponse {
+        let pool = pool.clone();
+        let user = auth.0;
+        info!(
+            "Updating file ({:?}) by {} with params: {:?}",
+            id.0, user.username, params
+        );
+
+        if let Err(failure) = writable_file(&pool, &id.0, &user).await {
+            return failure.into();
+        }
+
+        let status = if let Some(status) = &params.status {
+            status.clone()
+        } else {
+            "pending".to_string()
+        };
+
+        let url = &params.url;
+
+        if let Err(msg) = repo_config.check_url(url) {
+            warn!("Cannot add the url {}: {}", url, msg);
+            return PutResponse::BadRequest(PlainText(msg));
+        }
+
+        let rev = expected_rev(if_match.0, rev.0);
+        match File::add_url(&pool, &id.0, url, &user.username, &status, rev.as_deref()).await {
+            Ok(rev) => PutResponse::ok(rev),
+            Err(e) => PutResponse::from_error(e),
+        }
+    }
+
+    /// Call `/api/v1/files/:id/alias` to add alias for the file.
+    #[oai(
+        path = "/files/:id/alias",
+        method = "put",
+        tag = "FileApiTags::File",
+        operation_id = "addAliasToFile"
+    )]
+    async fn add_alias(
+        &self,
+        pool: Data<&Arc<sqlx::PgPool>>,
+        id: Path<uuid::Uuid>,
+        params: Json<AddFileAlias>,
+        rev: Query<Option<String>>,
+        #[oai(name = "If-Match")] if_match: Header<Option<String>>,
+        auth: CustomSecurityScheme,
+    ) -> PutResponse {
+        let pool = pool.clone();
+        let user = auth.0;
+        info!(
+            "Updating file ({:?}) by {} with params: {:?}",
+            id.0, user.username, params
+        );
+
+        if let Err(failure) = writable_file(&pool, &id.0, &user).await {
+            return failure.into();
+        }
+
+        let rev = expected_rev(if_match.0, rev.0);
+        match File::add_alias(&pool, &id.0, &params.alias, rev.as_deref()).await {
+            Ok(rev) => PutResponse::ok(rev),
+            Err(e) => PutResponse::from_error(e),
+        }
+    }
+
+    /// Call `/api/v1/files/:id/hash` to add hash for the file.
+    #[oai(
+        path = "/files/:id/hash",
+        method = "put",
+        tag = "FileApiTags::File",
+        operation_id = "addHashToFile"
+    )]
+    async fn add_hash(
+        &self,
+        pool: Data<&Arc<sqlx::PgPool>>,
+        id: Path<uuid::Uuid>,
+        params: Json<AddFileHash>,
+        rev: Query<Option<String>>,
+        #[oai(name = "If-Match")] if_match: Header<Option<String>>,
+        auth: CustomSecurityScheme,
+    ) -> PutResponse {
+        let pool = pool.clone();
+        let user = auth.0;
+        info!(
+            "Updating file ({:?}) by {} with params: {:?}",
+            id.0, user.username, params
+        );
+
+        if let Err(failure) = writable_file(&pool, &id.0, &user).await {
+            return failure.into();
+        }
+
+        let rev = expected_rev(if_match.0, rev.0);
+        match File::add_hash(
+            &pool,
+            &id.0,
+            &params.hash,
+            params.hash_type.as_deref(),
+            rev.as_deref(),
+        )
+        .await
+        {
+            Ok(rev) => PutResponse::ok(rev),
+            Err(e) => PutResponse::from_error(e),
+        }
+    }
+
+    /// Call `/api/v1/files/:id/tag` to add tag for the file.
+    #[oai(
+        path = "/files/:id/tag",
+        method = "put",
+        tag = "FileApiTags::File",
+        operation_id = "addTagToFile"
+    )]
+    async fn add_tag(
+        &self,
+        pool: Data<&Arc<sqlx::PgPool>>,
+        id: Path<uuid::Uuid>,
+        params: Json<AddFileTag>,
+        rev: Query<Option<String>>,
+        #[oai(name = "If-Match")] if_match: Header<Option<String>>,
+        auth: CustomSecurityScheme,
+    ) -> PutResponse {
+        let pool = pool.clone();
+        let user = auth.0;
+        info!(
+            "Updating file ({:?}) by {} with params: {:?}",
+            id.0, user.username, params
+        );
+
+        if let Err(failure) = writable_file(&pool, &id.0, &user).await {
+            return failure.into();
+        }
+
+        let rev = expected_rev(if_match.0, rev.0);
+        match File::add_tag(&pool, &id.0, &params.field_name, &params.field_value, rev.as_deref()).await {
+            Ok(rev) => PutResponse::ok(rev),
+            Err(e) => PutResponse::from_error(e),
+        }
+    }
+
+    /// Call `/api/v1/files/:id/status` to change the status of the file, e.g. the QC sign-off. Only the allowed transitions are accepted, and the change is recorded in the status history.
+    #[oai(
+        path = "/files/:id/status",
+        method = "put",
+        tag = "FileApiTags::File",
+        operation_id = "updateFileStatus"
+    )]
+    async fn update_status(
+        &self,
+        pool: Data<&Arc<sqlx::PgPool>>,
+        id: Path<uuid::Uuid>,
+        params: Json<UpdateFileStatus>,
+        rev: Query<Option<String>>,
+        #[oai(name = "If-Match")] if_match: Header<Option<String>>,
+        auth: CustomSecurityScheme,
+    ) -> PutResponse {
+        let pool = pool.clone();
+        let user = auth.0;
+        info!(
+            "Updating the status of file ({:?}) by {} with params: {:?}",
+            id.0, user.username, params
+        );
+
+        if let Err(failure) = writable_file(&pool, &id.0, &user).await {
+            return failure.into();
+        }
+
+        let status = match params.status.parse::<FileStatus>() {
+            Ok(status) => status,
+            Err(e) => return PutResponse::BadRequest(PlainText(e.to_string())),
+        };
+
+        let rev = expected_rev(if_match.0, rev.0);
+        match File::update_status(
+            &pool,
+            &id.0,
+            status,
+            &user.username,
+            params.reason.as_deref(),
+            rev.as_deref(),
+        )
+        .await
+        {
+            Ok(rev) => PutResponse::ok(rev),
+            Err(e) => PutResponse::from_error(e),
+        }
+    }
+
+    /// Call `/api/v1/files/:id/acl` to change the acl of the file, administrators only. The file becomes public when acl and authz are null.
+    #[oai(
+        path = "/files/:id/acl",
+        method = "put",
+        tag = "FileApiTags::File",
+        operation_id = "updateFileAcl"
+    )]
+    async fn update_file_acl(
+        &self,
+        pool: Data<&Arc<sqlx::PgPool>>,
+        id: Path<uuid::Uuid>,
+        params: Json<UpdateFileAcl>,
+        rev: Query<Option<String>>,
+        #[oai(name = "If-Match")] if_match: Header<Option<String>>,
+        auth: CustomSecurityScheme,
+    ) -> PutResponse {
+        let pool = pool.clone();
+        let user = auth.0;
+        info!(
+            "Updating the acl of file ({:?}) by {} with params: {:?}",
+            id.0, user.username, params
+        );
+
+        if !user.is_admin() {
+            return PutResponse::Forbidden(PlainText(
+                "Only administrators can change the acl.".to_string(),
+            ));
+        }
+
+        let update = match acl_of(params.acl.as_deref(), params.authz.as_deref()) {
+            Ok(acl) => FileUpdate {
+                acl: Some(acl),
+                ..Default::default()
+            },
+            Err(msg) => return PutResponse::BadRequest(PlainText(msg)),
+        };
+
+        let rev = expected_rev(if_match.0, rev.0);
+        match File::update(&pool, &id.0, &update, &user.username, rev.as_deref()).await {
+            Ok(rev) => PutResponse::ok(rev),
+            Err(e) => PutResponse::from_error(e),
+        }
     }
 
-    /// Call `/api/v1/files/:id` to fetch the file.
+    /// Call `/api/v1/files/acl` with query params to change the acl of all the matched files, administrators only. At least one filter is required.
     #[oai(
-        path = "/files/:id",
-        method = "get",
-        tag = "FileApiTags::File",
-        operation_id = "getFile"
+        path = "/files/acl",
+        method = "put",
+        tag = "FileApiTags::Files",
+        operation_id = "updateFilesAcl"
     )]
-    async fn get_file(&self, pool: Data<&sqlx::PgPool>, id: Path<uuid::Uuid>) -> GetFileResponse {
+    #[allow(clippy::too_many_arguments)]
+    async fn update_files_acl(
+        &self,
+        pool: Data<&Arc<sqlx::PgPool>>,
+        params: Json<UpdateFileAcl>,
+        filename: Query<Option<String>>,
+        baseid: Query<Option<String>>,
+        status: Query<Option<String>>,
+        uploader: Query<Option<String>>,
+        hash: Query<Option<String>>,
+        alias: Query<Option<String>>,
+        url: Query<Option<String>>,
+        field_name: Query<Option<String>>,
+        field_value: Query<Option<String>>,
+        auth: CustomSecurityScheme,
+    ) -> PutAclResponse {
         let pool = pool.clone();
-        let guid = id.0.to_string();
-        info!("Get file ({:?}) with params", guid);
+        let user = auth.0;
+        info!("Updating the acl of files by {} with params: {:?}", user.username, params);
 
-        match File::get_file(&pool, &id).await {
-            Ok(file) => GetFileResponse::Ok(Json(file)),
-            Err(e) => return GetFileResponse::NotFound(PlainText(e.to_string())),
+        if !user.is_admin() {
+            return PutAclResponse::Forbidden(PlainText(
+                "Only administrators can change the acl.".to_string(),
+            ));
+        }
+
+        let acl = match acl_of(params.acl.as_deref(), params.authz.as_deref()) {
+            Ok(acl) => acl,
+            Err(msg) => return PutAclResponse::BadRequest(PlainText(msg)),
+        };
+
+        let filters = [
+            &filename, &baseid, &status, &uploader, &hash, &alias, &url, &field_name, &field_value,
+        ];
+        if filters.iter().all(|filter| filter.0.as_deref().unwrap_or_default().is_empty()) {
+            return PutAclResponse::BadRequest(PlainText(
+                "At least one filter is required to change the acl of the files.".to_string(),
+            ));
+        }
+
+        let [filename, baseid, status, uploader, hash, alias, url, field_name, field_value] =
+            filters.map(|filter| filter.0.as_deref().unwrap_or_default());
+        // The tag filter is ignored unless both of them are given.
+        if field_name.is_empty() != field_value.is_empty() {
+            return PutAclResponse::BadRequest(PlainText(
+                "The field_name and field_value must be given together.".to_string(),
+            ));
+        }
+        let filter = QueryFilter::new(
+            "", filename, baseid, status, uploader, hash, alias, url, field_name, field_value,
+        );
+        match update_acl(&pool, &filter, acl.as_deref()).await {
+            Ok(updated) => PutAclResponse::Ok(Json(UpdateAclResponse { updated })),
+            Err(e) => PutAclResponse::InternalError(PlainText(e.to_string())),
         }
     }
 
-    /// Call `/api/v1/files/:id` to sign the file and get the downloading link.
+    /// Call `/api/v1/files/:id/status/history` to list the status changes of the file, ordered by time.
     #[oai(
-        path = "/files/hash/:hash",
-        method = "post",
+        path = "/files/:id/status/history",
+        method = "get",
         tag = "FileApiTags::File",
-        operation_id = "signFileWithHash"
+        operation_id = "getFileStatusHistory"
     )]
-    async fn sign_file_with_hash(
+    async fn get_status_history(
         &self,
         pool: Data<&Arc<sqlx::PgPool>>,
-        config: Data<&Arc<RepoConfig>>,
-        hash: Path<String>,
-        which_repo: Query<Option<String>>,
-        #[oai(name = "X-Auth-Groups", deprecated)] auth_groups: Header<Option<String>>,
-    ) -> PostSignResponse {
+        id: Path<uuid::Uuid>,
+        auth: CustomSecurityScheme,
+    ) -> GetStatusHistoryResponse {
         let pool = pool.clone();
-        let config_arc = config.clone();
-        let hash = hash.0.to_string();
-        let which_repo = match which_repo.0 {
-            Some(which_repo) => which_repo,
-            // TODO: Need to set a best repo, select gsa or select one based on the user's position.
-            None => "node".to_string(),
-        };
-        let auth_groups = auth_groups.0;
+        let user = auth.0;
+        info!(
+            "Get the status history of file ({:?}) by {}",
+            id.0, user.username
+        );
 
-        match util::which_hash_type(&hash) {
-            Some(_) => {}
-            None => {
-                return PostSignResponse::BadRequest(PlainText("Invalid hash type.".to_string()));
+        // The history of a purged file is kept, but its acl is gone, so only the administrators can read it.
+        let file = File::get_file(&pool, &id.0).await;
+        match &file {
+            Ok(file) if !user.can_access(file.acl.as_deref()) => {
+                return GetStatusHistoryResponse::Unauthorized(PlainText(
+                    PRIVATE_FILE_MESSAGE.to_string(),
+                ));
             }
-        };
-
-        info!("Sign file with {:?}", hash);
-
-        match File::get_file_with_hash(&pool, &hash).await {
-            Ok(file) => {
-                if file.access == "private".to_string() {
-                    if auth_groups.is_none()
-                        || !util::has_permission(&auth_groups.unwrap()[..], &file.acl.unwrap()[..])
-                    {
-                        return PostSignResponse::Unauthorized(PlainText(format!(
-                            "The data is private and you do not have permission to access."
-                        )));
-                    }
-                }
+            Err(e) if !user.is_admin() => {
+                return GetStatusHistoryResponse::NotFound(PlainText(e.to_string()));
+            }
+            _ => {}
+        }
 
-                if let Some(hashes) = &file.hashes {
-                    let hashes: Vec<Hash> = serde_json::from_value(hashes.clone()).unwrap();
-
-                    match &file.urls {
-                        Some(urls) => {
-                            let urls: Vec<URL> = serde_json::from_value(urls.clone()).unwrap();
-                            if let Some(idx) =
-                                urls.iter().position(|item| item.url.contains(&which_repo))
-                            {
-                                let url = &urls[idx];
-                                let identity = url.get_identity();
-                                match config_arc.fetch_config(&which_repo, &identity) {
-                                    Some(c) => {
-                                        let sign_data = c.sign(&url.url);
-                                        let sign_response = SignResponse {
-                                            sign: sign_data,
-                                            size: file.size as u64,
-                                            hashes: hashes.clone(),
-                                            filename: file.filename.clone(),
-                                        };
-                                        return PostSignResponse::Ok(Json(sign_response));
-                                    }
-                                    None => {
-                                        return PostSignResponse::InternalError(PlainText(
-                                            format!(
-                                                "The data has not been released, please contact the administrator for more details."
-                                            )
-                                        ));
-                                    }
-                                }
-                            }
-                        }
-                        _ => {}
-                    }
-                } else {
-                    return PostSignResponse::InternalError(PlainText(
-                        "The data has no hashes, please contact the administrator for more details.".to_string(),
-                    ));
-                }
+        let history = match File::get_status_history(&pool, &id.0).await {
+            Ok(history) => history,
+            Err(e) => return GetStatusHistoryResponse::InternalError(PlainText(e.to_string())),
+        };
 
-                // Last arm of the match
-                return PostSignResponse::NotFound(PlainText(format!(
-          "The data has not been released on {} repo, please contact the administrator to add it.",
-          which_repo
-        )));
-            }
-            Err(e) => return PostSignResponse::NotFound(PlainText(e.to_string())),
+        // Only report the file which has never been registered.
+        if let (true, Err(e)) = (history.is_empty(), file) {
+            return GetStatusHistoryResponse::NotFound(PlainText(e.to_string()));
         }
+
+        GetStatusHistoryResponse::Ok(Json(history))
     }
 
-    /// Call `/api/v1/files/:id` to sign the file and get the downloading link.
+    /// Call `/api/v1/files/:id` to delete the file. The file is marked as deleted and keeps a tombstone, set `purge=true` (administrators only) to remove the file and all its urls, hashes, aliases and tags.
     #[oai(
         path = "/files/:id",
-        method = "post",
+        method = "delete",
         tag = "FileApiTags::File",
-        operation_id = "signFile"
+        operation_id = "deleteFile"
     )]
-    async fn sign_file(
+    async fn delete_file(
         &self,
         pool: Data<&Arc<sqlx::PgPool>>,
-        config: Data<&Arc<RepoConfig>>,
         id: Path<uuid::Uuid>,
-        which_repo: Query<Option<String>>,
-        #[oai(name = "X-Auth-Groups", deprecated)] auth_groups: Header<Option<String>>,
-    ) -> PostSignResponse {
+        purge: Query<Option<bool>>,
+        reason: Query<Option<String>>,
+        auth: CustomSecurityScheme,
+    ) -> DeleteResponse {
         let pool = pool.clone();
-        let config_arc = config.clone();
-        let guid = id.0.to_string();
-        let which_repo = match which_repo.0 {
-            Some(which_repo) => which_repo,
-            // TODO: Need to set a best repo, select gsa or select one based on the user's position.
-            None => "node".to_string(),
-        };
-        let auth_groups = auth_groups.0;
+        let purge = purge.0.unwrap_or(false);
+        let reason = reason.0;
+        let user = auth.0;
+        info!("Deleting file ({:?}) by {} (purge: {})", id.0, user.username, purge);
 
-        info!("Sign file {:?}", guid);
+        if let Err(failure) = writable_file(&pool, &id.0, &user).await {
+            return failure.into();
+        }
 
-        match File::get_file(&pool, &id).await {
-            Ok(file) => {
-                if file.access == "private" {
-                    if auth_groups.is_none()
-                        || !util::has_permission(&auth_groups.unwrap()[..], &file.acl.unwrap()[..])
-                    {
-                        return PostSignResponse::Unauthorized(PlainText(format!(
-                            "The data is private and you do not have permission to access."
-                        )));
-                    }
-                }
+        let result = if purge {
+            if !user.is_admin() {
+                return DeleteResponse::Forbidden(PlainText(
+                    "Only administrators can purge files.".to_string(),
+                ));
+            }
 
-                if let Some(hashes) = &file.hashes {
-                    let hashes: Vec<Hash> = serde_json::from_value(hashes.clone()).unwrap();
-
-                    match &file.urls {
-                        Some(urls) => {
-                            let urls: Vec<URL> = serde_json::from_value(urls.clone()).unwrap();
-
-                            if let Some(idx) =
-                                urls.iter().position(|item| item.url.contains(&which_repo))
-                            {
-                                let url = &urls[idx];
-                                let identity = url.get_identity();
-                                match config_arc.fetch_config(&which_repo, &identity) {
-                                    Some(c) => {
-                                        let sign_data = c.sign(&url.url);
-                                        let sign_response = SignResponse {
-                                            sign: sign_data,
-                                            size: file.size as u64,
-                                            hashes: hashes.clone(),
-                                            filename: file.filename.clone(),
-                                        };
-                                        return PostSignResponse::Ok(Json(sign_response));
-                                    }
-                                    None => {
-                                        return PostSignResponse::InternalError(
-                                            PlainText(format!(
-                                                "The data has not been released, please contact the administrator for more details."
-                                            ))
-                                        );
-                                    }
-                                }
-                            }
-                        }
-                        _ => {}
-                    }
-                } else {
-                    return PostSignResponse::InternalError(PlainText(
-                        "The data has no hashes, please contact the administrator for more details.".to_string(),
-                    ));
-                }
+            File::purge_file(&pool, &id.0, &user.username, reason.as_deref()).await
+        } else {
+            File::delete_file(&pool, &id.0, &user.username, reason.as_deref()).await
+        };
 
-                // Last arm of the match
-                return PostSignResponse::NotFound(PlainText(format!(
-          "The data has not been released on {} repo, please contact the administrator to add it.",
-          which_repo
-        )));
-            }
-            Err(e) => return PostSignResponse::NotFound(PlainText(e.to_string())),
+        match result {
+            Ok(()) => DeleteResponse::ok(None),
+            Err(e) => DeleteResponse::from_error(e),
         }
     }
 
-    /// Call `/api/v1/files/:id/url` to add url for the file.
+    /// Call `/api/v1/files/:id/url` to delete the url of the file.
     #[oai(
         path = "/files/:id/url",
-        method = "put",
+        method = "delete",
         tag = "FileApiTags::File",
-        operation_id = "addUrlToFile"
+        operation_id = "deleteUrlFromFile"
     )]
-    async fn add_url(
+    async fn delete_url(
         &self,
         pool: Data<&Arc<sqlx::PgPool>>,
         id: Path<uuid::Uuid>,
-        params: Json<AddFileUrl>,
-    ) -> PutResponse {
+        url: Query<String>,
+        rev: Query<Option<String>>,
+        #[oai(name = "If-Match")] if_match: Header<Option<String>>,
+        auth: CustomSecurityScheme,
+    ) -> DeleteResponse {
         let pool = pool.clone();
-        info!("Updating file ({:?}) with params: {:?}", id.0, params);
-
-        let status = if let Some(status) = &params.status {
-            status.clone()
-        } else {
-            "pending".to_string()
-        };
-
-        let uploader = if let Some(uploader) = &params.uploader {
-            uploader.clone()
-        } else {
-            "biominer-admin".to_string()
-        };
-
-        let url = &params.url;
+        let user = auth.0;
+        info!(
+            "Deleting url ({:?}) from file ({:?}) by {}",
+            url.0, id.0, user.username
+        );
 
-        match util::which_protocol(url) {
-            Some(protocol) => protocol,
-            None => {
-                warn!("Cannot find the protocol {}", url);
-                // TODO: error message should be customized
-                return PutResponse::BadRequest(PlainText("Invalid url.".to_string()));
-            }
-        };
+        if let Err(failure) = writable_file(&pool, &id.0, &user).await {
+            return failure.into();
+        }
 
-        match File::add_url(&pool, &id.0, url, &uploader, &status).await {
-            Ok(()) => PutResponse::Ok(Json(MessageResponse {
-                msg: "Success".to_string(),
-            })),
-            Err(e) => PutResponse::BadRequest(PlainText(e.to_string())),
+        let rev = expected_rev(if_match.0, rev.0);
+        match File::delete_url(&pool, &id.0, &url.0, rev.as_deref()).await {
+            Ok(rev) => DeleteResponse::ok(Some(rev)),
+            Err(e) => DeleteResponse::from_error(e),
         }
     }
 
-    /// Call `/api/v1/files/:id/alias` to add alias for the file.
+    /// Call `/api/v1/files/:id/alias` to delete the alias of the file.
     #[oai(
         path = "/files/:id/alias",
-        method = "put",
+        method = "delete",
         tag = "FileApiTags::File",
-        operation_id = "addAliasToFile"
+        operation_id = "deleteAliasFromFile"
     )]
-    async fn add_alias(
+    async fn delete_alias(
         &self,
         pool: Data<&Arc<sqlx::PgPool>>,
         id: Path<uuid::Uuid>,
-        params: Json<AddFileAlias>,
-    ) -> PutResponse {
+        alias: Query<String>,
+        rev: Query<Option<String>>,
+        #[oai(name = "If-Match")] if_match: Header<Option<String>>,
+        auth: CustomSecurityScheme,
+    ) -> DeleteResponse {
         let pool = pool.clone();
-        info!("Updating file ({:?}) with params: {:?}", id.0, params);
+        let user = auth.0;
+        info!(
+            "Deleting alias ({:?}) from file ({:?}) by {}",
+            alias.0, id.0, user.username
+        );
 
-        match File::add_alias(&pool, &id.0, &params.alias).await {
-            Ok(()) => PutResponse::Ok(Json(MessageResponse {
-                msg: "Success".to_string(),
-            })),
-            Err(e) => PutResponse::BadRequest(PlainText(e.to_string())),
+        if let Err(failure) = writable_file(&pool, &id.0, &user).await {
+            return failure.into();
+        }
+
+        let rev = expected_rev(if_match.0, rev.0);
+        match File::delete_alias(&pool, &id.0, &alias.0, rev.as_deref()).await {
+            Ok(rev) => DeleteResponse::ok(Some(rev)),
+            Err(e) => DeleteResponse::from_error(e),
         }
     }
 
-    /// Call `/api/v1/files/:id/hash` to add hash for the file.
+    /// Call `/api/v1/files/:id/hash` to delete the hash of the file.
     #[oai(
         path = "/files/:id/hash",
-        method = "put",
+        method = "delete",
         tag = "FileApiTags::File",
-        operation_id = "addHashToFile"
+        operation_id = "deleteHashFromFile"
     )]
-    async fn add_hash(
+    async fn delete_hash(
         &self,
         pool: Data<&Arc<sqlx::PgPool>>,
         id: Path<uuid::Uuid>,
-        params: Json<AddFileHash>,
-    ) -> PutResponse {
+        hash: Query<String>,
+        rev: Query<Option<String>>,
+        #[oai(name = "If-Match")] if_match: Header<Option<String>>,
+        auth: CustomSecurityScheme,
+    ) -> DeleteResponse {
         let pool = pool.clone();
-        info!("Updating file ({:?}) with params: {:?}", id.0, params);
+        let user = auth.0;
+        info!(
+            "Deleting hash ({:?}) from file ({:?}) by {}",
+            hash.0, id.0, user.username
+        );
 
-        match File::add_hash(&pool, &id.0, &params.hash).await {
-            Ok(()) => PutResponse::Ok(Json(MessageResponse {
-                msg: "Success".to_string(),
-            })),
-            Err(e) => PutResponse::BadRequest(PlainText(e.to_string())),
+        if let Err(failure) = writable_file(&pool, &id.0, &user).await {
+            return failure.into();
+        }
+
+        let rev = expected_rev(if_match.0, rev.0);
+        match File::delete_hash(&pool, &id.0, &hash.0, rev.as_deref()).await {
+            Ok(rev) => DeleteResponse::ok(Some(rev)),
+            Err(e) => DeleteResponse::from_error(e),
         }
     }
 
-    /// Call `/api/v1/files/:id/tag` to add tag for the file.
+    /// Call `/api/v1/files/:id/tag` to delete the tag of the file.
     #[oai(
         path = "/files/:id/tag",
-        method = "put",
+        method = "delete",
         tag = "FileApiTags::File",
-        operation_id = "addTagToFile"
+        operation_id = "deleteTagFromFile"
     )]
-    async fn add_tag(
+    async fn delete_tag(
         &self,
         pool: Data<&Arc<sqlx::PgPool>>,
         id: Path<uuid::Uuid>,
-        params: Json<AddFileTag>,
-    ) -> PutResponse {
+        field_name: Query<String>,
+        rev: Query<Option<String>>,
+        #[oai(name = "If-Match")] if_match: Header<Option<String>>,
+        auth: CustomSecurityScheme,
+    ) -> DeleteResponse {
         let pool = pool.clone();
-        info!("Updating file ({:?}) with params: {:?}", id.0, params);
+        let user = auth.0;
+        info!(
+            "Deleting tag ({:?}) from file ({:?}) by {}",
+            field_name.0, id.0, user.username
+        );
 
-        match File::add_tag(&pool, &id.0, &params.field_name, &params.field_value).await {
-            Ok(()) => PutResponse::Ok(Json(MessageResponse {
-                msg: "Success".to_string(),
-            })),
-            Err(e) => PutResponse::BadRequest(PlainText(e.to_string())),
+        if let Err(failure) = writable_file(&pool, &id.0, &user).await {
+            return failure.into();
+        }
+
+        let rev = expected_rev(if_match.0, rev.0);
+        match File::delete_tag(&pool, &id.0, &field_name.0, rev.as_deref()).await {
+            Ok(rev) => DeleteResponse::ok(Some(rev)),
+            Err(e) => DeleteResponse::from_error(e),
         }
     }
 
@@ -814,7 +2665,11 @@ impl BioMinerIndexdApi {
         tag = "FileApiTags::Files",
         operation_id = "getTags"
     )]
-    async fn list_tags(&self, pool: Data<&Arc<sqlx::PgPool>>) -> GetTagsResponse {
+    async fn list_tags(
+        &self,
+        pool: Data<&Arc<sqlx::PgPool>>,
+        _auth: CustomSecurityScheme,
+    ) -> GetTagsResponse {
         let pool = pool.clone();
 
         match FileTagsResponse::get_fields(&pool).await {
@@ -830,7 +2685,11 @@ impl BioMinerIndexdApi {
         tag = "FileApiTags::Files",
         operation_id = "getFileStat"
     )]
-    async fn get_stat(&self, pool: Data<&Arc<sqlx::PgPool>>) -> GetStatResponse {
+    async fn get_stat(
+        &self,
+        pool: Data<&Arc<sqlx::PgPool>>,
+        _auth: CustomSecurityScheme,
+    ) -> GetStatResponse {
         let pool = pool.clone();
 
         match FileStatResponse::get_stat(&pool).await {
@@ -1141,18 +3000,104 @@ impl BioMinerIndexdApi {
 #[derive(Debug, Default, Clone, Serialize, Eq, PartialEq, Deserialize, Object)]
 pub struct CreateFile {
     pub filename: Option<String>,
-    pub uploader: Option<String>,
-    pub md5sum: String,
+    // Optional when the hashes have any other hash of the file.
+    pub md5sum: Option<String>,
     pub size: u64,
     pub alias: Option<String>,
     pub url: Option<String>,
+    // Other hashes of the file, such as sha256, crc32c or the etag of a multipart upload.
+    pub hashes: Option<Vec<AddFileHash>>,
+    // More urls of the file.
+    pub urls: Option<Vec<String>>,
+    pub tags: Option<Vec<AddFileTag>>,
+    // Such as 'admin,fudan-pgx', the file is public when acl and authz are null.
+    pub acl: Option<String>,
+    // The resource paths of the file, such as /programs/pgx/projects/quartet, a user granted a parent can access it.
+    pub authz: Option<Vec<String>>,
+}
+
+impl CreateFile {
+    fn validate(&self, repo_config: &RepoConfig) -> Result<(), String> {
+        if let Some(md5sum) = &self.md5sum {
+            util::check_hash(md5sum, Some("md5"))?;
+        }
+
+        if self.md5sum.is_none() && self.hashes.iter().flatten().next().is_none() {
+            return Err("At least one hash of the file is required.".to_string());
+        }
+
+        for url in self.url.iter().chain(self.urls.iter().flatten()) {
+            repo_config.check_url(url)?;
+        }
+
+        for hash in self.hashes.iter().flatten() {
+            util::check_hash(&hash.hash, hash.hash_type.as_deref())?;
+        }
+
+        for tag in self.tags.iter().flatten() {
+            if tag.field_name.is_empty() {
+                return Err("The field name of the tag cannot be empty.".to_string());
+            }
+        }
+
+        acl_of(self.acl.as_deref(), self.authz.as_deref())?;
+
+        Ok(())
+    }
+
+    /// The registration of the file, the uploader is the user who calls the api.
+    fn to_registration(
+        &self,
+        registry_id: &str,
+        default_filename: &str,
+        uploader: &str,
+    ) -> FileRegistration {
+        let filename = match &self.filename {
+            Some(filename) => filename,
+            None => default_filename,
+        };
+
+        let mut file = File::new(filename, self.size as i64, uploader, registry_id);
+        // The acl has been checked by `validate`.
+        file.acl = acl_of(self.acl.as_deref(), self.authz.as_deref()).unwrap_or_default();
+
+        // The hashes have been checked by `validate`.
+        let mut hashes: Vec<(String, String)> = self
+            .md5sum
+            .iter()
+            .map(|md5sum| ("md5".to_string(), md5sum.clone()))
+            .chain(self.hashes.iter().flatten().map(|hash| {
+                let hash_type = util::check_hash(&hash.hash, hash.hash_type.as_deref())
+                    .unwrap_or_default();
+                (hash_type.to_string(), hash.hash.clone())
+            }))
+            .collect();
+        let (hash_type, hash) = hashes.remove(0);
+
+        let mut registration = FileRegistration::new(file, &hash);
+        registration.hash_type = hash_type;
+        registration.hashes = hashes;
+        registration.urls = self
+            .url
+            .iter()
+            .chain(self.urls.iter().flatten())
+            .cloned()
+            .collect();
+        registration.aliases = self.alias.iter().cloned().collect();
+        registration.tags = self
+            .tags
+            .iter()
+            .flatten()
+            .map(|tag| (tag.field_name.clone(), tag.field_value.clone()))
+            .collect();
+        registration
+    }
 }
 
 #[derive(Debug, Default, Clone, Serialize, Eq, PartialEq, Deserialize, Object)]
 pub struct AddFileUrl {
     pub url: String,
     pub status: Option<String>,
-    pub uploader: Option<String>,
 }
 
 #[derive(Debug, Default, Clone, Serialize, Eq, PartialEq, Deserialize, Object)]
@@ -1163,6 +3108,16 @@ pub struct AddFileAlias {
 #[derive(Debug, Default, Clone, Serialize, Eq, PartialEq, Deserialize, Object)]
 pub struct AddFileHash {
     pub hash: String,
+    // md5, sha1, sha256, sha512, crc32, crc32c, crc64, crc64nvme or etag, it is detected when it is not given.
+    pub hash_type: Option<String>,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Eq, PartialEq, Deserialize, Object)]
+pub struct UpdateFileStatus {
+    // pending, processing, validated, failed or deleted
+    pub status: String,
+    #[oai(validator(max_length = 255))]
+    pub reason: Option<String>,
 }
 
 #[derive(Debug, Default, Clone, Serialize, Eq, PartialEq, Deserialize, Object)]
@@ -1171,11 +3126,73 @@ pub struct AddFileTag {
     pub field_value: String,
 }
 
+#[derive(Debug, Default, Clone, Serialize, Eq, PartialEq, Deserialize, Object)]
+pub struct UpdateFileAcl {
+    // Such as 'admin,fudan-pgx', the file becomes public when acl and authz are null.
+    pub acl: Option<String>,
+    // The resource paths of the file, such as /programs/pgx/projects/quartet.
+    pub authz: Option<Vec<String>>,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Eq, PartialEq, Deserialize, Object)]
+pub struct UpdateAclResponse {
+    // The number of the changed files.
+    pub updated: u64,
+}
+
 #[derive(Debug, Default, Clone, Serialize, Eq, PartialEq, Deserialize, Object)]
 pub struct GuidResponse {
     pub guid: String,
 }
 
+#[derive(Debug, Default, Clone, Serialize, Eq, PartialEq, Deserialize, Object)]
+pub struct FileVersionResponse {
+    pub guid: String,
+    pub baseid: String,
+    pub version: i32,
+}
+
+#[derive(Debug, Clone, Serialize, Eq, PartialEq, Deserialize, Object)]
+pub struct FileVersionsResponse {
+    pub baseid: String,
+    // The guid of the latest version which is not deleted.
+    pub latest: Option<String>,
+    pub versions: Vec<File>,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Eq, PartialEq, Deserialize, Object)]
+pub struct BulkCreateError {
+    // invalid, duplicated, conflict or failed
+    pub code: String,
+    pub msg: String,
+}
+
+impl BulkCreateError {
+    fn new(code: &str, msg: String) -> Self {
+        BulkCreateError {
+            code: code.to_string(),
+            msg,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Serialize, Eq, PartialEq, Deserialize, Object)]
+pub struct BulkCreateResult {
+    // The position of the file in the request, starting from 0.
+    pub index: u64,
+    pub guid: Option<String>,
+    // The guid is of the file which has registered the content, when `on_conflict` is `return_existing`.
+    pub existing: bool,
+    pub error: Option<BulkCreateError>,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Eq, PartialEq, Deserialize, Object)]
+pub struct BulkCreateResponse {
+    pub total: u64,
+    pub failed: u64,
+    pub results: Vec<BulkCreateResult>,
+}
+
 #[derive(Debug, Default, Clone, Serialize, Eq, PartialEq, Deserialize, Object)]
 pub struct MessageResponse {
     pub msg: String,
@@ -1185,7 +3202,211 @@ pub struct MessageResponse {
 pub struct SignResponse {
     pub sign: SignData,
     pub size: u64,
-    // At least one of the hashes exists.
+    // At least one of the hashes exists, the preferred hash type is listed first.
     pub hashes: Vec<Hash>,
+    // The preferred hash type which the file does not have.
+    pub missing_hash_type: Option<String>,
     pub filename: String,
+    // The protocol of the signed url, such as node.
+    pub which_repo: String,
+    // Why the url is chosen, such as the preference of the protocols and the skipped urls.
+    pub reason: String,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize, Object)]
+pub struct CreateUpload {
+    // minio or s3, the default is minio.
+    pub which_repo: Option<String>,
+    // The first bucket of the repo is used if it is not set.
+    pub bucket: Option<String>,
+    // Upload in parts if it is greater than 1, each part except the last one must be at least 5 MiB.
+    #[oai(validator(minimum(value = "1"), maximum(value = "10000")))]
+    pub parts: Option<u32>,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize, Object)]
+pub struct UploadPart {
+    pub part_number: u32,
+    pub sign: SignData,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize, Object)]
+pub struct UploadResponse {
+    pub which_repo: String,
+    pub bucket: String,
+    pub key: String,
+    // The url which is attached to the file after the upload is finalized.
+    pub url: String,
+    // Only for the multipart upload.
+    pub upload_id: Option<String>,
+    pub parts: Vec<UploadPart>,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize, Object)]
+pub struct CompleteUpload {
+    // The key given by the upload, the object is uploaded to the repo and the bucket of the upload.
+    pub key: String,
+    // Only for the multipart upload, the uploaded parts are listed by the object store.
+    pub upload_id: Option<String>,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize, Object)]
+pub struct SignFiles {
+    // Guids, bare uuids, hashes or aliases of the files.
+    #[oai(validator(max_items = 1000))]
+    pub ids: Vec<String>,
+    // node, gsa, s3, oss, minio, file or http, the url is chosen by the selection policy if it is not set.
+    pub which_repo: Option<String>,
+    // The region of the client, such as cn-hangzhou, the repos in the region are preferred.
+    pub region: Option<String>,
+    // The preferred hash type, such as sha256.
+    pub hash_type: Option<String>,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Eq, PartialEq, Deserialize, Object)]
+pub struct BulkSignError {
+    // not_found, unauthorized, gone, not_released or failed
+    pub code: String,
+    pub msg: String,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize, Object)]
+pub struct BulkSignResult {
+    // The id in the request.
+    pub id: String,
+    pub guid: Option<String>,
+    pub sign: Option<SignResponse>,
+    pub error: Option<BulkSignError>,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize, Object)]
+pub struct BulkSignResponse {
+    pub total: u64,
+    pub failed: u64,
+    pub results: Vec<BulkSignResult>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repo_config::{Sign, SignField};
+    use poem::{listener::TcpListener, middleware::AddData, EndpointExt, Route, Server};
+    use poem_openapi::OpenApiService;
+    use reqwest::header::{CONTENT_RANGE, RANGE};
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_download_local_file() {
+        let root = tempfile::tempdir().unwrap();
+        let path = root.path().join("data.txt");
+        std::fs::write(&path, b"hello indexd").unwrap();
+
+        let port = crate::get_free_port().unwrap();
+        let config = RepoConfig::read_config_data(&format!(
+            r#"{{"local": [{{"root": "{}", "secret": "secret", "download_url": "http://127.0.0.1:{}/api/v1/download/local"}}]}}"#,
+            root.path().display(),
+            port
+        ))
+        .unwrap();
+        let local = config.local.as_ref().unwrap()[0].clone();
+
+        let api_service = OpenApiService::new(BioMinerIndexdApi, "BioMiner Indexd", "v0.1.0");
+        let app = Route::new()
+            .nest("/", api_service)
+            .with(AddData::new(Arc::new(config)));
+        tokio::spawn(Server::new(TcpListener::bind(format!("127.0.0.1:{}", port))).run(app));
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        let client = reqwest::Client::new();
+        let sign = local.sign(&format!("file://{}", path.display())).await.unwrap();
+        let request = || sign.request(&client);
+
+        let response = request().send().await.unwrap();
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.text().await.unwrap(), "hello indexd");
+
+        let response = request().header(RANGE, "bytes=6-").send().await.unwrap();
+        assert_eq!(response.status(), 206);
+        assert_eq!(response.headers()[CONTENT_RANGE], "bytes 6-11/12");
+        assert_eq!(response.text().await.unwrap(), "indexd");
+
+        let response = request().header(RANGE, "bytes=12-").send().await.unwrap();
+        assert_eq!(response.status(), 416);
+
+        // The tampered link is rejected
+        let mut tampered = sign.clone();
+        tampered.params[2] = format!("signature={}", "0".repeat(64));
+        let response = tampered.request(&client).send().await.unwrap();
+        assert_eq!(response.status(), 403);
+    }
+
+    #[test]
+    fn test_acl_of() {
+        let authz = vec!["/programs/pgx/projects/quartet".to_string()];
+        assert_eq!(acl_of(None, None), Ok(None));
+        assert_eq!(acl_of(Some("admin, fudan-pgx"), None), Ok(Some("admin,fudan-pgx".to_string())));
+        assert_eq!(
+            acl_of(Some("admin,/programs/tcga"), Some(&authz)),
+            Ok(Some("admin,/programs/tcga,/programs/pgx/projects/quartet".to_string()))
+        );
+        assert!(acl_of(Some("admin,,fudan-pgx"), None).is_err());
+        assert!(acl_of(Some(""), None).is_err());
+        assert!(acl_of(None, Some(&["programs/pgx".to_string()])).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_writable_file() {
+        // The anonymous user is rejected before the file is read
+        let pool = sqlx::PgPool::connect_lazy("postgres://localhost/biominer").unwrap();
+        let id = uuid::Uuid::new_v4();
+        assert!(matches!(
+            writable_file(&pool, &id, &User::anonymous()).await,
+            Err(WriteFailure::Anonymous)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_delivery_of() {
+        let node = RepoConfig::read_config_data(
+            r#"{"node": [{
+                "download_url": "https://www.biosino.org/download/downloadByMember",
+                "account_name": "yjcyxky@163.com",
+                "member_id": "P34MVSHVGBDSLOQ6BGSAL4SFEN",
+                "project_id": "OEP003178"
+            }]}"#,
+        )
+        .unwrap()
+        .node
+        .unwrap()[0]
+            .clone();
+        let url = "node://yjcyxky@163.com/OEP003178/OEX015832/OES135568/OER249692/OED718095";
+        let sign = node.sign(url).await.unwrap();
+
+        let page = match delivery_of(&sign, false) {
+            Delivery::Form(page) => page,
+            delivery => panic!("Unexpected delivery: {:?}", delivery),
+        };
+        assert!(page.contains(
+            r#"<form method="post" action="https://www.biosino.org/download/downloadByMember">"#
+        ));
+        assert!(page.contains(r#"<input type="hidden" name="dataNo" value="OED718095">"#));
+        assert_eq!(delivery_of(&sign, true), Delivery::Proxy);
+
+        let mut sign = SignData {
+            baseurl: "https://example.com/a.txt".to_string(),
+            method: "GET".to_string(),
+            params: vec!["Expires=1".to_string(), "Signature=a%2Bb".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(
+            delivery_of(&sign, false),
+            Delivery::Redirect("https://example.com/a.txt?Expires=1&Signature=a%2Bb".to_string())
+        );
+
+        // A redirect cannot carry the headers
+        sign.headers.push(SignField::new("Authorization", "Bearer token"));
+        assert_eq!(delivery_of(&sign, false), Delivery::Proxy);
+
+        assert_eq!(escape_html(r#"<a href="x">&'"#), "&lt;a href=&quot;x&quot;&gt;&amp;&#39;");
+    }
 }
@@ -3,10 +3,12 @@ extern crate log;
 #[macro_use]
 extern crate lazy_static;
 
+use biominer_indexd::jobs::scrubber::{self, ScrubOptions};
+use biominer_indexd::jobs::url_checker::{self, CheckOptions};
 use biominer_indexd::model::dataset::init_cache;
 use biominer_indexd::{
     api, connect_db, get_free_port, get_local_postgres_url, init_logger, model, parse_db_url,
-    repo_config::RepoConfig, setup_local_postgres,
+    repo_config::SharedConfig, setup_local_postgres,
 };
 use dotenv::dotenv;
 use log::{error, LevelFilter};
@@ -73,7 +75,7 @@ struct Opt {
     #[structopt(name = "local-postgres", short = "l", long = "local-postgres")]
     local_postgres: bool,
 
-    /// The path of the repo config file.
+    /// The path of the repo config file, JSON, TOML (.toml) or YAML (.yaml/.yml). It is reloaded on SIGHUP.
     #[structopt(
         name = "config",
         short = "c",
@@ -82,6 +84,10 @@ struct Opt {
     )]
     config: String,
 
+    /// Check whether the repo config file is modified every N seconds and reload it. Disabled if not set.
+    #[structopt(name = "config-watch-interval", long = "config-watch-interval")]
+    config_watch_interval: Option<u64>,
+
     /// Pool size for database connection.
     #[structopt(name = "pool-size", short = "s", long = "pool-size")]
     pool_size: Option<u32>,
@@ -89,6 +95,19 @@ struct Opt {
     /// The path of the data directory.
     #[structopt(name = "data-dir", short = "D", long = "data-dir", required = true)]
     data_dir: String,
+
+    /// Check the urls in the background every N seconds, they are marked as validated or failed. Disabled if not set.
+    #[structopt(name = "check-urls-interval", long = "check-urls-interval")]
+    check_urls_interval: Option<u64>,
+
+    /// Scrub the files in the background every N seconds, the content is compared with the registered hashes. Disabled if not set.
+    #[structopt(name = "scrub-interval", long = "scrub-interval")]
+    scrub_interval: Option<u64>,
+
+    /// Serve the requests without a token as the anonymous user, who can only access the public files.
+    /// Without JWT_SECRET_KEY and JWT_CLIENT_ID, it is required and the tokens are not verified.
+    #[structopt(name = "allow-anonymous", long = "allow-anonymous")]
+    allow_anonymous: bool,
 }
 
 #[derive(RustEmbed)]
@@ -189,6 +208,18 @@ async fn main() -> Result<(), std::io::Error> {
     init_cache(&PathBuf::from(&args.data_dir)).expect("Failed to init cache...");
     env::set_var("BIOMINER_INDEXD_DATA_DIR", &args.data_dir);
 
+    // The tokens are verified with JWT_SECRET_KEY (HS256) or JWT_CLIENT_ID (RS256).
+    let jwt_enabled = ["JWT_SECRET_KEY", "JWT_CLIENT_ID"]
+        .iter()
+        .any(|key| env::var(key).is_ok_and(|v| !v.is_empty()));
+    if args.allow_anonymous {
+        warn!("The anonymous access is allowed, the requests without a token can access the public files.");
+    } else if !jwt_enabled {
+        error!("JWT_SECRET_KEY and JWT_CLIENT_ID are not set, please set one of them or use `--allow-anonymous` flag.");
+        std::process::exit(1);
+    }
+    api::auth::set_allow_anonymous(args.allow_anonymous);
+
     println!(
         "\n\t\t*** Launch biominer-indexd on {}:{}{} ***",
         host,
@@ -253,21 +284,47 @@ async fn main() -> Result<(), std::io::Error> {
 
     // Read the repo config file
     let config_path = args.config;
-    let indexd_repo_config = match RepoConfig::read_config(&config_path) {
+    let repo_config = match SharedConfig::load_from(&config_path) {
         Ok(v) => v,
-        Err(e) => {
-            error!("{}: {}", e, config_path);
+        Err(problems) => {
+            for problem in problems {
+                error!("{}: {}", config_path, problem);
+            }
             std::process::exit(1);
         }
     };
-    let arc_config = Arc::new(indexd_repo_config);
-    let shared_repo_config = AddData::new(arc_config.clone());
+    tokio::spawn(
+        repo_config
+            .clone()
+            .watch(args.config_watch_interval.map(|interval| Duration::from_secs(interval.max(1)))),
+    );
+
+    if let Some(interval) = args.check_urls_interval {
+        info!("Check the urls every {} seconds.", interval);
+        tokio::spawn(url_checker::run_periodically(
+            arc_pool.clone(),
+            Some(repo_config.clone()),
+            CheckOptions::default(),
+            Duration::from_secs(interval.max(1)),
+        ));
+    }
+
+    if let Some(interval) = args.scrub_interval {
+        info!("Scrub the files every {} seconds.", interval);
+        tokio::spawn(scrubber::run_periodically(
+            arc_pool.clone(),
+            Some(repo_config.clone()),
+            ScrubOptions::default(),
+            Duration::from_secs(interval.max(1)),
+        ));
+    }
 
     let config = model::datafile::Config::init_config(&arc_pool.clone()).await;
     info!("Initialize Config with `{:?}`", config);
     let shared_config = AddData::new(Arc::new(config));
 
-    let api_service = OpenApiService::new(api::route::BioMinerIndexdApi, "BioMiner Indexd", "v0.1.0")
+    let apis = (api::route::BioMinerIndexdApi, api::drs::DrsApi, api::gen3::Gen3Api);
+    let api_service = OpenApiService::new(apis, "BioMiner Indexd", "v0.1.0")
                                                                   .summary("A RESTful API for BioMiner Indexd")
                                                                   .description("BioMiner Indexd is a hash-based data indexing and tracking service providing globally unique identifiers.")
                                                                   .license("GNU AFFERO GENERAL PUBLIC LICENSE v3")
@@ -310,7 +367,16 @@ async fn main() -> Result<(), std::io::Error> {
         .with(shared_postgres_instance)
         .with(shared_rb)
         .with(shared_config)
-        .with(shared_repo_config);
+        // Every request gets the current repo config and keeps it even if the config is reloaded.
+        .around(move |ep, mut req| {
+            let config = repo_config.load();
+            async move {
+                req.extensions_mut().insert(config);
+                ep.call(req).await
+            }
+        })
+        // The gen3 clients do not encode the slash of the did
+        .before(api::gen3::encode_did);
 
     Server::new(TcpListener::bind(format!("{}:{}", host, port)))
         .run_with_graceful_shutdown(
@@ -0,0 +1,44 @@
+//! The background jobs over the registered files, the server runs them periodically and the cli runs them once.
+pub mod scrubber;
+pub mod url_checker;
+
+use crate::model::datafile::URL;
+use crate::repo_config::{which_protocol, RepoConfig, SignData};
+use std::path::PathBuf;
+
+/// Where the content of an url can be read from.
+#[derive(Debug, Clone)]
+pub enum Source {
+    Local(PathBuf),
+    Http(String),
+    // The signed request of an object store, such as s3, oss, minio, node or gsa.
+    Signed(SignData),
+}
+
+impl Source {
+    /// Resolve the url to a readable source, the urls of the object stores are signed with the repo config and the local files must be under a local repo.
+    pub async fn resolve(url: &str, config: Option<&RepoConfig>) -> Result<Source, String> {
+        let protocol = match which_protocol(url) {
+            Some(protocol) => protocol,
+            None => return Err(format!("The protocol of the url {} is not supported", url)),
+        };
+
+        match protocol {
+            // Only the files under the roots of the local repos are read
+            "file" => config
+                .ok_or_else(|| format!("No local repo is configured for the url {}", url))?
+                .local_file(url)
+                .map(Source::Local),
+            "http" => Ok(Source::Http(url.to_string())),
+            _ => {
+                let signer = config
+                    .and_then(|config| config.fetch_config(protocol, &URL::identity_of(url)))
+                    .ok_or_else(|| format!("No {} repo is configured for the url {}", protocol, url))?;
+
+                signer.sign(url).await.map(Source::Signed).map_err(|e| {
+                    format!("Cannot sign the url {} with the {} repo: {}", url, protocol, e)
+                })
+            }
+        }
+    }
+}
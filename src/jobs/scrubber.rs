@@ -0,0 +1,532 @@
+//! Read the content of the files again and compare it with the registered hashes, so the silent corruption in the storage is found.
+use super::Source;
+use crate::model::datafile::{File, FileStatus};
+use crate::repo_config::{RepoConfig, SharedConfig};
+use anyhow::Ok as AnyOk;
+use chrono::Utc;
+use log::{debug, info, warn};
+use openssl::hash::{Hasher, MessageDigest};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::AsyncReadExt;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+
+// The hash types computed from the content.
+pub const SCRUB_HASH_TYPES: [&str; 2] = ["md5", "sha256"];
+
+// Who changes the status of the mismatched files.
+const SCRUBBER: &str = "scrubber";
+
+#[derive(Debug, Clone)]
+pub struct ScrubOptions {
+    // The number of files fetched from the database at a time.
+    pub batch_size: i64,
+    // The number of files read at the same time.
+    pub concurrency: usize,
+    // The files scrubbed within the period (milliseconds) are skipped.
+    pub rescrub_after: i64,
+    // The timeout of reading one file, the large files need a long timeout.
+    pub timeout: Duration,
+    // Only scrub the files uploaded by the uploader.
+    pub uploader: Option<String>,
+}
+
+impl Default for ScrubOptions {
+    fn default() -> Self {
+        ScrubOptions {
+            batch_size: 100,
+            concurrency: 4,
+            rescrub_after: 30 * 24 * 60 * 60 * 1000,
+            timeout: Duration::from_secs(60 * 60),
+            uploader: None,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ScrubStats {
+    pub scrubbed: usize,
+    pub verified: usize,
+    pub mismatched: usize,
+    pub unreadable: usize,
+    // The number of the missing hashes added to the files.
+    pub added_hashes: usize,
+}
+
+impl std::fmt::Display for ScrubStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} files scrubbed, {} verified, {} mismatched, {} unreadable and {} hashes added",
+            self.scrubbed, self.verified, self.mismatched, self.unreadable, self.added_hashes
+        )
+    }
+}
+
+/// The hashes and the size computed from the content.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Digest {
+    pub md5: String,
+    pub sha256: String,
+    pub size: u64,
+}
+
+impl Digest {
+    pub fn get(&self, hash_type: &str) -> Option<&str> {
+        match hash_type {
+            "md5" => Some(&self.md5),
+            "sha256" => Some(&self.sha256),
+            _ => None,
+        }
+    }
+}
+
+struct Digester {
+    md5: Hasher,
+    sha256: Hasher,
+    size: u64,
+}
+
+impl Digester {
+    fn new() -> Result<Digester, String> {
+        let new = |digest| Hasher::new(digest).map_err(|e| e.to_string());
+        Ok(Digester {
+            md5: new(MessageDigest::md5())?,
+            sha256: new(MessageDigest::sha256())?,
+            size: 0,
+        })
+    }
+
+    fn update(&mut self, data: &[u8]) -> Result<(), String> {
+        self.md5.update(data).map_err(|e| e.to_string())?;
+        self.sha256.update(data).map_err(|e| e.to_string())?;
+        self.size += data.len() as u64;
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<Digest, String> {
+        Ok(Digest {
+            md5: hex::encode(self.md5.finish().map_err(|e| e.to_string())?),
+            sha256: hex::encode(self.sha256.finish().map_err(|e| e.to_string())?),
+            size: self.size,
+        })
+    }
+}
+
+/// Stream the content of the url and compute the hashes, the content is never kept in memory as a whole.
+///
+/// A local file is read only if it is under the root of a local repo, so the scrubber never hashes the other files of the server.
+pub async fn digest(
+    client: &reqwest::Client,
+    url: &str,
+    config: Option<&RepoConfig>,
+) -> Result<Digest, String> {
+    let mut digester = Digester::new()?;
+
+    let request = match Source::resolve(url, config).await? {
+        Source::Local(path) => {
+            let mut file = tokio::fs::File::open(&path)
+                .await
+                .map_err(|e| format!("Cannot open {}: {}", path.display(), e))?;
+            let mut buffer = vec![0; 1024 * 1024];
+            loop {
+                let n = file
+                    .read(&mut buffer)
+                    .await
+                    .map_err(|e| format!("Cannot read {}: {}", path.display(), e))?;
+                if n == 0 {
+                    break;
+                }
+                digester.update(&buffer[..n])?;
+            }
+            return digester.finish();
+        }
+        Source::Http(url) => client.get(url),
+        Source::Signed(sign) => sign.request(client),
+    };
+
+    let mut response = request
+        .send()
+        .await
+        .map_err(|e| format!("Cannot reach the url: {}", e))?;
+    if !response.status().is_success() {
+        return Err(format!("The server responds {}", response.status()));
+    }
+
+    while let Some(chunk) = response
+        .chunk()
+        .await
+        .map_err(|e| format!("Cannot read the content: {}", e))?
+    {
+        digester.update(&chunk)?;
+    }
+    digester.finish()
+}
+
+/// What the scrubber finds for a file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    // The content matches the registered hashes, with the hash types added to the file.
+    Verified(Vec<String>),
+    Mismatched(String),
+    Unreadable(String),
+}
+
+impl Outcome {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Outcome::Verified(_) => "verified",
+            Outcome::Mismatched(_) => "mismatched",
+            Outcome::Unreadable(_) => "unreadable",
+        }
+    }
+}
+
+/// Compare the digest with the registered size and hashes, return the mismatches.
+///
+/// Only the hash types in `SCRUB_HASH_TYPES` can be compared, the others are left as they are.
+pub fn compare(digest: &Digest, size: i64, hashes: &[(String, String)]) -> Vec<String> {
+    let mut mismatches = vec![];
+    if digest.size as i64 != size {
+        mismatches.push(format!("size {} != {}", digest.size, size));
+    }
+
+    for (hash_type, hash) in hashes.iter() {
+        if let Some(computed) = digest.get(hash_type) {
+            if !computed.eq_ignore_ascii_case(hash) {
+                mismatches.push(format!("{} {} != {}", hash_type, computed, hash));
+            }
+        }
+    }
+    mismatches
+}
+
+async fn scrub_file(
+    pool: &sqlx::PgPool,
+    client: &reqwest::Client,
+    config: Option<&RepoConfig>,
+    guid: &str,
+    size: i64,
+    status: &str,
+) -> Result<(Option<String>, Outcome), anyhow::Error> {
+    let urls = sqlx::query_scalar::<_, String>(
+        "SELECT url FROM biominer_indexd_url WHERE file = $1 ORDER BY status = 'failed', id",
+    )
+    .bind(guid)
+    .fetch_all(pool)
+    .await?;
+    let hashes = sqlx::query_as::<_, (String, String)>(
+        "SELECT hash_type, hash FROM biominer_indexd_hash WHERE file = $1",
+    )
+    .bind(guid)
+    .fetch_all(pool)
+    .await?;
+
+    // Read from the first readable url.
+    let mut reasons = vec![];
+    let mut found = None;
+    for url in urls.iter() {
+        match digest(client, url, config).await {
+            Ok(digest) => {
+                found = Some((url.clone(), digest));
+                break;
+            }
+            Err(reason) => reasons.push(reason),
+        }
+    }
+
+    let (url, digest) = match found {
+        Some(found) => found,
+        None => return AnyOk((None, Outcome::Unreadable(reasons.join("; ")))),
+    };
+
+    let id = guid
+        .rsplit('/')
+        .next()
+        .and_then(|id| uuid::Uuid::parse_str(id).ok())
+        .ok_or_else(|| anyhow::anyhow!("Invalid guid {}", guid))?;
+
+    let mismatches = compare(&digest, size, &hashes);
+    if !mismatches.is_empty() {
+        let reason = format!("The content of {} does not match: {}", url, mismatches.join(", "));
+        let reason: String = reason.chars().take(255).collect();
+        let failed = matches!(status.parse::<FileStatus>(), Ok(FileStatus::Failed));
+        if !failed {
+            File::update_status(pool, &id, FileStatus::Failed, SCRUBBER, Some(&reason), None)
+                .await?;
+        }
+        return AnyOk((Some(url), Outcome::Mismatched(reason)));
+    }
+
+    // The content is verified, so the hashes computed from it can be trusted.
+    let mut added = vec![];
+    for hash_type in SCRUB_HASH_TYPES.iter() {
+        if !hashes.iter().any(|(registered, _)| registered == hash_type) {
+            let hash = digest.get(hash_type).unwrap_or_default();
+            File::add_hash(pool, &id, hash, Some(hash_type), None).await?;
+            added.push(hash_type.to_string());
+        }
+    }
+    AnyOk((Some(url), Outcome::Verified(added)))
+}
+
+/// Scrub the files which are not deleted, the files which have not been scrubbed come first.
+///
+/// The result of each file is saved as soon as it is scrubbed, so an interrupted run resumes from the files left.
+/// A mismatched file is marked as failed, the unreadable files keep their status, the url checker reports them.
+pub async fn scrub(
+    pool: &sqlx::PgPool,
+    config: Option<Arc<RepoConfig>>,
+    options: &ScrubOptions,
+) -> Result<ScrubStats, anyhow::Error> {
+    let client = reqwest::Client::builder().timeout(options.timeout).build()?;
+    let threshold = Utc::now().timestamp_millis() - options.rescrub_after.max(0);
+    let semaphore = Arc::new(Semaphore::new(options.concurrency.max(1)));
+    let mut stats = ScrubStats::default();
+
+    loop {
+        let files = sqlx::query_as::<_, (String, i64, String)>(
+            "
+                SELECT f.guid, f.size, f.status FROM biominer_indexd_file f
+                    LEFT JOIN biominer_indexd_scrub s ON s.file = f.guid
+                    WHERE f.status != 'deleted' AND (s.scrubbed_at IS NULL OR s.scrubbed_at < $1)
+                        AND ($3::VARCHAR IS NULL OR f.uploader = $3)
+                    ORDER BY s.scrubbed_at NULLS FIRST, f.guid
+                    LIMIT $2;
+            ",
+        )
+        .bind(threshold)
+        .bind(options.batch_size.max(1))
+        .bind(&options.uploader)
+        .fetch_all(pool)
+        .await?;
+
+        if files.is_empty() {
+            break;
+        }
+
+        let mut tasks = JoinSet::new();
+        for (guid, size, status) in files {
+            let pool = pool.clone();
+            let client = client.clone();
+            let config = config.clone();
+            let semaphore = semaphore.clone();
+            tasks.spawn(async move {
+                let _permit = semaphore.acquire_owned().await;
+                let scrubbed =
+                    scrub_file(&pool, &client, config.as_deref(), &guid, size, &status).await;
+                (guid, scrubbed)
+            });
+        }
+
+        while let Some(joined) = tasks.join_next().await {
+            let (guid, scrubbed) = joined?;
+            let (url, outcome) = match scrubbed {
+                Ok(scrubbed) => scrubbed,
+                // Record the error, otherwise the file blocks the following runs.
+                Err(e) => (None, Outcome::Unreadable(format!("Cannot scrub the file: {}", e))),
+            };
+            debug!("Scrub the file {}: {:?}", guid, outcome);
+
+            let reason = match &outcome {
+                Outcome::Verified(added) if added.is_empty() => None,
+                Outcome::Verified(added) => Some(format!("Add the hashes: {}", added.join(", "))),
+                Outcome::Mismatched(reason) | Outcome::Unreadable(reason) => Some(reason.clone()),
+            };
+            sqlx::query(
+                "
+                    INSERT INTO biominer_indexd_scrub (file, url, result, reason, scrubbed_at)
+                        VALUES ($1, $2, $3, $4, $5)
+                        ON CONFLICT (file)
+                        DO UPDATE SET url = EXCLUDED.url, result = EXCLUDED.result,
+                            reason = EXCLUDED.reason, scrubbed_at = EXCLUDED.scrubbed_at;
+                ",
+            )
+            .bind(&guid)
+            .bind(url)
+            .bind(outcome.as_str())
+            .bind(reason.map(|r| r.chars().take(255).collect::<String>()))
+            .bind(Utc::now().timestamp_millis())
+            .execute(pool)
+            .await?;
+
+            stats.scrubbed += 1;
+            match outcome {
+                Outcome::Verified(added) => {
+                    stats.verified += 1;
+                    stats.added_hashes += added.len();
+                }
+                Outcome::Mismatched(reason) => {
+                    warn!("The file {} is corrupted: {}", guid, reason);
+                    stats.mismatched += 1;
+                }
+                Outcome::Unreadable(_) => stats.unreadable += 1,
+            }
+        }
+    }
+
+    info!("Scrub files: {}", stats);
+    AnyOk(stats)
+}
+
+/// Scrub the files every `interval`, a failed run is logged and resumed in the next round.
+pub async fn run_periodically(
+    pool: Arc<sqlx::PgPool>,
+    config: Option<SharedConfig>,
+    options: ScrubOptions,
+    interval: Duration,
+) {
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        ticker.tick().await;
+        // The config may have been reloaded since the last run.
+        let config = config.as_ref().map(|config| config.load());
+        if let Err(e) = scrub(&pool, config, &options).await {
+            warn!("Failed to scrub the files: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::datafile::tests::init;
+    use crate::model::datafile::Hash;
+    use poem::{handler, listener::TcpListener, Route, Server};
+
+    const MD5: &str = "7ef38920ff55ffe509c424d265c1df65";
+    const SHA256: &str = "be52a6afb1ea1320d4b45477b35bca02710cdfdc259c2b48c9301a64e7c11610";
+
+    #[handler]
+    fn data() -> &'static str {
+        "hello indexd"
+    }
+
+    fn local_config(root: &std::path::Path) -> RepoConfig {
+        RepoConfig::read_config_data(&format!(
+            r#"{{"local": [{{"root": "{}", "secret": "secret", "download_url": "http://localhost:3000/api/v1/download/local"}}]}}"#,
+            root.display()
+        ))
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_digest() {
+        let client = reqwest::Client::new();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data.txt");
+        std::fs::write(&path, b"hello indexd").unwrap();
+        let config = local_config(dir.path());
+
+        let url = format!("file://{}", path.display());
+        let digest = digest(&client, &url, Some(&config)).await.unwrap();
+        assert_eq!((digest.md5.as_str(), digest.sha256.as_str(), digest.size), (MD5, SHA256, 12));
+
+        let url = format!("file://{}", dir.path().join("missing.txt").display());
+        assert!(super::digest(&client, &url, Some(&config)).await.is_err());
+
+        // The files out of the local repos are never read
+        let url = format!("file://{}", path.display());
+        assert!(super::digest(&client, &url, None).await.is_err());
+        let other = tempfile::tempdir().unwrap();
+        let outside = other.path().join("data.txt");
+        std::fs::write(&outside, b"hello indexd").unwrap();
+        let url = format!("file://{}", outside.display());
+        assert!(super::digest(&client, &url, Some(&config)).await.is_err());
+
+        let port = crate::get_free_port().unwrap();
+        let app = Route::new().at("/data.txt", data);
+        tokio::spawn(Server::new(TcpListener::bind(format!("127.0.0.1:{}", port))).run(app));
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        let url = format!("http://127.0.0.1:{}/data.txt", port);
+        assert_eq!(super::digest(&client, &url, None).await.unwrap(), digest);
+        let url = format!("http://127.0.0.1:{}/missing.txt", port);
+        assert!(super::digest(&client, &url, None).await.is_err());
+    }
+
+    #[test]
+    fn test_compare() {
+        let digest = Digest {
+            md5: MD5.to_string(),
+            sha256: SHA256.to_string(),
+            size: 12,
+        };
+        let hashes = vec![
+            ("md5".to_string(), MD5.to_uppercase()),
+            ("crc32c".to_string(), "c1b2d3e4".to_string()),
+        ];
+        assert!(compare(&digest, 12, &hashes).is_empty());
+        assert_eq!(compare(&digest, 10, &hashes).len(), 1);
+
+        let hashes = vec![("sha256".to_string(), "0".repeat(64))];
+        let mismatches = compare(&digest, 12, &hashes);
+        assert_eq!(mismatches.len(), 1);
+        assert!(mismatches[0].starts_with("sha256"));
+    }
+
+    #[tokio::test]
+    async fn test_scrub() {
+        let (_postgres, pool) = init().await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data.txt");
+        std::fs::write(&path, b"hello indexd").unwrap();
+        let copy = dir.path().join("copy.txt");
+        std::fs::write(&copy, b"hello indexd").unwrap();
+        let other = tempfile::tempdir().unwrap();
+        let outside = other.path().join("data.txt");
+        std::fs::write(&outside, b"hello indexd").unwrap();
+        let config = Arc::new(local_config(dir.path()));
+
+        let uploader = format!("scrub_{}", &uuid::Uuid::new_v4().to_simple().to_string()[..8]);
+        let files = [
+            (format!("file://{}", path.display()), MD5.to_string()),
+            // The same content, but it is registered with another md5
+            (format!("file://{}", copy.display()), uuid::Uuid::new_v4().to_simple().to_string()),
+            (format!("file://{}", dir.path().join("missing.txt").display()), MD5.to_string()),
+            // Out of the local repos, it is never read
+            (format!("file://{}", outside.display()), MD5.to_string()),
+        ];
+        let mut ids = vec![];
+        for (url, hash) in files.iter() {
+            let mut file = File::new("test_scrub.txt", 12, &uploader, "fudan-pgx");
+            file.add(&pool, hash, Some(url), None).await.unwrap();
+            ids.push(uuid::Uuid::parse_str(file.guid.rsplit('/').next().unwrap()).unwrap());
+        }
+
+        let options = ScrubOptions {
+            uploader: Some(uploader.clone()),
+            concurrency: 2,
+            ..Default::default()
+        };
+        let stats = scrub(&pool, Some(config.clone()), &options).await.unwrap();
+        assert_eq!(
+            (stats.scrubbed, stats.verified, stats.mismatched, stats.unreadable, stats.added_hashes),
+            (4, 1, 1, 2, 1)
+        );
+
+        // The missing sha256 is added to the verified file
+        let file = File::get_file(&pool, &ids[0]).await.unwrap();
+        let hashes: Vec<Hash> = serde_json::from_value(file.hashes.unwrap()).unwrap();
+        assert!(hashes.iter().any(|h| h.hash_type == "sha256" && h.hash == SHA256));
+        assert_ne!(file.status, "failed");
+
+        let file = File::get_file(&pool, &ids[1]).await.unwrap();
+        assert_eq!(file.status, "failed");
+        let history = File::get_status_history(&pool, &ids[1]).await.unwrap();
+        assert_eq!(history.last().unwrap().actor, "scrubber");
+
+        let file = File::get_file(&pool, &ids[2]).await.unwrap();
+        assert_ne!(file.status, "failed");
+
+        let file = File::get_file(&pool, &ids[3]).await.unwrap();
+        let hashes: Vec<Hash> = serde_json::from_value(file.hashes.unwrap()).unwrap();
+        assert!(hashes.iter().all(|h| h.hash_type != "sha256"));
+
+        // The progress is kept, the scrubbed files are not read again
+        let stats = scrub(&pool, Some(config.clone()), &options).await.unwrap();
+        assert_eq!(stats.scrubbed, 0);
+    }
+}
@@ -0,0 +1,361 @@
+//! Probe the registered urls and mark them as validated or failed, so the dead links are found before the users.
+use super::Source;
+use crate::model::datafile::FileStatus;
+use crate::repo_config::{RepoConfig, SharedConfig};
+use anyhow::Ok as AnyOk;
+use chrono::Utc;
+use log::{debug, info, warn};
+use reqwest::header::{CONTENT_LENGTH, CONTENT_RANGE, RANGE};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+
+#[derive(Debug, Clone)]
+pub struct CheckOptions {
+    // The number of urls fetched from the database at a time.
+    pub batch_size: i64,
+    // The number of urls probed at the same time.
+    pub concurrency: usize,
+    // The urls checked within the period (milliseconds) are skipped.
+    pub recheck_after: i64,
+    pub timeout: Duration,
+    // Only check the urls of the files uploaded by the uploader.
+    pub uploader: Option<String>,
+}
+
+impl Default for CheckOptions {
+    fn default() -> Self {
+        CheckOptions {
+            batch_size: 100,
+            concurrency: 8,
+            recheck_after: 24 * 60 * 60 * 1000,
+            timeout: Duration::from_secs(30),
+            uploader: None,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct CheckStats {
+    pub checked: usize,
+    pub validated: usize,
+    pub failed: usize,
+    pub skipped: usize,
+}
+
+impl std::fmt::Display for CheckStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} urls checked, {} validated, {} failed and {} skipped",
+            self.checked, self.validated, self.failed, self.skipped
+        )
+    }
+}
+
+/// What is found at the url.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Probe {
+    // The content is reachable, with its size if the size is reported.
+    Reachable(Option<u64>),
+    Unreachable(String),
+    // The url cannot be probed here, e.g. no repo is configured for it.
+    Skipped(String),
+}
+
+impl Probe {
+    /// Judge the url against the registered size of the file, return the new status (`None` keeps the status) and the reason.
+    pub fn judge(&self, size: i64) -> (Option<FileStatus>, String) {
+        match self {
+            Probe::Reachable(Some(found)) if *found as i64 != size => (
+                Some(FileStatus::Failed),
+                format!(
+                    "The size {} does not match the registered size {}",
+                    found, size
+                ),
+            ),
+            Probe::Reachable(Some(_)) => (
+                Some(FileStatus::Validated),
+                "The url is reachable and the size matches".to_string(),
+            ),
+            Probe::Reachable(None) => (
+                Some(FileStatus::Validated),
+                "The url is reachable, but the size is not reported".to_string(),
+            ),
+            Probe::Unreachable(reason) => (Some(FileStatus::Failed), reason.clone()),
+            Probe::Skipped(reason) => (None, reason.clone()),
+        }
+    }
+}
+
+/// Probe the url without downloading the content: stat a local file, send a HEAD request to a http url, or fetch the first byte with the signed request of an object store.
+pub async fn probe(client: &reqwest::Client, url: &str, config: Option<&RepoConfig>) -> Probe {
+    let source = match Source::resolve(url, config).await {
+        Ok(source) => source,
+        Err(reason) => return Probe::Skipped(reason),
+    };
+
+    match source {
+        Source::Local(path) => match tokio::fs::metadata(&path).await {
+            Ok(meta) if meta.is_file() => Probe::Reachable(Some(meta.len())),
+            Ok(_) => Probe::Unreachable(format!("{} is not a file", path.display())),
+            Err(e) => Probe::Unreachable(format!("Cannot stat {}: {}", path.display(), e)),
+        },
+        Source::Http(url) => send(client.head(url)).await,
+        Source::Signed(sign) => {
+            let request = sign.request(client);
+            // The signed url may only be valid for GET, so fetch the first byte and read the total size from the Content-Range.
+            let request = if sign.method.eq_ignore_ascii_case("GET") {
+                request.header(RANGE, "bytes=0-0")
+            } else {
+                request
+            };
+            send(request).await
+        }
+    }
+}
+
+async fn send(request: reqwest::RequestBuilder) -> Probe {
+    match request.send().await {
+        Ok(response) if response.status().is_success() => {
+            let header = |name| {
+                response
+                    .headers()
+                    .get(name)
+                    .and_then(|value| value.to_str().ok())
+            };
+
+            // Content-Range: bytes 0-0/1234
+            let size = match header(CONTENT_RANGE) {
+                Some(range) => range.rsplit('/').next().and_then(|total| total.parse().ok()),
+                None => header(CONTENT_LENGTH).and_then(|length| length.parse().ok()),
+            };
+            Probe::Reachable(size)
+        }
+        Ok(response) => Probe::Unreachable(format!("The server responds {}", response.status())),
+        Err(e) => Probe::Unreachable(format!("Cannot reach the url: {}", e)),
+    }
+}
+
+/// Check the urls of the files which are not deleted, the urls which have not been checked come first.
+///
+/// The urls are probed concurrently, and each of them gets the status (validated or failed), the reason and the time of the check.
+/// The skipped urls keep their status, but they are also marked as checked, so they are not probed again in the same run.
+pub async fn check_urls(
+    pool: &sqlx::PgPool,
+    config: Option<Arc<RepoConfig>>,
+    options: &CheckOptions,
+) -> Result<CheckStats, anyhow::Error> {
+    let client = reqwest::Client::builder().timeout(options.timeout).build()?;
+    let threshold = Utc::now().timestamp_millis() - options.recheck_after.max(0);
+    let semaphore = Arc::new(Semaphore::new(options.concurrency.max(1)));
+    let mut stats = CheckStats::default();
+
+    loop {
+        let urls = sqlx::query_as::<_, (i64, String, i64)>(
+            "
+                SELECT u.id, u.url, f.size FROM biominer_indexd_url u
+                    JOIN biominer_indexd_file f ON f.guid = u.file
+                    WHERE f.status != 'deleted' AND (u.checked_at IS NULL OR u.checked_at < $1)
+                        AND ($3::VARCHAR IS NULL OR f.uploader = $3)
+                    ORDER BY u.checked_at NULLS FIRST, u.id
+                    LIMIT $2;
+            ",
+        )
+        .bind(threshold)
+        .bind(options.batch_size.max(1))
+        .bind(&options.uploader)
+        .fetch_all(pool)
+        .await?;
+
+        if urls.is_empty() {
+            break;
+        }
+
+        let mut tasks = JoinSet::new();
+        for (id, url, size) in urls {
+            let client = client.clone();
+            let config = config.clone();
+            let semaphore = semaphore.clone();
+            tasks.spawn(async move {
+                let _permit = semaphore.acquire_owned().await;
+                let probe = probe(&client, &url, config.as_deref()).await;
+                (id, url, size, probe)
+            });
+        }
+
+        while let Some(joined) = tasks.join_next().await {
+            let (id, url, size, probe) = joined?;
+            let (status, reason) = probe.judge(size);
+            debug!("Check the url {}: {:?}, {}", url, status, reason);
+
+            sqlx::query(
+                "UPDATE biominer_indexd_url SET status = COALESCE($2, status), reason = $3, checked_at = $4 WHERE id = $1",
+            )
+            .bind(id)
+            .bind(status.map(|s| s.as_str()))
+            .bind(reason.chars().take(255).collect::<String>())
+            .bind(Utc::now().timestamp_millis())
+            .execute(pool)
+            .await?;
+
+            stats.checked += 1;
+            match status {
+                Some(FileStatus::Validated) => stats.validated += 1,
+                Some(_) => {
+                    warn!("The url {} is failed: {}", url, reason);
+                    stats.failed += 1
+                }
+                None => stats.skipped += 1,
+            }
+        }
+    }
+
+    info!("Check urls: {}", stats);
+    AnyOk(stats)
+}
+
+/// Check the urls every `interval`, a failed run is logged and retried in the next round.
+pub async fn run_periodically(
+    pool: Arc<sqlx::PgPool>,
+    config: Option<SharedConfig>,
+    options: CheckOptions,
+    interval: Duration,
+) {
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        ticker.tick().await;
+        // The config may have been reloaded since the last run.
+        let config = config.as_ref().map(|config| config.load());
+        if let Err(e) = check_urls(&pool, config, &options).await {
+            warn!("Failed to check the urls: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::datafile::tests::init;
+    use crate::model::datafile::{File, URL};
+    use poem::{handler, listener::TcpListener, Route, Server};
+    use std::io::Write;
+
+    fn local_config(root: &std::path::Path) -> RepoConfig {
+        RepoConfig::read_config_data(&format!(
+            r#"{{"local": [{{"root": "{}", "secret": "secret", "download_url": "http://localhost:3000/api/v1/download/local"}}]}}"#,
+            root.display()
+        ))
+        .unwrap()
+    }
+
+    #[handler]
+    fn data() -> &'static str {
+        "hello indexd"
+    }
+
+    #[test]
+    fn test_judge() {
+        let (status, _) = Probe::Reachable(Some(12)).judge(12);
+        assert_eq!(status, Some(FileStatus::Validated));
+        let (status, reason) = Probe::Reachable(Some(10)).judge(12);
+        assert_eq!(status, Some(FileStatus::Failed));
+        assert!(reason.contains("does not match"));
+        let (status, _) = Probe::Skipped("no repo".to_string()).judge(12);
+        assert_eq!(status, None);
+    }
+
+    #[tokio::test]
+    async fn test_probe_file() {
+        let client = reqwest::Client::new();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data.txt");
+        std::fs::File::create(&path)
+            .unwrap()
+            .write_all(b"hello indexd")
+            .unwrap();
+
+        let config = local_config(dir.path());
+
+        let url = format!("file://{}", path.display());
+        assert_eq!(probe(&client, &url, Some(&config)).await, Probe::Reachable(Some(12)));
+
+        let url = format!("file://{}", dir.path().join("missing.txt").display());
+        assert!(matches!(probe(&client, &url, Some(&config)).await, Probe::Unreachable(_)));
+
+        // The files out of the local repos are never read
+        assert!(matches!(probe(&client, &url, None).await, Probe::Skipped(_)));
+        let other = tempfile::tempdir().unwrap();
+        let outside = other.path().join("secret.txt");
+        std::fs::write(&outside, b"secret").unwrap();
+        let url = format!("file://{}", outside.display());
+        assert!(matches!(probe(&client, &url, Some(&config)).await, Probe::Skipped(_)));
+        std::os::unix::fs::symlink(&outside, dir.path().join("link.txt")).unwrap();
+        let url = format!("file://{}", dir.path().join("link.txt").display());
+        assert!(matches!(probe(&client, &url, Some(&config)).await, Probe::Skipped(_)));
+
+        // No repo is configured for the object store
+        let probed = probe(&client, "s3://test-bucket/data.txt", None).await;
+        assert!(matches!(probed, Probe::Skipped(_)));
+    }
+
+    #[tokio::test]
+    async fn test_probe_http() {
+        let port = crate::get_free_port().unwrap();
+        let app = Route::new().at("/data.txt", data);
+        tokio::spawn(Server::new(TcpListener::bind(format!("127.0.0.1:{}", port))).run(app));
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        let client = reqwest::Client::new();
+        let url = format!("http://127.0.0.1:{}/data.txt", port);
+        assert_eq!(probe(&client, &url, None).await, Probe::Reachable(Some(12)));
+
+        let url = format!("http://127.0.0.1:{}/missing.txt", port);
+        assert!(matches!(probe(&client, &url, None).await, Probe::Unreachable(_)));
+    }
+
+    #[tokio::test]
+    async fn test_check_urls() {
+        let (_postgres, pool) = init().await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data.txt");
+        std::fs::write(&path, b"hello indexd").unwrap();
+        let config = Arc::new(local_config(dir.path()));
+
+        let uploader = format!("check_{}", &uuid::Uuid::new_v4().to_simple().to_string()[..8]);
+        let urls = [
+            format!("file://{}", path.display()),
+            format!("file://{}", dir.path().join("missing.txt").display()),
+            format!("s3://test-check/{}.txt", uploader),
+        ];
+        let mut guids = vec![];
+        for url in urls.iter() {
+            let mut file = File::new("test_check.txt", 12, &uploader, "fudan-pgx");
+            let hash = uuid::Uuid::new_v4().to_simple().to_string();
+            file.add(&pool, &hash, Some(url), None).await.unwrap();
+            guids.push(file.guid);
+        }
+
+        let options = CheckOptions {
+            uploader: Some(uploader.clone()),
+            ..Default::default()
+        };
+        let stats = check_urls(&pool, Some(config.clone()), &options).await.unwrap();
+        assert_eq!((stats.checked, stats.validated, stats.failed, stats.skipped), (3, 1, 1, 1));
+
+        let mut statuses = vec![];
+        for guid in guids.iter() {
+            let file = File::query_file(&pool, "guid", guid).await.unwrap();
+            let urls: Vec<URL> = serde_json::from_value(file.urls.unwrap()).unwrap();
+            assert!(urls[0].checked_at.is_some());
+            statuses.push(urls[0].status.clone());
+        }
+        assert_eq!(statuses, vec!["validated", "failed", "pending"]);
+
+        // The urls checked recently are not checked again
+        let stats = check_urls(&pool, Some(config.clone()), &options).await.unwrap();
+        assert_eq!(stats.checked, 0);
+    }
+}
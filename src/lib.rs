@@ -2,6 +2,7 @@
 const DB_VERSION: &str = "2.8.3";
 
 pub mod api;
+pub mod jobs;
 pub mod model;
 pub mod query_builder;
 pub mod repo_config;
@@ -1,20 +1,40 @@
-use crate::model::util::load_tsv;
+use crate::model::util::{load_csv, load_tsv};
 use crate::query_builder::where_builder::ComposeQuery;
+use crate::repo_config;
 use crate::util::{self, get_delimiter, parse_csv_error, ValidationError};
 use anyhow::{Error as AnyError, Ok as AnyOk};
 use chrono::{self, Utc};
 use csv;
+use custom_error::custom_error;
 use log::{debug, info, warn};
 use poem_openapi::Object;
 use regex::Regex;
 use serde::{Deserialize, Serialize};
 use sqlx;
-use sqlx::Row;
+use sqlx::{Acquire, Row};
+use std::collections::{HashMap, HashSet};
 use std::error::Error;
 use std::{option::Option, path::PathBuf};
 use uuid;
 use validator::Validate;
 
+custom_error! {pub FileError
+    NotFound{guid: String} = "Cannot find the file with guid {guid}",
+    Deleted{guid: String} = "The file {guid} has been deleted",
+    NoSuchItem{guid: String, item: String} = "Cannot find the {item} in the file {guid}",
+    RevMismatch{guid: String, rev: String, current: String} = "The file {guid} has been modified (current rev: {current}), the rev {rev} is stale",
+    Registered{item: String, guid: String} = "The {item} has been registered by the file {guid}",
+    InvalidTransition{guid: String, from: String, to: String} = "The status of the file {guid} cannot be changed from {from} to {to}",
+}
+
+/// Whether the error is a unique violation of the database, i.e. the value has been registered.
+fn is_unique_violation(e: &anyhow::Error) -> bool {
+    matches!(
+        e.downcast_ref::<sqlx::Error>().and_then(|e| e.as_database_error()),
+        Some(db) if db.code().as_deref() == Some("23505")
+    )
+}
+
 pub trait CheckData {
     fn check_csv_is_valid(filepath: &PathBuf) -> Vec<Box<dyn Error>>;
 
@@ -221,6 +241,29 @@ pub struct QueryFilter<'a> {
     pub url: Option<&'a str>,
     pub field_name: Option<&'a str>,
     pub field_value: Option<&'a str>,
+    // Milliseconds since epoch, created_after <= created_at < created_before.
+    pub created_after: Option<i64>,
+    pub created_before: Option<i64>,
+    // Only the files whose guid is after it, for paging by the last guid of the previous page.
+    pub guid_after: Option<&'a str>,
+    // The comma separated groups of the caller, the private files which none of them grants are hidden.
+    pub auth_groups: Option<&'a str>,
+    // Keep the deleted files when no status is given, they are hidden by default.
+    pub include_deleted: bool,
+}
+
+/// Whether the file `f` is public or granted to one of the comma separated groups in the param.
+/// The same as `util::has_permission`, a resource path grants itself and its children.
+fn granted_clause(param_index: usize) -> String {
+    format!(
+        "(f.acl IS NULL OR EXISTS (
+            SELECT 1 FROM regexp_split_to_table(trim(f.acl), '\\s*,\\s*') AS a(item),
+                regexp_split_to_table(trim(${}), '\\s*,\\s*') AS g(grp)
+                WHERE g.grp != '' AND (a.item = g.grp
+                    OR (left(g.grp, 1) = '/' AND position(rtrim(g.grp, '/') || '/' IN a.item) = 1))
+        ))",
+        param_index
+    )
 }
 
 impl<'a> QueryFilter<'a> {
@@ -271,9 +314,39 @@ impl<'a> QueryFilter<'a> {
             } else {
                 Some(tag_field_value)
             },
+            created_after: None,
+            created_before: None,
+            guid_after: None,
+            auth_groups: None,
+            include_deleted: false,
         }
     }
 
+    /// Only keep the files created in the range, both ends are milliseconds since epoch.
+    pub fn with_created_range(mut self, after: Option<i64>, before: Option<i64>) -> Self {
+        self.created_after = after;
+        self.created_before = before;
+        self
+    }
+
+    /// Only keep the files whose guid is after the given one, the files are ordered by guid.
+    pub fn with_guid_after(mut self, guid: Option<&'a str>) -> Self {
+        self.guid_after = guid;
+        self
+    }
+
+    /// Only keep the public files and the private files granted to one of the groups, None keeps all the files.
+    pub fn with_auth_groups(mut self, auth_groups: Option<&'a str>) -> Self {
+        self.auth_groups = auth_groups;
+        self
+    }
+
+    /// Keep the deleted files too, e.g. to remove all the files of an uploader.
+    pub fn with_deleted(mut self, include_deleted: bool) -> Self {
+        self.include_deleted = include_deleted;
+        self
+    }
+
     /// 拼接 SQL WHERE 条件，并返回参数列表
     pub fn to_sql_and_params(&self) -> (String, Vec<String>) {
         let mut clauses = vec![];
@@ -300,9 +373,19 @@ impl<'a> QueryFilter<'a> {
             "f.filename ILIKE ${}",
             Some(&format!("%{}%", self.filename.unwrap()))
         );
+        push_clause!(self.guid_after, "f.guid > ${}", self.guid_after);
         push_clause!(self.baseid, "f.baseid = ${}", self.baseid);
         push_clause!(self.status, "f.status = ${}", self.status);
+        // The deleted files are kept as tombstones, so hide them unless they are asked for.
+        if self.status.is_none() && !self.include_deleted {
+            clauses.push("f.status != 'deleted'".to_string());
+        }
         push_clause!(self.uploader, "f.uploader = ${}", self.uploader);
+        if let Some(auth_groups) = self.auth_groups {
+            clauses.push(granted_clause(param_index));
+            params.push(auth_groups.to_string());
+            param_index += 1;
+        }
         push_clause!(
             self.hash,
             "EXISTS (SELECT 1 FROM biominer_indexd_hash h WHERE h.file = f.guid AND h.hash = ${})",
@@ -319,6 +402,17 @@ impl<'a> QueryFilter<'a> {
             self.url
         );
 
+        push_clause!(
+            self.created_after,
+            "f.created_at >= ${}::BIGINT",
+            self.created_after
+        );
+        push_clause!(
+            self.created_before,
+            "f.created_at < ${}::BIGINT",
+            self.created_before
+        );
+
         if let (Some(fn_), Some(fv)) = (self.field_name, self.field_value) {
             clauses.push(format!(
                 "EXISTS (SELECT 1 FROM biominer_indexd_tag t WHERE t.file = f.guid AND t.field_name = ${} AND t.field_value = ${})",
@@ -345,6 +439,26 @@ pub async fn fetch_guid_page(
     filter: &QueryFilter<'_>,
     page_no: u64,
     page_size: u64,
+) -> Result<(Vec<String>, i64), anyhow::Error> {
+    fetch_guids(pool, filter, page_no, page_size, false).await
+}
+
+/// Same as fetch_guid_page, but the guids are ordered, so the pages are stable between the calls.
+pub async fn fetch_sorted_guid_page(
+    pool: &sqlx::PgPool,
+    filter: &QueryFilter<'_>,
+    page_no: u64,
+    page_size: u64,
+) -> Result<(Vec<String>, i64), anyhow::Error> {
+    fetch_guids(pool, filter, page_no, page_size, true).await
+}
+
+async fn fetch_guids(
+    pool: &sqlx::PgPool,
+    filter: &QueryFilter<'_>,
+    page_no: u64,
+    page_size: u64,
+    sorted: bool,
 ) -> Result<(Vec<String>, i64), anyhow::Error> {
     let offset = (page_no - 1) * page_size;
     let (where_clause, params) = filter.to_sql_and_params();
@@ -354,8 +468,9 @@ pub async fn fetch_guid_page(
 
     let count_sql = format!("SELECT COUNT(*) {}", base_sql);
     let guid_sql = format!(
-        "SELECT f.guid {} OFFSET ${} LIMIT ${}",
+        "SELECT f.guid {} {}OFFSET ${} LIMIT ${}",
         base_sql,
+        if sorted { "ORDER BY f.guid " } else { "" },
         num_params + 1,
         num_params + 2
     );
@@ -444,6 +559,141 @@ pub async fn load_files_by_guids(
     Ok(result)
 }
 
+/// Fetch all the files matching the filter with their urls, hashes, aliases and tags, ordered by guid.
+pub async fn fetch_all_files(
+    pool: &sqlx::PgPool,
+    filter: &QueryFilter<'_>,
+    page_size: u64,
+) -> Result<Vec<File>, anyhow::Error> {
+    let mut files = vec![];
+    let mut page_no = 1;
+    loop {
+        let (guids, total) = fetch_sorted_guid_page(pool, filter, page_no, page_size).await?;
+        let mut page = load_files_by_guids(pool, &guids, true, true, true).await?;
+        page.sort_by(|a, b| a.guid.cmp(&b.guid));
+        files.extend(page);
+
+        debug!("Fetched {} of {} files.", files.len(), total);
+        if guids.is_empty() || files.len() as i64 >= total {
+            break;
+        }
+        page_no += 1;
+    }
+
+    AnyOk(files)
+}
+
+/// The aliases of the active files ordered by name, the page starts after the given alias.
+/// The aliases of the private files are only listed for the groups granted them, see `QueryFilter::with_auth_groups`.
+pub async fn fetch_aliases(
+    pool: &sqlx::PgPool,
+    start: Option<&str>,
+    limit: u64,
+    auth_groups: Option<&str>,
+) -> Result<Vec<String>, anyhow::Error> {
+    let sql = format!(
+        "
+            SELECT a.name FROM biominer_indexd_alias a
+                JOIN biominer_indexd_file f ON f.guid = a.file
+                WHERE f.status != 'deleted' AND ($1::VARCHAR IS NULL OR a.name > $1)
+                    AND ($3::VARCHAR IS NULL OR {})
+                ORDER BY a.name
+                LIMIT $2;
+        ",
+        granted_clause(3)
+    );
+    let aliases = sqlx::query_scalar::<_, String>(&sql)
+        .bind(start)
+        .bind(limit as i64)
+        .bind(auth_groups)
+        .fetch_all(pool)
+        .await?;
+
+    AnyOk(aliases)
+}
+
+/// Remove all the files matching the filter with their urls, hashes, aliases, tags, tombstones, status history, scrub results and uploads in one transaction, return the number of the removed files.
+///
+/// NOTICE: Unlike `File::purge_file`, no tombstone is left, so the guids can be imported again. It is used to roll back a bad import,
+/// so the filter should keep the deleted files (see `QueryFilter::with_deleted`). The tombstones of the purged files have no file record, so they can't be matched by the filter.
+pub async fn purge_files(pool: &sqlx::PgPool, filter: &QueryFilter<'_>) -> Result<u64, anyhow::Error> {
+    let (where_clause, params) = filter.to_sql_and_params();
+    let guid_sql = format!(
+        "SELECT f.guid FROM biominer_indexd_file f WHERE {} FOR UPDATE",
+        where_clause
+    );
+
+    let mut tx = pool.begin().await?;
+    let mut guid_query = sqlx::query_scalar::<_, String>(&guid_sql);
+    for val in params.iter() {
+        guid_query = guid_query.bind(val);
+    }
+    let guids = guid_query.fetch_all(&mut tx).await?;
+
+    for table in [
+        "biominer_indexd_url",
+        "biominer_indexd_hash",
+        "biominer_indexd_alias",
+        "biominer_indexd_tag",
+        "biominer_indexd_status_history",
+        "biominer_indexd_scrub",
+        "biominer_indexd_upload",
+    ] {
+        let sql = format!("DELETE FROM {} WHERE file = ANY($1)", table);
+        sqlx::query(&sql).bind(&guids).execute(&mut tx).await?;
+    }
+
+    sqlx::query("DELETE FROM biominer_indexd_tombstone WHERE guid = ANY($1)")
+        .bind(&guids)
+        .execute(&mut tx)
+        .await?;
+
+    sqlx::query("DELETE FROM biominer_indexd_file WHERE guid = ANY($1)")
+        .bind(&guids)
+        .execute(&mut tx)
+        .await?;
+    tx.commit().await?;
+
+    info!("Purged {} files.", guids.len());
+    AnyOk(guids.len() as u64)
+}
+
+/// Set the acl of all the files matching the filter in one statement, return the number of the changed files.
+///
+/// The deleted files are kept as they are, every changed file gets a new rev. None makes the files public.
+pub async fn update_acl(
+    pool: &sqlx::PgPool,
+    filter: &QueryFilter<'_>,
+    acl: Option<&str>,
+) -> Result<u64, anyhow::Error> {
+    let (where_clause, params) = filter.to_sql_and_params();
+    let sql = format!(
+        "
+            UPDATE biominer_indexd_file
+                SET acl = ${}, updated_at = ${}, rev = substr(md5(random()::text || guid), 1, 8)
+                WHERE guid IN (
+                    SELECT f.guid FROM biominer_indexd_file f WHERE {} AND f.status != 'deleted'
+                );
+        ",
+        params.len() + 1,
+        params.len() + 2,
+        where_clause
+    );
+
+    let mut query = sqlx::query(&sql);
+    for val in params.iter() {
+        query = query.bind(val);
+    }
+    let result = query
+        .bind(acl)
+        .bind(Utc::now().timestamp_millis())
+        .execute(pool)
+        .await?;
+
+    info!("Changed the acl of {} files to {:?}.", result.rows_affected(), acl);
+    AnyOk(result.rows_affected())
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Object)]
 pub struct RecordResponse<S>
 where
@@ -641,22 +891,24 @@ pub struct URL {
     pub uploader: String,
     #[oai(validator(max_length = 64))]
     pub file: Option<String>,
+    #[oai(read_only)]
+    pub reason: Option<String>, // Why the url is validated or failed, reported by the url checker
+    #[oai(read_only)]
+    pub checked_at: Option<i64>,
 }
 
 impl URL {
     pub fn get_identity(&self) -> String {
-        let url_parts = self.url.split("/").collect::<Vec<&str>>();
-        // NODE: node://<account_name>/<project_id>/<experiment_id>/<sample_id>/<run_id>/<data_id>;
-        // S3/OSS/Minio: s3://<bucket_name>/<object_name>;
-        // GSA: gsa://<account_name>/<project_id>/<sample_id>/<experiment_id>/<run_id>/<filename>;
-        match url_parts[0] {
-            "node:" => url_parts[3].to_string(),  // project_id
-            "s3:" => url_parts[2].to_string(),    // bucket_name
-            "oss:" => url_parts[2].to_string(),   // bucket_name
-            "minio:" => url_parts[2].to_string(), // bucket_name
-            "gsa:" => url_parts[3].to_string(),   // project_id
-            _ => "".to_string(),
-        }
+        URL::identity_of(&self.url)
+    }
+
+    /// The identity of the repo which the url belongs to, it is used to find the repo config.
+    pub fn identity_of(url: &str) -> String {
+        // NODE: node://<account_name>/<project_id>/... -> project_id;
+        // S3/OSS/Minio: s3://<bucket_name>/<object_name> -> bucket_name;
+        // GSA: gsa://<account_name>/<project_id>/... -> project_id;
+        // Local: file:///<path> -> path, the local repo is found by the path;
+        repo_config::provider::identity_of(url)
     }
 }
 
@@ -711,7 +963,7 @@ impl Config {
     }
 
     // TODO: use a better way to get the registry_id?
-    fn get_registry_id() -> String {
+    pub fn get_registry_id() -> String {
         let registry_id = match std::env::var("BIOMIER_REGISTRY_ID") {
             Ok(v) => v,
             Err(_) => "fudan-pgx".to_string(),
@@ -751,6 +1003,115 @@ impl Config {
     }
 }
 
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone, Object, sqlx::FromRow)]
+pub struct Tombstone {
+    pub guid: String,
+    pub baseid: String,
+    pub reason: Option<String>,
+    pub deleted_by: String,
+    pub purged: bool, // The file record and all its urls, hashes, aliases and tags have been removed
+    pub deleted_at: i64,
+}
+
+/// The lifecycle of a file, the status can only be changed along the allowed transitions.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum FileStatus {
+    Pending,
+    Processing,
+    Validated,
+    Failed,
+    Deleted,
+}
+
+impl FileStatus {
+    pub const ALL: [FileStatus; 5] = [
+        FileStatus::Pending,
+        FileStatus::Processing,
+        FileStatus::Validated,
+        FileStatus::Failed,
+        FileStatus::Deleted,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FileStatus::Pending => "pending",
+            FileStatus::Processing => "processing",
+            FileStatus::Validated => "validated",
+            FileStatus::Failed => "failed",
+            FileStatus::Deleted => "deleted",
+        }
+    }
+
+    /// The statuses which the status can be changed to, a deleted file is read-only.
+    pub fn next_statuses(&self) -> &'static [FileStatus] {
+        match self {
+            FileStatus::Pending => &[
+                FileStatus::Processing,
+                FileStatus::Validated,
+                FileStatus::Failed,
+                FileStatus::Deleted,
+            ],
+            FileStatus::Processing => &[
+                FileStatus::Pending,
+                FileStatus::Validated,
+                FileStatus::Failed,
+                FileStatus::Deleted,
+            ],
+            // Revalidate or revoke the QC sign-off
+            FileStatus::Validated => &[
+                FileStatus::Processing,
+                FileStatus::Failed,
+                FileStatus::Deleted,
+            ],
+            // Retry the failed file
+            FileStatus::Failed => &[
+                FileStatus::Pending,
+                FileStatus::Processing,
+                FileStatus::Deleted,
+            ],
+            FileStatus::Deleted => &[],
+        }
+    }
+
+    pub fn can_transition_to(&self, next: FileStatus) -> bool {
+        self.next_statuses().contains(&next)
+    }
+}
+
+impl std::fmt::Display for FileStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl std::str::FromStr for FileStatus {
+    type Err = AnyError;
+
+    fn from_str(status: &str) -> Result<Self, Self::Err> {
+        match FileStatus::ALL.iter().find(|s| s.as_str() == status) {
+            Some(s) => AnyOk(*s),
+            None => Err(anyhow::anyhow!(
+                "Invalid status: {}, only {} are supported.",
+                status,
+                FileStatus::ALL.map(|s| s.as_str()).join(", ")
+            )),
+        }
+    }
+}
+
+/// A change of the file status, it is kept after the file is purged.
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone, Object, sqlx::FromRow)]
+pub struct StatusHistory {
+    #[oai(read_only)]
+    pub id: i64,
+    pub file: String,
+    pub from_status: String,
+    pub to_status: String,
+    pub actor: String,
+    pub reason: Option<String>,
+    pub created_at: i64,
+}
+
 #[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone, Object, sqlx::FromRow)]
 pub struct File {
     pub guid: String,
@@ -758,7 +1119,7 @@ pub struct File {
     pub size: i64,
     pub created_at: i64,
     pub updated_at: i64,
-    pub status: String, // "pending" | "processing" | "validated" | "failed"
+    pub status: String, // "pending" | "processing" | "validated" | "failed" | "deleted", see `FileStatus`
     pub baseid: String, // The file with multiple versions will have the same baseid
     pub rev: String,
     pub version: i32,
@@ -801,7 +1162,7 @@ impl File {
 
     /// Loads the datafiles from a file.
     ///
-    /// This function loads the datafiles from a file.
+    /// This function loads the datafiles from a file, a `.tsv` file is split by tabs and a `.csv`/`.txt` file is read by the csv reader.
     ///
     /// # Arguments
     ///
@@ -824,7 +1185,10 @@ impl File {
     /// let datafiles = File::from_file(&datafile_path)?;
     /// ```
     pub fn from_file(datafile_path: &PathBuf) -> Result<Vec<Self>, AnyError> {
-        return load_tsv(datafile_path);
+        match datafile_path.extension().and_then(|ext| ext.to_str()) {
+            Some("tsv") => load_tsv(datafile_path),
+            _ => load_csv(datafile_path),
+        }
     }
 
     pub async fn query_file(
@@ -838,24 +1202,86 @@ impl File {
             ));
         }
 
-        let id = if field_name == "guid" {
-            let uid = match field_value
+        let condition = if field_name == "guid" {
+            if !field_value
                 .contains(format!("{}.{}", "biominer", Config::get_registry_id()).as_str())
             {
-                true => field_value.to_string(),
-                false => {
-                    return Err(anyhow::anyhow!("Invalid guid: {}", field_value));
-                }
-            };
+                return Err(anyhow::anyhow!("Invalid guid: {}", field_value));
+            }
 
-            uid
+            "f.guid = $1"
         } else if field_name == "hash" {
-            field_value.to_string()
+            // The hashes live in their own table, so match the file which owns the hash.
+            "f.guid IN (SELECT file FROM biominer_indexd_hash WHERE hash = $1)"
+        } else if field_name == "alias" {
+            "f.guid IN (SELECT file FROM biominer_indexd_alias WHERE name = $1)"
         } else {
             return Err(anyhow::anyhow!("Invalid field name: {}", field_name));
         };
 
-        let sql_str = format!(
+        match File::find_file(pool, condition, field_value).await? {
+            Some(file) => AnyOk(file),
+            None => Err(anyhow::anyhow!(
+                "Cannot find the file with {} {}",
+                field_name,
+                field_value
+            )),
+        }
+    }
+
+    /// Resolve a file by any of its identifiers: a guid, a bare uuid, a hash or an alias.
+    ///
+    /// A hash-like id which does not match any hash is also tried as an alias, because an alias may look like a hash.
+    pub async fn resolve(pool: &sqlx::PgPool, id: &str) -> Result<File, anyhow::Error> {
+        let id = id.trim();
+        if id.is_empty() {
+            return Err(anyhow::anyhow!("The id cannot be empty"));
+        }
+
+        // A md5 hash is also a valid uuid in the simple format, so only the hyphenated uuid is taken as a guid.
+        if id.len() == 36 {
+            if let Ok(uid) = uuid::Uuid::parse_str(id) {
+                return File::get_file(pool, &uid).await;
+            }
+        }
+
+        if id.starts_with("biominer.") {
+            return File::query_file(pool, "guid", id).await;
+        }
+
+        if util::which_hash_type(id).is_some() {
+            let condition = "f.guid IN (SELECT file FROM biominer_indexd_hash WHERE hash = $1)";
+            if let Some(file) = File::find_file(pool, condition, id).await? {
+                return AnyOk(file);
+            }
+        }
+
+        let condition = "f.guid IN (SELECT file FROM biominer_indexd_alias WHERE name = $1)";
+        match File::find_file(pool, condition, id).await? {
+            Some(file) => AnyOk(file),
+            None => Err(anyhow::anyhow!("Cannot find the file with id {}", id)),
+        }
+    }
+
+    /// Find the file matching the condition, a hash may be shared by several files, the active file registered first wins.
+    async fn find_file(
+        pool: &sqlx::PgPool,
+        condition: &str,
+        value: &str,
+    ) -> Result<Option<File>, anyhow::Error> {
+        let sql_str = File::select_sql(condition, "f.status = 'deleted', f.created_at, f.guid");
+
+        let file = sqlx::query_as::<_, File>(&sql_str)
+            .bind(value)
+            .fetch_optional(pool)
+            .await?;
+
+        AnyOk(file)
+    }
+
+    /// Build the sql for selecting files with their urls, hashes, aliases and tags.
+    fn select_sql(condition: &str, order_by: &str) -> String {
+        format!(
             "
                 SELECT 
                 f.*,
@@ -869,11 +1295,13 @@ impl File {
                             'created_at', u.created_at,
                             'status', u.status,
                             'uploader', u.uploader,
-                            'file', u.file
+                            'file', u.file,
+                            'reason', u.reason,
+                            'checked_at', u.checked_at
                         )
                     )
                     FROM biominer_indexd_url u
-                    WHERE u.file = f.{field_name}
+                    WHERE u.file = f.guid
                 ) AS urls,
 
                 (
@@ -886,7 +1314,7 @@ impl File {
                         )
                     )
                     FROM biominer_indexd_hash h
-                    WHERE h.file = f.{field_name}
+                    WHERE h.file = f.guid
                 ) AS hashes,
 
                 (
@@ -898,7 +1326,7 @@ impl File {
                         )
                     )
                     FROM biominer_indexd_alias a
-                    WHERE a.file = f.{field_name}
+                    WHERE a.file = f.guid
                 ) AS aliases,
 
                 (
@@ -911,30 +1339,17 @@ impl File {
                         )
                     )
                     FROM biominer_indexd_tag t
-                    WHERE t.file = f.{field_name}
+                    WHERE t.file = f.guid
                 ) AS tags
 
                 FROM biominer_indexd_file f
-                WHERE f.{field_name} = $1;
+                WHERE {condition}
+                ORDER BY {order_by};
 
             ",
-            field_name = field_name
-        );
-
-        let file = sqlx::query_as::<_, File>(&sql_str)
-            .bind(id)
-            .fetch_optional(pool)
-            .await?;
-
-        if file.is_none() {
-            return Err(anyhow::anyhow!(
-                "Cannot find the file with {} {}",
-                field_name,
-                field_value
-            ));
-        }
-
-        AnyOk(file.unwrap())
+            condition = condition,
+            order_by = order_by
+        )
     }
 
     pub async fn get_file(pool: &sqlx::PgPool, id: &uuid::Uuid) -> Result<File, anyhow::Error> {
@@ -948,125 +1363,364 @@ impl File {
         File::query_file(pool, "hash", hash).await
     }
 
+    pub async fn get_file_with_alias(
+        pool: &sqlx::PgPool,
+        alias: &str,
+    ) -> Result<File, anyhow::Error> {
+        let condition = "f.guid IN (SELECT file FROM biominer_indexd_alias WHERE name = $1)";
+        match File::find_file(pool, condition, alias).await? {
+            Some(file) => AnyOk(file),
+            None => Err(anyhow::anyhow!("Cannot find the file with alias {}", alias)),
+        }
+    }
+
     fn gen_guid(id: &uuid::Uuid) -> String {
         return format!("biominer.{}/{}", Config::get_registry_id(), id);
     }
 
-    pub async fn delete_file(pool: &sqlx::PgPool, id: &uuid::Uuid) -> Result<(), anyhow::Error> {
+    pub async fn get_tombstone(
+        pool: &sqlx::PgPool,
+        id: &uuid::Uuid,
+    ) -> Result<Option<Tombstone>, anyhow::Error> {
         let guid = File::gen_guid(id);
-        // NOTICE: Be careful, this is a hard delete (delete cascade).
-        let v = sqlx::query("DELETE FROM biominer_indexd_file WHERE guid = $1;")
-            .bind(&guid)
-            .execute(pool)
-            .await?;
-
-        if v.rows_affected() >= 1 {
-            AnyOk(())
-        } else {
-            Err(anyhow::anyhow!("Cannot delete the file with guid {}", guid))
-        }
-    }
-
-    pub async fn check_hash_exists(pool: &sqlx::PgPool, hash: &str) -> Result<bool, anyhow::Error> {
-        let v = sqlx::query("SELECT count(*) as count FROM biominer_indexd_hash WHERE hash = $1")
-            .bind(hash)
-            .fetch_one(pool)
-            .await?;
+        let tombstone =
+            sqlx::query_as::<_, Tombstone>("SELECT * FROM biominer_indexd_tombstone WHERE guid = $1")
+                .bind(&guid)
+                .fetch_optional(pool)
+                .await?;
 
-        Ok(v.get::<i64, _>("count") > 0)
+        AnyOk(tombstone)
     }
 
-    pub async fn add_url(
+    /// Soft delete the file: mark it as `deleted` and leave a tombstone, so the guid keeps pointing to the same (deleted) object.
+    /// The urls, hashes, aliases and tags are kept, call `purge_file` to remove them.
+    pub async fn delete_file(
         pool: &sqlx::PgPool,
-        uuid: &uuid::Uuid,
-        url: &str,
-        uploader: &str,
-        status: &str,
+        id: &uuid::Uuid,
+        deleted_by: &str,
+        reason: Option<&str>,
     ) -> Result<(), anyhow::Error> {
-        let guid = File::gen_guid(uuid);
-
-        // 校验文件是否存在
-        let file_exists =
-            sqlx::query_scalar::<_, i64>("SELECT 1 FROM biominer_indexd_file WHERE guid = $1")
-                .bind(&guid)
-                .fetch_optional(pool)
-                .await?
-                .is_some();
+        let guid = File::gen_guid(id);
+        let mut tx = pool.begin().await?;
 
-        if !file_exists {
-            warn!("Cannot find the file {}.", guid);
-            return Err(anyhow::anyhow!("Cannot find the file with guid {}", guid));
-        }
+        // The old status is read from the snapshot before the update
+        let deleted = sqlx::query_as::<_, (String, String)>(
+            "
+                UPDATE biominer_indexd_file f
+                    SET status = 'deleted', updated_at = $2
+                    FROM biominer_indexd_file old
+                    WHERE f.guid = $1 AND old.guid = f.guid AND f.status != 'deleted'
+                    RETURNING f.baseid, old.status;
+            ",
+        )
+        .bind(&guid)
+        .bind(Utc::now().timestamp_millis())
+        .fetch_optional(&mut tx)
+        .await?;
 
-        // 合法性校验
-        let status = match status {
-            "pending" | "processing" | "validated" | "failed" => status.to_string(),
-            _ => "pending".to_string(),
+        let (baseid, status) = match deleted {
+            Some(deleted) => deleted,
+            None => {
+                tx.rollback().await?;
+                return match File::check_file_exists(pool, &guid).await? {
+                    true => Err(FileError::Deleted { guid }.into()),
+                    false => Err(FileError::NotFound { guid }.into()),
+                };
+            }
         };
 
-        // 插入或更新 URL
-        let result = sqlx::query(
+        File::add_tombstone(&mut tx, &guid, &baseid, deleted_by, reason).await?;
+        File::record_status(&mut tx, &guid, &status, "deleted", deleted_by, reason).await?;
+
+        tx.commit().await?;
+        info!("Soft delete the file {} by {}", guid, deleted_by);
+        AnyOk(())
+    }
+
+    async fn add_tombstone(
+        tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+        guid: &str,
+        baseid: &str,
+        deleted_by: &str,
+        reason: Option<&str>,
+    ) -> Result<(), anyhow::Error> {
+        sqlx::query(
             "
-                INSERT INTO biominer_indexd_url (file, url, status, uploader)
-                VALUES ($1, $2, $3, $4)
-                ON CONFLICT (file, url)
-                DO UPDATE SET status = EXCLUDED.status, uploader = EXCLUDED.uploader
-                RETURNING *;
+                INSERT INTO biominer_indexd_tombstone (guid, baseid, reason, deleted_by)
+                    VALUES ($1, $2, $3, $4)
+                    ON CONFLICT DO NOTHING;
             ",
         )
-        .bind(&guid)
-        .bind(url)
-        .bind(&status)
-        .bind(uploader)
-        .execute(pool)
+        .bind(guid)
+        .bind(baseid)
+        .bind(reason)
+        .bind(deleted_by)
+        .execute(&mut *tx)
         .await?;
 
-        if result.rows_affected() == 1 {
-            info!("Add url {} to file {}", url, guid);
-        } else {
-            info!("Url {} already exists in file {}.", url, guid);
-        }
+        Ok(())
+    }
+
+    async fn record_status(
+        tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+        guid: &str,
+        from_status: &str,
+        to_status: &str,
+        actor: &str,
+        reason: Option<&str>,
+    ) -> Result<(), anyhow::Error> {
+        sqlx::query(
+            "
+                INSERT INTO biominer_indexd_status_history (file, from_status, to_status, actor, reason, created_at)
+                    VALUES ($1, $2, $3, $4, $5, $6);
+            ",
+        )
+        .bind(guid)
+        .bind(from_status)
+        .bind(to_status)
+        .bind(actor)
+        .bind(reason)
+        .bind(Utc::now().timestamp_millis())
+        .execute(&mut *tx)
+        .await?;
 
         Ok(())
     }
 
-    pub async fn delete_url(
+    /// Change the status of the file along the allowed transitions, and record who changed it and why.
+    ///
+    /// Changing the status to `deleted` is the same as `delete_file`, the file leaves a tombstone.
+    pub async fn update_status(
         pool: &sqlx::PgPool,
         id: &uuid::Uuid,
-        url: Option<&str>,
-    ) -> Result<(), anyhow::Error> {
+        status: FileStatus,
+        actor: &str,
+        reason: Option<&str>,
+        rev: Option<&str>,
+    ) -> Result<String, anyhow::Error> {
         let guid = File::gen_guid(id);
+        let mut tx = pool.begin().await?;
+        let new_rev = File::bump_rev(&mut tx, &guid, rev).await?;
 
-        let result = match url {
-            Some(u) => {
-                sqlx::query("DELETE FROM biominer_indexd_url WHERE file = $1 AND url = $2")
-                    .bind(&guid)
-                    .bind(u)
-                    .execute(pool)
-                    .await?
-            }
-            None => {
-                sqlx::query("DELETE FROM biominer_indexd_url WHERE file = $1")
-                    .bind(&guid)
-                    .execute(pool)
-                    .await?
-            }
-        };
+        let (current, baseid) = sqlx::query_as::<_, (String, String)>(
+            "SELECT status, baseid FROM biominer_indexd_file WHERE guid = $1",
+        )
+        .bind(&guid)
+        .fetch_one(&mut tx)
+        .await?;
 
-        if result.rows_affected() >= 1 {
-            AnyOk(())
-        } else {
-            Err(anyhow::anyhow!(
-                "Cannot delete the url with guid {} and url {:?}",
+        // The status registered before the lifecycle is enforced may be unknown, it can be changed to any status.
+        let allowed = match current.parse::<FileStatus>() {
+            Ok(current) => current.can_transition_to(status),
+            Err(_) => true,
+        };
+        if !allowed {
+            return Err(FileError::InvalidTransition {
                 guid,
-                url
-            ))
+                from: current,
+                to: status.to_string(),
+            }
+            .into());
         }
-    }
+
+        sqlx::query("UPDATE biominer_indexd_file SET status = $2 WHERE guid = $1")
+            .bind(&guid)
+            .bind(status.as_str())
+            .execute(&mut tx)
+            .await?;
+
+        if status == FileStatus::Deleted {
+            File::add_tombstone(&mut tx, &guid, &baseid, actor, reason).await?;
+        }
+        File::record_status(&mut tx, &guid, &current, status.as_str(), actor, reason).await?;
+
+        tx.commit().await?;
+        info!(
+            "Change the status of the file {} from {} to {} by {}",
+            guid, current, status, actor
+        );
+        AnyOk(new_rev)
+    }
+
+    /// Get the status changes of the file, ordered by time.
+    pub async fn get_status_history(
+        pool: &sqlx::PgPool,
+        id: &uuid::Uuid,
+    ) -> Result<Vec<StatusHistory>, anyhow::Error> {
+        let guid = File::gen_guid(id);
+        let history = sqlx::query_as::<_, StatusHistory>(
+            "SELECT * FROM biominer_indexd_status_history WHERE file = $1 ORDER BY created_at, id",
+        )
+        .bind(&guid)
+        .fetch_all(pool)
+        .await?;
+
+        AnyOk(history)
+    }
+
+    /// Remove the file and all its urls, hashes, aliases and tags in one transaction.
+    ///
+    /// NOTICE: Be careful, this is a hard delete. The schema has no `ON DELETE CASCADE`, so the child rows are deleted explicitly.
+    /// Only the tombstone is kept, it prevents the guid from being registered again.
+    pub async fn purge_file(
+        pool: &sqlx::PgPool,
+        id: &uuid::Uuid,
+        deleted_by: &str,
+        reason: Option<&str>,
+    ) -> Result<(), anyhow::Error> {
+        let guid = File::gen_guid(id);
+        let mut tx = pool.begin().await?;
+
+        let baseid = sqlx::query_scalar::<_, String>(
+            "SELECT baseid FROM biominer_indexd_file WHERE guid = $1 FOR UPDATE",
+        )
+        .bind(&guid)
+        .fetch_optional(&mut tx)
+        .await?;
+
+        let baseid = match baseid {
+            Some(baseid) => baseid,
+            None => {
+                tx.rollback().await?;
+                return Err(FileError::NotFound { guid }.into());
+            }
+        };
+
+        for table in [
+            "biominer_indexd_url",
+            "biominer_indexd_hash",
+            "biominer_indexd_alias",
+            "biominer_indexd_tag",
+            "biominer_indexd_upload",
+        ] {
+            let sql = format!("DELETE FROM {} WHERE file = $1", table);
+            sqlx::query(&sql).bind(&guid).execute(&mut tx).await?;
+        }
+
+        sqlx::query("DELETE FROM biominer_indexd_file WHERE guid = $1")
+            .bind(&guid)
+            .execute(&mut tx)
+            .await?;
+
+        sqlx::query(
+            "
+                INSERT INTO biominer_indexd_tombstone (guid, baseid, reason, deleted_by, purged)
+                    VALUES ($1, $2, $3, $4, TRUE)
+                    ON CONFLICT (guid)
+                    DO UPDATE SET purged = TRUE, reason = EXCLUDED.reason, deleted_by = EXCLUDED.deleted_by;
+            ",
+        )
+        .bind(&guid)
+        .bind(&baseid)
+        .bind(reason)
+        .bind(deleted_by)
+        .execute(&mut tx)
+        .await?;
+
+        tx.commit().await?;
+        warn!("Purge the file {} by {}", guid, deleted_by);
+        AnyOk(())
+    }
+
+    pub async fn check_hash_exists(pool: &sqlx::PgPool, hash: &str) -> Result<bool, anyhow::Error> {
+        let v = sqlx::query("SELECT count(*) as count FROM biominer_indexd_hash WHERE hash = $1")
+            .bind(hash)
+            .fetch_one(pool)
+            .await?;
+
+        Ok(v.get::<i64, _>("count") > 0)
+    }
+
+    pub async fn add_url(
+        pool: &sqlx::PgPool,
+        uuid: &uuid::Uuid,
+        url: &str,
+        uploader: &str,
+        status: &str,
+        rev: Option<&str>,
+    ) -> Result<String, anyhow::Error> {
+        let guid = File::gen_guid(uuid);
+
+        // 校验文件是否存在，并更新 rev
+        let mut tx = pool.begin().await?;
+        let new_rev = File::bump_rev(&mut tx, &guid, rev).await?;
+
+        // 合法性校验，URL 不能被标记为 deleted
+        let status = match status.parse::<FileStatus>() {
+            Ok(FileStatus::Deleted) | Err(_) => FileStatus::Pending.to_string(),
+            Ok(status) => status.to_string(),
+        };
+
+        // 插入或更新 URL
+        let result = sqlx::query(
+            "
+                INSERT INTO biominer_indexd_url (file, url, status, uploader)
+                VALUES ($1, $2, $3, $4)
+                ON CONFLICT (url)
+                DO UPDATE SET status = EXCLUDED.status, uploader = EXCLUDED.uploader
+                WHERE biominer_indexd_url.file = EXCLUDED.file
+                RETURNING *;
+            ",
+        )
+        .bind(&guid)
+        .bind(url)
+        .bind(&status)
+        .bind(uploader)
+        .execute(&mut tx)
+        .await?;
+
+        // 已被其他文件登记的 URL 不会被更新
+        if result.rows_affected() == 0 {
+            warn!("Url {} has been registered by another file.", url);
+            let owner = sqlx::query_scalar::<_, String>(
+                "SELECT file FROM biominer_indexd_url WHERE url = $1",
+            )
+            .bind(url)
+            .fetch_one(&mut tx)
+            .await?;
+            return Err(FileError::Registered {
+                item: format!("url {}", url),
+                guid: owner,
+            }
+            .into());
+        }
+        info!("Add url {} to file {}", url, guid);
+
+        tx.commit().await?;
+        Ok(new_rev)
+    }
+
+    pub async fn delete_url(
+        pool: &sqlx::PgPool,
+        id: &uuid::Uuid,
+        url: &str,
+        rev: Option<&str>,
+    ) -> Result<String, anyhow::Error> {
+        let guid = File::gen_guid(id);
+        let mut tx = pool.begin().await?;
+        let new_rev = File::bump_rev(&mut tx, &guid, rev).await?;
+
+        let result = sqlx::query("DELETE FROM biominer_indexd_url WHERE file = $1 AND url = $2;")
+            .bind(&guid)
+            .bind(url)
+            .execute(&mut tx)
+            .await?;
+
+        if result.rows_affected() >= 1 {
+            tx.commit().await?;
+            AnyOk(new_rev)
+        } else {
+            Err(FileError::NoSuchItem {
+                guid,
+                item: format!("url {}", url),
+            }
+            .into())
+        }
+    }
 
     async fn check_file_exists(pool: &sqlx::PgPool, guid: &str) -> Result<bool, anyhow::Error> {
         let exists =
-            sqlx::query_scalar::<_, i64>("SELECT 1 FROM biominer_indexd_file WHERE guid = $1")
+            sqlx::query_scalar::<_, i32>("SELECT 1 FROM biominer_indexd_file WHERE guid = $1")
                 .bind(guid)
                 .fetch_optional(pool)
                 .await?
@@ -1075,65 +1729,155 @@ impl File {
         Ok(exists)
     }
 
+    /// Make sure the file exists and has not been deleted, the deleted files are read-only.
+    async fn ensure_file_active(pool: &sqlx::PgPool, guid: &str) -> Result<(), anyhow::Error> {
+        let status =
+            sqlx::query_scalar::<_, String>("SELECT status FROM biominer_indexd_file WHERE guid = $1")
+                .bind(guid)
+                .fetch_optional(pool)
+                .await?;
+
+        match status.as_deref() {
+            None => {
+                warn!("Cannot find the file {}.", guid);
+                Err(FileError::NotFound {
+                    guid: guid.to_string(),
+                }
+                .into())
+            }
+            Some("deleted") => Err(FileError::Deleted {
+                guid: guid.to_string(),
+            }
+            .into()),
+            Some(_) => Ok(()),
+        }
+    }
+
+    fn gen_rev() -> String {
+        uuid::Uuid::new_v4().to_string()[..8].to_string()
+    }
+
+    /// Rotate the rev and bump the updated_at of the file, it must be called in the transaction of the mutation.
+    ///
+    /// The row is locked until the transaction ends, so the writers of the same file are serialized.
+    /// If the `rev` is specified, the mutation is rejected when the file has been modified by others.
+    async fn bump_rev(
+        tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+        guid: &str,
+        rev: Option<&str>,
+    ) -> Result<String, anyhow::Error> {
+        let new_rev = File::gen_rev();
+        let updated = sqlx::query_scalar::<_, String>(
+            "
+                UPDATE biominer_indexd_file
+                    SET rev = $2, updated_at = $3
+                    WHERE guid = $1 AND status != 'deleted' AND ($4::VARCHAR IS NULL OR rev = $4)
+                    RETURNING rev;
+            ",
+        )
+        .bind(guid)
+        .bind(&new_rev)
+        .bind(Utc::now().timestamp_millis())
+        .bind(rev)
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        if let Some(new_rev) = updated {
+            return AnyOk(new_rev);
+        }
+
+        let current = sqlx::query_as::<_, (String, String)>(
+            "SELECT status, rev FROM biominer_indexd_file WHERE guid = $1",
+        )
+        .bind(guid)
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        let guid = guid.to_string();
+        match current {
+            None => Err(FileError::NotFound { guid }.into()),
+            Some((status, _)) if status == "deleted" => Err(FileError::Deleted { guid }.into()),
+            Some((_, current)) => {
+                warn!("The rev of the file {} is stale, current rev: {}", guid, current);
+                Err(FileError::RevMismatch {
+                    guid,
+                    rev: rev.unwrap_or_default().to_string(),
+                    current,
+                }
+                .into())
+            }
+        }
+    }
+
     pub async fn add_alias(
         pool: &sqlx::PgPool,
         uuid: &uuid::Uuid,
         alias: &str,
-    ) -> Result<(), anyhow::Error> {
+        rev: Option<&str>,
+    ) -> Result<String, anyhow::Error> {
         let guid = File::gen_guid(uuid);
 
-        if !File::check_file_exists(pool, &guid).await? {
-            warn!("Cannot find the file {}.", guid);
-            return Err(anyhow::anyhow!("Cannot find the file with {}", guid));
-        }
+        let mut tx = pool.begin().await?;
+        let new_rev = File::bump_rev(&mut tx, &guid, rev).await?;
 
         let result = sqlx::query(
             "INSERT INTO biominer_indexd_alias (file, name) VALUES ($1, $2) ON CONFLICT DO NOTHING",
         )
         .bind(&guid)
         .bind(alias)
-        .execute(pool)
+        .execute(&mut tx)
         .await?;
 
         if result.rows_affected() == 1 {
             info!("Add alias {} to file {}", alias, guid);
         } else {
+            // 已被其他文件登记的别名不能再添加
+            let owner = sqlx::query_scalar::<_, String>(
+                "SELECT file FROM biominer_indexd_alias WHERE name = $1",
+            )
+            .bind(alias)
+            .fetch_one(&mut tx)
+            .await?;
+            if owner != guid {
+                warn!("Alias {} has been registered by another file.", alias);
+                return Err(FileError::Registered {
+                    item: format!("alias {}", alias),
+                    guid: owner,
+                }
+                .into());
+            }
             info!("Alias {} already exists in file {}.", alias, guid);
         }
 
-        Ok(())
+        tx.commit().await?;
+        Ok(new_rev)
     }
 
     pub async fn delete_alias(
         pool: &sqlx::PgPool,
         id: &uuid::Uuid,
-        name: Option<&str>,
-    ) -> Result<(), anyhow::Error> {
+        name: &str,
+        rev: Option<&str>,
+    ) -> Result<String, anyhow::Error> {
         let guid = File::gen_guid(id);
-        let result = match name {
-            Some(n) => {
-                sqlx::query("DELETE FROM biominer_indexd_alias WHERE file = $1 AND name = $2")
-                    .bind(&guid)
-                    .bind(n)
-                    .execute(pool)
-                    .await?
-            }
-            None => {
-                sqlx::query("DELETE FROM biominer_indexd_alias WHERE file = $1")
-                    .bind(&guid)
-                    .execute(pool)
-                    .await?
-            }
-        };
+        let mut tx = pool.begin().await?;
+        let new_rev = File::bump_rev(&mut tx, &guid, rev).await?;
+
+        let result = sqlx::query("DELETE FROM biominer_indexd_alias WHERE file = $1 AND name = $2;")
+            .bind(&guid)
+            .bind(name)
+            .execute(&mut tx)
+            .await?;
 
         if result.rows_affected() >= 1 {
-            AnyOk(())
+            tx.commit().await?;
+            AnyOk(new_rev)
         } else {
-            Err(anyhow::anyhow!(
-                "Cannot delete the alias with {} and {:?}",
+            Err(FileError::NoSuchItem {
                 guid,
-                name
-            ))
+                item: format!("alias {}", name),
+            }
+            .into())
         }
     }
 
@@ -1142,13 +1886,12 @@ impl File {
         uuid: &uuid::Uuid,
         field_name: &str,
         field_value: &str,
-    ) -> Result<(), anyhow::Error> {
+        rev: Option<&str>,
+    ) -> Result<String, anyhow::Error> {
         let guid = File::gen_guid(uuid);
 
-        if !File::check_file_exists(pool, &guid).await? {
-            warn!("Cannot find the file {}.", guid);
-            return Err(anyhow::anyhow!("Cannot find the file with guid {}", guid));
-        }
+        let mut tx = pool.begin().await?;
+        let new_rev = File::bump_rev(&mut tx, &guid, rev).await?;
 
         // 插入或更新 tag
         let result = sqlx::query(
@@ -1163,7 +1906,7 @@ impl File {
         .bind(&guid)
         .bind(field_name)
         .bind(field_value)
-        .execute(pool)
+        .execute(&mut tx)
         .await?;
 
         if result.rows_affected() == 1 {
@@ -1178,67 +1921,60 @@ impl File {
             );
         }
 
-        Ok(())
+        tx.commit().await?;
+        Ok(new_rev)
     }
 
     pub async fn delete_tag(
         pool: &sqlx::PgPool,
         id: &uuid::Uuid,
-        field_name: Option<&str>,
-    ) -> Result<(), anyhow::Error> {
+        field_name: &str,
+        rev: Option<&str>,
+    ) -> Result<String, anyhow::Error> {
         let guid = File::gen_guid(id);
+        let mut tx = pool.begin().await?;
+        let new_rev = File::bump_rev(&mut tx, &guid, rev).await?;
 
-        let v = match field_name {
-            Some(h) => {
-                sqlx::query("DELETE FROM biominer_indexd_tag WHERE file = $1 AND field_name = $2;")
-                    .bind(&guid)
-                    .bind(h)
-                    .execute(pool)
-                    .await?
-            }
-            None => {
-                sqlx::query("DELETE FROM biominer_indexd_tag WHERE file = $1;")
-                    .bind(&guid)
-                    .execute(pool)
-                    .await?
-            }
-        };
+        let result = sqlx::query("DELETE FROM biominer_indexd_tag WHERE file = $1 AND field_name = $2;")
+            .bind(&guid)
+            .bind(field_name)
+            .execute(&mut tx)
+            .await?;
 
-        if v.rows_affected() >= 1 {
-            AnyOk(())
+        if result.rows_affected() >= 1 {
+            tx.commit().await?;
+            AnyOk(new_rev)
         } else {
-            return Err(anyhow::anyhow!(
-                "Cannot delete the tag with {} and {:?}",
+            Err(FileError::NoSuchItem {
                 guid,
-                field_name
-            ));
+                item: format!("tag {}", field_name),
+            }
+            .into())
         }
     }
 
+    /// Add a hash to the file, the hash type is detected when it is not given.
     pub async fn add_hash(
         pool: &sqlx::PgPool,
         uuid: &uuid::Uuid,
         hash: &str,
-    ) -> Result<(), anyhow::Error> {
+        hash_type: Option<&str>,
+        rev: Option<&str>,
+    ) -> Result<String, anyhow::Error> {
         let guid = File::gen_guid(uuid);
 
-        if !File::check_file_exists(pool, &guid).await? {
-            warn!("Cannot find the file {}.", guid);
-            return Err(anyhow::anyhow!("Cannot find the file with guid {}", guid));
-        }
-
         // 判定 hash 类型
-        let hash_type = match util::which_hash_type(hash) {
-            Some(ht) => ht,
-            None => {
+        let hash_type = match util::check_hash(hash, hash_type) {
+            Ok(ht) => ht,
+            Err(msg) => {
                 warn!("Cannot determine hash type of {}", hash);
-                return Err(anyhow::anyhow!(
-                    "Unsupported hash: {}. Only support md5, sha1, sha256, sha512, crc32, etag, crc64.",
-                    hash
-                ));
+                return Err(anyhow::anyhow!(msg));
             }
         };
 
+        let mut tx = pool.begin().await?;
+        let new_rev = File::bump_rev(&mut tx, &guid, rev).await?;
+
         // 插入 hash（去重）
         let result = sqlx::query(
             "
@@ -1251,7 +1987,7 @@ impl File {
         .bind(&guid)
         .bind(hash_type)
         .bind(hash)
-        .execute(pool)
+        .execute(&mut tx)
         .await?;
 
         if result.rows_affected() == 1 {
@@ -1260,41 +1996,122 @@ impl File {
             info!("Hash {} already exists in file {}.", hash, guid);
         }
 
-        Ok(())
+        tx.commit().await?;
+        Ok(new_rev)
     }
 
     pub async fn delete_hash(
         pool: &sqlx::PgPool,
         id: &uuid::Uuid,
-        hash: Option<&str>,
-    ) -> Result<(), anyhow::Error> {
+        hash: &str,
+        rev: Option<&str>,
+    ) -> Result<String, anyhow::Error> {
         let guid = File::gen_guid(id);
+        let mut tx = pool.begin().await?;
+        let new_rev = File::bump_rev(&mut tx, &guid, rev).await?;
 
-        let v = match hash {
-            Some(h) => {
-                sqlx::query("DELETE FROM biominer_indexd_hash WHERE file = $1 AND hash = $2;")
-                    .bind(&guid)
-                    .bind(h)
-                    .execute(pool)
-                    .await?
-            }
-            None => {
-                sqlx::query("DELETE FROM biominer_indexd_hash WHERE file = $1;")
-                    .bind(&guid)
-                    .execute(pool)
-                    .await?
-            }
-        };
+        let result = sqlx::query("DELETE FROM biominer_indexd_hash WHERE file = $1 AND hash = $2;")
+            .bind(&guid)
+            .bind(hash)
+            .execute(&mut tx)
+            .await?;
 
-        if v.rows_affected() >= 1 {
-            AnyOk(())
+        if result.rows_affected() >= 1 {
+            tx.commit().await?;
+            AnyOk(new_rev)
         } else {
-            return Err(anyhow::anyhow!(
-                "Cannot delete the hash with {} and {:?}",
+            Err(FileError::NoSuchItem {
                 guid,
-                hash
-            ));
+                item: format!("hash {}", hash),
+            }
+            .into())
+        }
+    }
+
+    /// Replace the fields of the file in one transaction, so the rev is checked once for all of them.
+    ///
+    /// The urls which have been registered by the other files are rejected.
+    pub async fn update(
+        pool: &sqlx::PgPool,
+        id: &uuid::Uuid,
+        update: &FileUpdate,
+        uploader: &str,
+        rev: Option<&str>,
+    ) -> Result<String, anyhow::Error> {
+        let guid = File::gen_guid(id);
+        let mut tx = pool.begin().await?;
+        let new_rev = File::bump_rev(&mut tx, &guid, rev).await?;
+
+        if let Some(filename) = &update.filename {
+            sqlx::query("UPDATE biominer_indexd_file SET filename = $2 WHERE guid = $1")
+                .bind(&guid)
+                .bind(filename)
+                .execute(&mut tx)
+                .await?;
+        }
+
+        if let Some(acl) = &update.acl {
+            sqlx::query("UPDATE biominer_indexd_file SET acl = $2 WHERE guid = $1")
+                .bind(&guid)
+                .bind(acl)
+                .execute(&mut tx)
+                .await?;
+        }
+
+        if let Some(urls) = &update.urls {
+            let owner = sqlx::query_as::<_, (String, String)>(
+                "SELECT url, file FROM biominer_indexd_url WHERE url = ANY($1) AND file != $2 LIMIT 1",
+            )
+            .bind(urls)
+            .bind(&guid)
+            .fetch_optional(&mut tx)
+            .await?;
+
+            if let Some((url, owner)) = owner {
+                return Err(FileError::Registered {
+                    item: format!("url {}", url),
+                    guid: owner,
+                }
+                .into());
+            }
+
+            sqlx::query("DELETE FROM biominer_indexd_url WHERE file = $1 AND NOT (url = ANY($2))")
+                .bind(&guid)
+                .bind(urls)
+                .execute(&mut tx)
+                .await?;
+
+            for url in urls {
+                sqlx::query(
+                    "INSERT INTO biominer_indexd_url (file, url, uploader) VALUES ($1, $2, $3) ON CONFLICT DO NOTHING",
+                )
+                .bind(&guid)
+                .bind(url)
+                .bind(uploader)
+                .execute(&mut tx)
+                .await?;
+            }
+        }
+
+        for (field_name, field_value) in &update.tags {
+            sqlx::query(
+                "
+                    INSERT INTO biominer_indexd_tag (file, field_name, field_value)
+                        VALUES ($1, $2, $3)
+                        ON CONFLICT (file, field_name)
+                        DO UPDATE SET field_value = EXCLUDED.field_value;
+                ",
+            )
+            .bind(&guid)
+            .bind(field_name)
+            .bind(field_value)
+            .execute(&mut tx)
+            .await?;
         }
+
+        tx.commit().await?;
+        info!("Update the file {} by {}, the rev is {}", guid, uploader, new_rev);
+        Ok(new_rev)
     }
 
     pub async fn add(
@@ -1313,17 +2130,94 @@ impl File {
             }
         };
 
-        // 插入 File（忽略冲突）
-        let insert_file = sqlx::query(
-        "
-                INSERT INTO biominer_indexd_file 
-                    (guid, filename, size, created_at, updated_at, status, baseid, uploader, rev, version)
-                    VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
-                    ON CONFLICT DO NOTHING
-                    RETURNING *;
-            ",
-        )
-        .bind(&self.guid)
+        self.insert(&mut tx, "md5", hash, url, alias).await?;
+
+        // 提交事务
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Register the file as a new version of the file `id`, it inherits the baseid and gets an incremented version.
+    pub async fn add_version(
+        &mut self,
+        pool: &sqlx::PgPool,
+        id: &uuid::Uuid,
+        hash: &str,
+        url: Option<&str>,
+        alias: Option<&str>,
+    ) -> Result<(), anyhow::Error> {
+        let mut registration = FileRegistration::new(self.clone(), hash);
+        registration.urls.extend(url.map(String::from));
+        registration.aliases.extend(alias.map(String::from));
+        registration.add_version(pool, id).await?;
+
+        *self = registration.file;
+        Ok(())
+    }
+
+    /// Get all versions of the file with the baseid, ordered by version.
+    pub async fn get_versions(
+        pool: &sqlx::PgPool,
+        baseid: &str,
+    ) -> Result<Vec<File>, anyhow::Error> {
+        let sql_str = File::select_sql("f.baseid = $1", "f.version");
+        let files = sqlx::query_as::<_, File>(&sql_str)
+            .bind(baseid)
+            .fetch_all(pool)
+            .await?;
+
+        AnyOk(files)
+    }
+
+    /// Get the latest version of the file with the baseid, the deleted versions are skipped.
+    pub async fn get_latest_version(
+        pool: &sqlx::PgPool,
+        baseid: &str,
+    ) -> Result<File, anyhow::Error> {
+        let files = File::get_versions(pool, baseid).await?;
+        match files.into_iter().rev().find(|file| file.status != "deleted") {
+            Some(file) => AnyOk(file),
+            None => Err(anyhow::anyhow!(
+                "Cannot find any available version of the file with baseid {}",
+                baseid
+            )),
+        }
+    }
+
+    async fn insert(
+        &self,
+        tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+        hash_type: &str,
+        hash: &str,
+        url: Option<&str>,
+        alias: Option<&str>,
+    ) -> Result<(), anyhow::Error> {
+        // 已删除的 guid 不允许重新登记
+        let tombstoned = sqlx::query_scalar::<_, i32>(
+            "SELECT 1 FROM biominer_indexd_tombstone WHERE guid = $1",
+        )
+        .bind(&self.guid)
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        if tombstoned.is_some() {
+            return Err(FileError::Deleted {
+                guid: self.guid.clone(),
+            }
+            .into());
+        }
+
+        // 插入 File（忽略冲突）
+        let insert_file = sqlx::query(
+        "
+                INSERT INTO biominer_indexd_file 
+                    (guid, filename, size, created_at, updated_at, status, baseid, uploader, rev, version, acl)
+                    VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
+                    ON CONFLICT DO NOTHING
+                    RETURNING *;
+            ",
+        )
+        .bind(&self.guid)
         .bind(&self.filename)
         .bind(self.size)
         .bind(self.created_at)
@@ -1333,96 +2227,652 @@ impl File {
         .bind(&self.uploader)
         .bind(&self.rev)
         .bind(self.version)
-        .execute(&mut tx)
+        .bind(&self.acl)
+        .execute(&mut *tx)
+        .await;
+
+        if let Err(e) = insert_file {
+            warn!("Insert File Error: {:?}", e);
+            return Err(anyhow::anyhow!("Failed to insert file: {}", e));
+        }
+
+        // 插入 Hash
+        let insert_hash = sqlx::query(
+            "
+                INSERT INTO biominer_indexd_hash (hash, hash_type, file)
+                    VALUES ($1, $2, $3)
+                    ON CONFLICT DO NOTHING
+                    RETURNING *;
+            ",
+        )
+        .bind(hash)
+        .bind(hash_type)
+        .bind(&self.guid)
+        .execute(&mut *tx)
         .await;
 
-        if let Err(e) = insert_file {
-            warn!("Insert File Error: {:?}", e);
-            tx.rollback().await?;
-            return Err(anyhow::anyhow!("Failed to insert file: {}", e));
-        }
+        if let Err(e) = insert_hash {
+            warn!("Insert Hash Error: {:?}", e);
+            return Err(anyhow::anyhow!(
+                "The hash ({}) already exists or has been registered.",
+                hash
+            ));
+        }
+
+        // 插入 URL（如果有）
+        if let Some(u) = url {
+            let insert_url = sqlx::query(
+                "
+                    INSERT INTO biominer_indexd_url (file, url, uploader)
+                        VALUES ($1, $2, $3)
+                        ON CONFLICT DO NOTHING
+                        RETURNING *;
+                ",
+            )
+            .bind(&self.guid)
+            .bind(u)
+            .bind(&self.uploader)
+            .execute(&mut *tx)
+            .await;
+
+            if let Err(e) = insert_url {
+                warn!("Insert URL Error: {:?}", e);
+                    return Err(anyhow::anyhow!(
+                    "The URL ({}) already exists or has been registered.",
+                    u
+                ));
+            }
+        }
+
+        // 插入 Alias（如果有）
+        if let Some(a) = alias {
+            let insert_alias = sqlx::query(
+                "
+                    INSERT INTO biominer_indexd_alias (file, name)
+                        VALUES ($1, $2)
+                        ON CONFLICT DO NOTHING
+                        RETURNING *;
+                ",
+            )
+            .bind(&self.guid)
+            .bind(a)
+            .execute(&mut *tx)
+            .await;
+
+            if let Err(e) = insert_alias {
+                warn!("Insert Alias Error: {:?}", e);
+                    return Err(anyhow::anyhow!("Failed to insert alias: {}", e));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// The fields of a file to replace by `File::update`, the fields which are not set are kept.
+#[derive(Debug, Clone, Default)]
+pub struct FileUpdate {
+    pub filename: Option<String>,
+    // Some(None) makes the file public
+    pub acl: Option<Option<String>>,
+    // All the urls of the file, the other urls of the file are removed
+    pub urls: Option<Vec<String>>,
+    // The tags to add or replace
+    pub tags: Vec<(String, String)>,
+}
+
+/// The policies for registering the content which has been indexed, see `FileRegistration::add_with_policy`.
+pub const ON_CONFLICT_POLICIES: [&str; 3] = ["error", "return_existing", "new_version"];
+
+/// The guid in `FileError::Registered` when the caller cannot change the file which has registered the content.
+pub const HIDDEN_GUID: &str = "(hidden)";
+
+/// Whether the caller can change a file of the uploader with the acl, such as `User::can_write`.
+pub type WriteCheck<'a> = dyn Fn(&str, Option<&str>) -> bool + Sync + 'a;
+
+/// A file to be registered with all its hashes, urls, aliases and tags.
+#[derive(Debug, Clone)]
+pub struct FileRegistration {
+    pub file: File,
+    pub hash: String, // The primary hash of the file, the md5sum by default
+    pub hash_type: String,
+    pub hashes: Vec<(String, String)>, // Other hashes as (hash_type, hash), such as sha256
+    pub urls: Vec<String>,
+    pub aliases: Vec<String>,
+    pub tags: Vec<(String, String)>,
+}
+
+impl FileRegistration {
+    pub fn new(file: File, hash: &str) -> Self {
+        FileRegistration {
+            file,
+            hash: hash.to_string(),
+            hash_type: "md5".to_string(),
+            hashes: vec![],
+            urls: vec![],
+            aliases: vec![],
+            tags: vec![],
+        }
+    }
+
+    pub async fn add(&self, pool: &sqlx::PgPool) -> Result<(), anyhow::Error> {
+        let mut tx = pool.begin().await?;
+        self.insert(&mut tx).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Register the file as a new version of the file `id`, it inherits the baseid and gets an incremented version.
+    pub async fn add_version(
+        &mut self,
+        pool: &sqlx::PgPool,
+        id: &uuid::Uuid,
+    ) -> Result<(), anyhow::Error> {
+        let guid = File::gen_guid(id);
+        File::ensure_file_active(pool, &guid).await?;
+
+        let mut tx = pool.begin().await?;
+        self.insert_version(&mut tx, &guid).await?;
+        tx.commit().await?;
+        info!(
+            "Register the file {} as the version {} of {}",
+            self.file.guid, self.file.version, self.file.baseid
+        );
+        Ok(())
+    }
+
+    /// Insert the file as a new version of the file `guid` in the transaction.
+    async fn insert_version(
+        &mut self,
+        tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+        guid: &str,
+    ) -> Result<(), anyhow::Error> {
+        let baseid = sqlx::query_scalar::<_, String>(
+            "SELECT baseid FROM biominer_indexd_file WHERE guid = $1",
+        )
+        .bind(guid)
+        .fetch_one(&mut *tx)
+        .await?;
+
+        // Serialize the writers of the same baseid, otherwise they may get the same version
+        sqlx::query("SELECT pg_advisory_xact_lock(hashtext($1))")
+            .bind(&baseid)
+            .execute(&mut *tx)
+            .await?;
+
+        let version = sqlx::query_scalar::<_, i32>(
+            "SELECT COALESCE(MAX(version), 0) + 1 FROM biominer_indexd_file WHERE baseid = $1",
+        )
+        .bind(&baseid)
+        .fetch_one(&mut *tx)
+        .await?;
+
+        self.file.baseid = baseid;
+        self.file.version = version;
+        self.insert(tx).await
+    }
+
+    /// Register the file with a policy for the content which has been indexed, i.e. one of its hashes is owned by an active file.
+    ///
+    /// - `error`: reject the registration with `FileError::Registered`.
+    /// - `return_existing`: attach the urls and aliases to the existing file instead of registering a new one.
+    /// - `new_version`: register the file as a new version of the existing file, the versions share the hashes.
+    ///
+    /// The existing file must be writable by the caller, otherwise the registration is rejected whatever the policy,
+    /// and the guid of the existing file is hidden, see `HIDDEN_GUID`.
+    ///
+    /// # Returns
+    ///
+    /// Returns the guid of the registered or the existing file, and whether the file is newly registered.
+    pub async fn add_with_policy(
+        &mut self,
+        pool: &sqlx::PgPool,
+        on_conflict: &str,
+        writable: &WriteCheck<'_>,
+    ) -> Result<(String, bool), anyhow::Error> {
+        let hashes = self.content_hashes();
+
+        let mut tx = pool.begin().await?;
+        // Serialize the registrations of the same content, otherwise all of them may find no existing file
+        FileRegistration::lock_hashes(&mut tx, &hashes).await?;
+
+        let existing = sqlx::query_as::<_, (String, String, String, Option<String>)>(
+            "
+                SELECT h.hash, f.guid, f.uploader, f.acl FROM biominer_indexd_file f
+                    JOIN biominer_indexd_hash h ON h.file = f.guid
+                    WHERE h.hash = ANY($1) AND f.status != 'deleted'
+                    ORDER BY f.created_at, f.guid
+                    LIMIT 1;
+            ",
+        )
+        .bind(&hashes)
+        .fetch_optional(&mut tx)
+        .await?;
+
+        let (hash, guid) = match existing {
+            Some((hash, _, uploader, acl)) if !writable(&uploader, acl.as_deref()) => {
+                return Err(FileError::Registered {
+                    item: format!("hash {}", hash),
+                    guid: HIDDEN_GUID.to_string(),
+                }
+                .into());
+            }
+            Some((hash, guid, _, _)) => (hash, guid),
+            None => {
+                self.insert(&mut tx).await?;
+                tx.commit().await?;
+                return AnyOk((self.file.guid.clone(), true));
+            }
+        };
+
+        match on_conflict {
+            "return_existing" => {
+                self.attach(&mut tx, &guid).await?;
+                tx.commit().await?;
+                info!("The hash {} has been registered by the file {}, return it.", hash, guid);
+                AnyOk((guid, false))
+            }
+            "new_version" => {
+                // Still under the locks of the hashes, so the concurrent registrations see the new version
+                self.insert_version(&mut tx, &guid).await?;
+                tx.commit().await?;
+                info!(
+                    "Register the file {} as the version {} of {}",
+                    self.file.guid, self.file.version, self.file.baseid
+                );
+                AnyOk((self.file.guid.clone(), true))
+            }
+            _ => Err(FileError::Registered {
+                item: format!("hash {}", hash),
+                guid,
+            }
+            .into()),
+        }
+    }
+
+    /// All the hashes of the file, sorted and deduplicated.
+    fn content_hashes(&self) -> Vec<String> {
+        let mut hashes: Vec<String> = std::iter::once(&self.hash)
+            .chain(self.hashes.iter().map(|(_, h)| h))
+            .cloned()
+            .collect();
+        hashes.sort();
+        hashes.dedup();
+        hashes
+    }
+
+    /// Take the transaction-scoped locks of the hashes, the hashes must be sorted so the writers don't deadlock.
+    async fn lock_hashes(
+        tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+        hashes: &[String],
+    ) -> Result<(), anyhow::Error> {
+        for hash in hashes.iter() {
+            sqlx::query("SELECT pg_advisory_xact_lock(hashtext($1))")
+                .bind(hash)
+                .execute(&mut *tx)
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// The hashes which are owned by the active files.
+    async fn active_hashes(
+        tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+        hashes: &[String],
+    ) -> Result<HashSet<String>, anyhow::Error> {
+        let rows = sqlx::query_scalar::<_, String>(
+            "
+                SELECT h.hash FROM biominer_indexd_hash h
+                    JOIN biominer_indexd_file f ON h.file = f.guid
+                    WHERE h.hash = ANY($1) AND f.status != 'deleted';
+            ",
+        )
+        .bind(hashes)
+        .fetch_all(&mut *tx)
+        .await?;
+
+        AnyOk(rows.into_iter().collect())
+    }
+
+    /// Attach the urls and aliases to the existing file, the ones registered by the other files are rejected.
+    async fn attach(
+        &self,
+        tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+        guid: &str,
+    ) -> Result<(), anyhow::Error> {
+        if self.urls.is_empty() && self.aliases.is_empty() {
+            return Ok(());
+        }
+
+        File::bump_rev(tx, guid, None).await?;
+
+        let items = self
+            .urls
+            .iter()
+            .map(|u| ("url", u, "biominer_indexd_url", "url"))
+            .chain(
+                self.aliases
+                    .iter()
+                    .map(|a| ("alias", a, "biominer_indexd_alias", "name")),
+            );
+
+        for (item, value, table, column) in items {
+            let owner = sqlx::query_scalar::<_, String>(&format!(
+                "SELECT file FROM {table} WHERE {column} = $1",
+                table = table,
+                column = column
+            ))
+            .bind(value)
+            .fetch_optional(&mut *tx)
+            .await?;
+
+            match owner {
+                Some(owner) if owner == guid => continue,
+                Some(owner) => {
+                    return Err(FileError::Registered {
+                        item: format!("{} {}", item, value),
+                        guid: owner,
+                    }
+                    .into())
+                }
+                None => {}
+            }
+
+            if item == "url" {
+                sqlx::query(
+                    "INSERT INTO biominer_indexd_url (file, url, uploader) VALUES ($1, $2, $3)",
+                )
+                .bind(guid)
+                .bind(value)
+                .bind(&self.file.uploader)
+                .execute(&mut *tx)
+                .await?;
+            } else {
+                sqlx::query("INSERT INTO biominer_indexd_alias (file, name) VALUES ($1, $2)")
+                    .bind(guid)
+                    .bind(value)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+            info!("Attach {} {} to the file {}", item, value, guid);
+        }
+
+        Ok(())
+    }
+
+    /// Register a large number of files in batched transactions.
+    ///
+    /// All hashes, urls and aliases are checked against the database before inserting, so the conflicts are reported per file.
+    /// Each file is inserted in its own savepoint, a failed file doesn't affect the other files in the same batch.
+    /// A batch holds the locks of its hashes like `add_with_policy`, and the content is checked again under the locks.
+    /// The files whose content has been indexed are registered one by one with the `on_conflict` policy, see `add_with_policy`.
+    ///
+    /// # Returns
+    ///
+    /// Returns the result of each file in the same order as the registrations, or an `Err(Error)` if the database is unavailable.
+    /// A result is the guid of the registered or the existing file, and whether the file is newly registered.
+    pub async fn add_bulk(
+        pool: &sqlx::PgPool,
+        registrations: &[FileRegistration],
+        batch_size: usize,
+        on_conflict: &str,
+        writable: &WriteCheck<'_>,
+    ) -> Result<Vec<Result<(String, bool), anyhow::Error>>, anyhow::Error> {
+        let hashes: Vec<String> = registrations
+            .iter()
+            .flat_map(|r| {
+                std::iter::once(&r.hash)
+                    .chain(r.hashes.iter().map(|(_, h)| h))
+                    .cloned()
+            })
+            .collect();
+        let urls: Vec<String> = registrations.iter().flat_map(|r| r.urls.clone()).collect();
+        let aliases: Vec<String> = registrations
+            .iter()
+            .flat_map(|r| r.aliases.clone())
+            .collect();
+
+        let registered_hashes =
+            FileRegistration::fetch_registered(pool, "biominer_indexd_hash", "hash", &hashes).await?;
+        let registered_urls =
+            FileRegistration::fetch_registered(pool, "biominer_indexd_url", "url", &urls).await?;
+        let registered_aliases =
+            FileRegistration::fetch_registered(pool, "biominer_indexd_alias", "name", &aliases)
+                .await?;
+
+        // The files whose content has been indexed, they are left to the policy.
+        let mut indexed: Vec<usize> = vec![];
+        let mut results: Vec<Result<(String, bool), anyhow::Error>> = registrations
+            .iter()
+            .enumerate()
+            .map(|(idx, r)| {
+                let mut hashes = std::iter::once(&r.hash).chain(r.hashes.iter().map(|(_, h)| h));
+                if hashes.any(|h| registered_hashes.contains_key(h)) {
+                    indexed.push(idx);
+                    return AnyOk((r.file.guid.clone(), true));
+                }
+
+                let checks = r
+                    .urls
+                    .iter()
+                    .map(|u| ("url", u, &registered_urls))
+                    .chain(r.aliases.iter().map(|a| ("alias", a, &registered_aliases)));
+
+                for (item, value, registered) in checks {
+                    if let Some(guid) = registered.get(value) {
+                        return Err(FileError::Registered {
+                            item: format!("{} {}", item, value),
+                            guid: guid.clone(),
+                        }
+                        .into());
+                    }
+                }
+
+                AnyOk((r.file.guid.clone(), true))
+            })
+            .collect();
+
+        let pending: Vec<usize> = (0..registrations.len())
+            .filter(|idx| results[*idx].is_ok() && !indexed.contains(idx))
+            .collect();
+
+        for chunk in pending.chunks(batch_size.max(1)) {
+            let mut tx = pool.begin().await?;
+            let mut hashes: Vec<String> = chunk
+                .iter()
+                .flat_map(|idx| registrations[*idx].content_hashes())
+                .collect();
+            hashes.sort();
+            hashes.dedup();
+            FileRegistration::lock_hashes(&mut tx, &hashes).await?;
+
+            // The content indexed since the check, also by the earlier files of the batch, is left to the policy
+            let mut active = FileRegistration::active_hashes(&mut tx, &hashes).await?;
+            for idx in chunk {
+                let hashes = registrations[*idx].content_hashes();
+                if hashes.iter().any(|h| active.contains(h)) {
+                    indexed.push(*idx);
+                    continue;
+                }
+
+                let mut savepoint = (&mut tx).begin().await?;
+                match registrations[*idx].insert(&mut savepoint).await {
+                    Ok(()) => {
+                        savepoint.commit().await?;
+                        active.extend(hashes);
+                    }
+                    Err(e) => {
+                        savepoint.rollback().await?;
+                        let registered = if is_unique_violation(&e) {
+                            registrations[*idx].find_registered(&mut tx).await?
+                        } else {
+                            None
+                        };
+                        results[*idx] = Err(registered.map(AnyError::from).unwrap_or(e));
+                    }
+                }
+            }
+
+            if let Err(e) = tx.commit().await {
+                warn!("Failed to commit the batch: {}", e);
+                for idx in chunk {
+                    if results[*idx].is_ok() {
+                        results[*idx] = Err(anyhow::anyhow!("Failed to commit the batch: {}", e));
+                    }
+                }
+            }
+        }
+
+        for idx in indexed {
+            let mut registration = registrations[idx].clone();
+            results[idx] = match registration.add_with_policy(pool, on_conflict, writable).await {
+                Ok(result) => Ok(result),
+                Err(e) if is_unique_violation(&e) => {
+                    let mut tx = pool.begin().await?;
+                    let registered = registration.find_registered(&mut tx).await?;
+                    Err(registered.map(AnyError::from).unwrap_or(e))
+                }
+                Err(e) => Err(e),
+            };
+        }
+
+        info!(
+            "Register {} files in bulk, {} failed.",
+            registrations.len(),
+            results.iter().filter(|r| r.is_err()).count()
+        );
+        AnyOk(results)
+    }
+
+    /// Find the url or alias which has been registered by another file, e.g. by a concurrent registration.
+    async fn find_registered(
+        &self,
+        tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    ) -> Result<Option<FileError>, anyhow::Error> {
+        let items = [
+            ("url", "biominer_indexd_url", "url", &self.urls),
+            ("alias", "biominer_indexd_alias", "name", &self.aliases),
+        ];
+
+        for (item, table, column, values) in items {
+            if values.is_empty() {
+                continue;
+            }
+
+            let owner = sqlx::query_as::<_, (String, String)>(&format!(
+                "SELECT {column}, file FROM {table} WHERE {column} = ANY($1) LIMIT 1",
+                column = column,
+                table = table
+            ))
+            .bind(values)
+            .fetch_optional(&mut *tx)
+            .await?;
+
+            if let Some((value, guid)) = owner {
+                return AnyOk(Some(FileError::Registered {
+                    item: format!("{} {}", item, value),
+                    guid,
+                }));
+            }
+        }
+
+        AnyOk(None)
+    }
+
+    /// Get the files which the values have been registered to, e.g. hash -> guid.
+    async fn fetch_registered(
+        pool: &sqlx::PgPool,
+        table: &str,
+        column: &str,
+        values: &[String],
+    ) -> Result<HashMap<String, String>, anyhow::Error> {
+        if values.is_empty() {
+            return AnyOk(HashMap::new());
+        }
+
+        let sql_str = format!(
+            "SELECT {column}, file FROM {table} WHERE {column} = ANY($1)",
+            column = column,
+            table = table
+        );
+        let rows = sqlx::query_as::<_, (String, String)>(&sql_str)
+            .bind(values)
+            .fetch_all(pool)
+            .await?;
+
+        AnyOk(rows.into_iter().collect())
+    }
+
+    async fn insert(
+        &self,
+        tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    ) -> Result<(), anyhow::Error> {
+        let guid = &self.file.guid;
+        self.file
+            .insert(tx, &self.hash_type, &self.hash, None, None)
+            .await?;
 
-        // 插入 Hash
-        let insert_hash = sqlx::query(
-            "
-                INSERT INTO biominer_indexd_hash (hash, hash_type, file)
-                    VALUES ($1, $2, $3)
-                    ON CONFLICT DO NOTHING
-                    RETURNING *;
-            ",
-        )
-        .bind(hash)
-        .bind("md5") // 默认 hash_type
-        .bind(&self.guid)
-        .execute(&mut tx)
-        .await;
+        // The items registered by the other files in the meantime fail the registration instead of being dropped
+        let mut hashes = vec![&self.hash];
+        for (hash_type, hash) in &self.hashes {
+            if hashes.contains(&hash) {
+                continue;
+            }
+            hashes.push(hash);
 
-        if let Err(e) = insert_hash {
-            warn!("Insert Hash Error: {:?}", e);
-            tx.rollback().await?;
-            return Err(anyhow::anyhow!(
-                "The hash ({}) already exists or has been registered.",
-                hash
-            ));
+            sqlx::query(
+                "INSERT INTO biominer_indexd_hash (hash, hash_type, file) VALUES ($1, $2, $3)",
+            )
+            .bind(hash)
+            .bind(hash_type)
+            .bind(guid)
+            .execute(&mut *tx)
+            .await?;
         }
 
-        // 插入 URL（如果有）
-        if let Some(u) = url {
-            let insert_url = sqlx::query(
-                "
-                    INSERT INTO biominer_indexd_url (file, url, uploader)
-                        VALUES ($1, $2, $3)
-                        ON CONFLICT DO NOTHING
-                        RETURNING *;
-                ",
-            )
-            .bind(&self.guid)
-            .bind(u)
-            .bind(&self.uploader)
-            .execute(&mut tx)
-            .await;
+        for url in &self.urls {
+            sqlx::query("INSERT INTO biominer_indexd_url (file, url, uploader) VALUES ($1, $2, $3)")
+                .bind(guid)
+                .bind(url)
+                .bind(&self.file.uploader)
+                .execute(&mut *tx)
+                .await?;
+        }
 
-            if let Err(e) = insert_url {
-                warn!("Insert URL Error: {:?}", e);
-                tx.rollback().await?;
-                return Err(anyhow::anyhow!(
-                    "The URL ({}) already exists or has been registered.",
-                    u
-                ));
-            }
+        for alias in &self.aliases {
+            sqlx::query("INSERT INTO biominer_indexd_alias (file, name) VALUES ($1, $2)")
+                .bind(guid)
+                .bind(alias)
+                .execute(&mut *tx)
+                .await?;
         }
 
-        // 插入 Alias（如果有）
-        if let Some(a) = alias {
-            let insert_alias = sqlx::query(
+        for (field_name, field_value) in &self.tags {
+            sqlx::query(
                 "
-                    INSERT INTO biominer_indexd_alias (file, name)
-                        VALUES ($1, $2)
-                        ON CONFLICT DO NOTHING
-                        RETURNING *;
+                    INSERT INTO biominer_indexd_tag (file, field_name, field_value)
+                        VALUES ($1, $2, $3)
+                        ON CONFLICT (file, field_name)
+                        DO UPDATE SET field_value = EXCLUDED.field_value;
                 ",
             )
-            .bind(&self.guid)
-            .bind(a)
-            .execute(&mut tx)
-            .await;
-
-            if let Err(e) = insert_alias {
-                warn!("Insert Alias Error: {:?}", e);
-                tx.rollback().await?;
-                return Err(anyhow::anyhow!("Failed to insert alias: {}", e));
-            }
+            .bind(guid)
+            .bind(field_name)
+            .bind(field_value)
+            .execute(&mut *tx)
+            .await?;
         }
 
-        // 提交事务
-        tx.commit().await?;
         Ok(())
     }
 }
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use super::*;
+    use crate::model::import::{self, ImportRecord};
+    use crate::model::upload::tests::s3_config;
+    use crate::model::upload::{PendingUpload, Upload};
     use crate::connect_db;
     use crate::init_logger;
     use crate::run_migrations;
@@ -1431,7 +2881,7 @@ mod tests {
     use std::env;
     use std::time::Duration;
 
-    async fn init() -> (PostgreSQL, sqlx::PgPool) {
+    pub async fn init() -> (PostgreSQL, sqlx::PgPool) {
         let _ = init_logger("test", LevelFilter::Debug);
         let mut settings = Settings::default();
         settings.version = VersionReq::parse("17.5.0").unwrap();
@@ -1501,7 +2951,7 @@ mod tests {
             .await
             .expect("Failed to insert test data");
 
-        (postgres.clone(), pool)
+        (postgres, pool)
     }
 
     #[tokio::test]
@@ -1555,6 +3005,12 @@ mod tests {
     #[tokio::test]
     async fn test_file_operations() {
         let (_postgres, pool) = init().await;
+        // The urls and the aliases are unique, the database is kept between the runs
+        let suffix = uuid::Uuid::new_v4().to_simple().to_string();
+        let url = format!("http://example.com/test2-{}.txt", suffix);
+        let alt_url = format!("http://example.com/test2_alt-{}.txt", suffix);
+        let alias2 = format!("test_alias2-{}", suffix);
+        let alias3 = format!("test_alias3-{}", suffix);
 
         // Create a new file
         let mut file = File::new("test2.txt", 2048, "test_user", "fudan-pgx");
@@ -1563,8 +3019,8 @@ mod tests {
         file.add(
             &pool,
             "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855", // sha256 hash
-            Some("http://example.com/test2.txt"),
-            Some("test_alias2"),
+            Some(&url),
+            Some(&alias2),
         )
         .await
         .unwrap();
@@ -1573,9 +3029,10 @@ mod tests {
         File::add_url(
             &pool,
             &uuid::Uuid::parse_str(&file.guid.split("/").last().unwrap()).unwrap(),
-            "http://example.com/test2_alt.txt",
+            &alt_url,
             "test_user",
             "validated",
+            None,
         )
         .await
         .unwrap();
@@ -1584,7 +3041,8 @@ mod tests {
         File::add_alias(
             &pool,
             &uuid::Uuid::parse_str(&file.guid.split("/").last().unwrap()).unwrap(),
-            "test_alias3",
+            &alias3,
+            None,
         )
         .await
         .unwrap();
@@ -1595,6 +3053,7 @@ mod tests {
             &uuid::Uuid::parse_str(&file.guid.split("/").last().unwrap()).unwrap(),
             "test_tag",
             "test_value",
+            None,
         )
         .await
         .unwrap();
@@ -1608,16 +3067,16 @@ mod tests {
         // Verify URLs
         let urls: Vec<URL> = serde_json::from_value(queried_file.urls.unwrap()).unwrap();
         assert_eq!(urls.len(), 2);
-        assert!(urls.iter().any(|u| u.url == "http://example.com/test2.txt"));
+        assert!(urls.iter().any(|u| u.url == url));
         assert!(urls
             .iter()
-            .any(|u| u.url == "http://example.com/test2_alt.txt"));
+            .any(|u| u.url == alt_url));
 
         // Verify aliases
         let aliases: Vec<Alias> = serde_json::from_value(queried_file.aliases.unwrap()).unwrap();
         assert_eq!(aliases.len(), 2);
-        assert!(aliases.iter().any(|a| a.name == "test_alias2"));
-        assert!(aliases.iter().any(|a| a.name == "test_alias3"));
+        assert!(aliases.iter().any(|a| a.name == alias2));
+        assert!(aliases.iter().any(|a| a.name == alias3));
 
         // Verify tags
         let tags: Vec<Tag> = serde_json::from_value(queried_file.tags.unwrap()).unwrap();
@@ -1625,4 +3084,746 @@ mod tests {
         assert_eq!(tags[0].field_name, "test_tag");
         assert_eq!(tags[0].field_value, "test_value");
     }
+
+    #[tokio::test]
+    async fn test_add_url_registered_by_other_file() {
+        let (_postgres, pool) = init().await;
+
+        let url = format!("http://example.com/{}.txt", uuid::Uuid::new_v4());
+        let mut owner = File::new("test_url_owner.txt", 128, "test_user", "fudan-pgx");
+        let hash = uuid::Uuid::new_v4().to_simple().to_string();
+        owner.add(&pool, &hash, Some(&url), None).await.unwrap();
+
+        let mut other = File::new("test_url_other.txt", 128, "test_user", "fudan-pgx");
+        let hash = uuid::Uuid::new_v4().to_simple().to_string();
+        other.add(&pool, &hash, None, None).await.unwrap();
+        let id = uuid::Uuid::parse_str(other.guid.split("/").last().unwrap()).unwrap();
+
+        let err = File::add_url(&pool, &id, &url, "test_user", "validated", None)
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<FileError>(),
+            Some(FileError::Registered { guid, .. }) if *guid == owner.guid
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_add_alias_registered_by_other_file() {
+        let (_postgres, pool) = init().await;
+
+        let alias = format!("alias-{}", uuid::Uuid::new_v4());
+        let mut owner = File::new("test_alias_owner.txt", 128, "test_user", "fudan-pgx");
+        let hash = uuid::Uuid::new_v4().to_simple().to_string();
+        owner.add(&pool, &hash, None, Some(&alias)).await.unwrap();
+        let owner_id = uuid::Uuid::parse_str(owner.guid.split("/").last().unwrap()).unwrap();
+
+        let mut other = File::new("test_alias_other.txt", 128, "test_user", "fudan-pgx");
+        let hash = uuid::Uuid::new_v4().to_simple().to_string();
+        other.add(&pool, &hash, None, None).await.unwrap();
+        let id = uuid::Uuid::parse_str(other.guid.split("/").last().unwrap()).unwrap();
+
+        // The rev of the file is kept when the alias is rejected
+        let rev = File::get_file(&pool, &id).await.unwrap().rev;
+        let err = File::add_alias(&pool, &id, &alias, None).await.unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<FileError>(),
+            Some(FileError::Registered { guid, .. }) if *guid == owner.guid
+        ));
+        assert_eq!(File::get_file(&pool, &id).await.unwrap().rev, rev);
+
+        // Adding the alias to its own file again is fine
+        assert!(File::add_alias(&pool, &owner_id, &alias, None).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_delete_file() {
+        let (_postgres, pool) = init().await;
+
+        // Soft delete keeps the record and marks it as deleted
+        let mut file = File::new("test_delete.txt", 512, "test_user", "fudan-pgx");
+        let hash = uuid::Uuid::new_v4().to_simple().to_string();
+        file.add(&pool, &hash, None, None).await.unwrap();
+        let id = uuid::Uuid::parse_str(file.guid.split("/").last().unwrap()).unwrap();
+
+        File::delete_file(&pool, &id, "test_user", Some("duplicated"))
+            .await
+            .unwrap();
+        let deleted = File::query_file(&pool, "guid", &file.guid).await.unwrap();
+        assert_eq!(deleted.status, "deleted");
+        assert!(File::add_alias(&pool, &id, "test_alias_deleted", None)
+            .await
+            .is_err());
+
+        let tombstone = File::get_tombstone(&pool, &id).await.unwrap().unwrap();
+        assert_eq!(tombstone.reason, Some("duplicated".to_string()));
+        assert!(!tombstone.purged);
+        let history = File::get_status_history(&pool, &id).await.unwrap();
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].to_status, "deleted");
+
+        // Purge removes the file, its hashes and its upload, but the tombstone remains
+        let key = format!("{}/test.txt", id);
+        let config = s3_config("http://127.0.0.1:9000");
+        let upload = Upload { protocol: "minio", config: &config, key: &key };
+        PendingUpload::new(&file.guid, &upload, None, "test_user").save(&pool).await.unwrap();
+        File::purge_file(&pool, &id, "biominer-admin", None)
+            .await
+            .unwrap();
+        assert!(File::query_file(&pool, "guid", &file.guid).await.is_err());
+        assert!(!File::check_hash_exists(&pool, &hash).await.unwrap());
+        assert!(PendingUpload::get(&pool, &file.guid, &key, None).await.is_err());
+
+        let tombstone = File::get_tombstone(&pool, &id).await.unwrap().unwrap();
+        assert!(tombstone.purged);
+    }
+
+    #[tokio::test]
+    async fn test_file_versions() {
+        let (_postgres, pool) = init().await;
+
+        let mut file = File::new("test_version.txt", 1024, "test_user", "fudan-pgx");
+        let hash = uuid::Uuid::new_v4().to_simple().to_string();
+        file.add(&pool, &hash, None, None).await.unwrap();
+        let id = uuid::Uuid::parse_str(file.guid.split("/").last().unwrap()).unwrap();
+
+        // Each new version inherits the baseid and gets an incremented version
+        let mut guids = vec![file.guid.clone()];
+        for i in 2..4 {
+            let mut new_file = File::new("test_version.txt", 1024 * i, "test_user", "fudan-pgx");
+            let hash = uuid::Uuid::new_v4().to_simple().to_string();
+            new_file
+                .add_version(&pool, &id, &hash, None, None)
+                .await
+                .unwrap();
+            assert_eq!(new_file.baseid, file.baseid);
+            assert_eq!(new_file.version, i as i32);
+            guids.push(new_file.guid.clone());
+        }
+
+        let versions = File::get_versions(&pool, &file.baseid).await.unwrap();
+        assert_eq!(
+            versions.iter().map(|f| f.guid.clone()).collect::<Vec<_>>(),
+            guids
+        );
+        assert_eq!(
+            versions.iter().map(|f| f.version).collect::<Vec<_>>(),
+            vec![1, 2, 3]
+        );
+
+        // The deleted version is skipped when resolving the latest version
+        let latest = File::get_latest_version(&pool, &file.baseid).await.unwrap();
+        assert_eq!(latest.version, 3);
+        let latest_id = uuid::Uuid::parse_str(latest.guid.split("/").last().unwrap()).unwrap();
+        File::delete_file(&pool, &latest_id, "test_user", None)
+            .await
+            .unwrap();
+        let latest = File::get_latest_version(&pool, &file.baseid).await.unwrap();
+        assert_eq!(latest.version, 2);
+    }
+
+    #[tokio::test]
+    async fn test_file_rev() {
+        let (_postgres, pool) = init().await;
+
+        let mut file = File::new("test_rev.txt", 256, "test_user", "fudan-pgx");
+        let hash = uuid::Uuid::new_v4().to_simple().to_string();
+        file.add(&pool, &hash, None, None).await.unwrap();
+        let id = uuid::Uuid::parse_str(file.guid.split("/").last().unwrap()).unwrap();
+
+        // Each mutation rotates the rev
+        let rev = File::add_tag(&pool, &id, "test_tag", "v1", Some(&file.rev))
+            .await
+            .unwrap();
+        assert_ne!(rev, file.rev);
+        let queried_file = File::query_file(&pool, "guid", &file.guid).await.unwrap();
+        assert_eq!(queried_file.rev, rev);
+        assert!(queried_file.updated_at >= file.updated_at);
+
+        // The stale writer is rejected and nothing is changed
+        let err = File::add_tag(&pool, &id, "test_tag", "v2", Some(&file.rev))
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<FileError>(),
+            Some(FileError::RevMismatch { .. })
+        ));
+        let queried_file = File::query_file(&pool, "guid", &file.guid).await.unwrap();
+        let tags: Vec<Tag> = serde_json::from_value(queried_file.tags.unwrap()).unwrap();
+        assert_eq!(tags[0].field_value, "v1");
+        assert_eq!(queried_file.rev, rev);
+
+        // Without the precondition, the last writer wins
+        let new_rev = File::delete_tag(&pool, &id, "test_tag", None)
+            .await
+            .unwrap();
+        assert_ne!(new_rev, rev);
+    }
+
+    #[tokio::test]
+    async fn test_update_file() {
+        let (_postgres, pool) = init().await;
+
+        let hash = uuid::Uuid::new_v4().to_simple().to_string();
+        let alias = format!("dg.TEST/{}", hash);
+        let mut file = File::new("test_update.txt", 128, "test_user", "fudan-pgx");
+        file.add(&pool, &hash, Some("s3://bucket/old.txt"), Some(&alias))
+            .await
+            .unwrap();
+        let id = uuid::Uuid::parse_str(file.guid.split("/").last().unwrap()).unwrap();
+
+        let update = FileUpdate {
+            filename: Some("renamed.txt".to_string()),
+            acl: Some(Some("phs000001".to_string())),
+            urls: Some(vec![format!("s3://bucket/{}.txt", hash)]),
+            tags: vec![("authz".to_string(), "/programs/test".to_string())],
+        };
+        let rev = File::update(&pool, &id, &update, "test_user", Some(&file.rev))
+            .await
+            .unwrap();
+        assert_ne!(rev, file.rev);
+
+        let queried_file = File::get_file_with_alias(&pool, &alias).await.unwrap();
+        assert_eq!(queried_file.guid, file.guid);
+        assert_eq!(queried_file.filename, "renamed.txt");
+        assert_eq!(queried_file.acl.as_deref(), Some("phs000001"));
+        let urls: Vec<URL> = serde_json::from_value(queried_file.urls.unwrap()).unwrap();
+        assert_eq!(urls.len(), 1);
+        assert_eq!(urls[0].url, format!("s3://bucket/{}.txt", hash));
+
+        // The stale writer is rejected
+        let err = File::update(&pool, &id, &FileUpdate::default(), "test_user", Some(&file.rev))
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<FileError>(),
+            Some(FileError::RevMismatch { .. })
+        ));
+
+        // The url of the other file is rejected
+        let update = FileUpdate {
+            urls: Some(vec!["http://example.com/test.txt".to_string()]),
+            ..Default::default()
+        };
+        let err = File::update(&pool, &id, &update, "test_user", Some(&rev))
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<FileError>(),
+            Some(FileError::Registered { .. })
+        ));
+
+        // The aliases are paged by name
+        let aliases = fetch_aliases(&pool, None, 1024, None).await.unwrap();
+        assert!(aliases.contains(&alias));
+        let aliases = fetch_aliases(&pool, Some(&alias), 1, None).await.unwrap();
+        assert!(aliases.iter().all(|a| a > &alias));
+
+        // The alias of the private file is only listed for its group
+        let before = &alias[..alias.len() - 1];
+        let cases = [
+            (Some(""), false),
+            (Some("phs000002,phs000001"), true),
+            (None, true),
+        ];
+        for (groups, listed) in cases {
+            let aliases = fetch_aliases(&pool, Some(before), 1, groups).await.unwrap();
+            assert_eq!(aliases.contains(&alias), listed);
+        }
+
+        // The guids are paged by guid
+        let filter = QueryFilter::new("", "", "", "", "", "", "", "", "", "")
+            .with_guid_after(Some(&file.guid));
+        let files = RecordResponse::<File>::query_files(&pool, filter, 1, 1024, false, false, false)
+            .await
+            .unwrap();
+        assert!(files.records.iter().all(|f| f.guid > file.guid));
+
+        // The private file is hidden from the callers without its group
+        for (groups, total) in [(Some(""), 0), (Some("phs000002, phs000001"), 1), (None, 1)] {
+            let filter = QueryFilter::new(&file.guid, "", "", "", "", "", "", "", "", "")
+                .with_auth_groups(groups);
+            let files = RecordResponse::<File>::query_files(&pool, filter, 1, 10, false, false, false)
+                .await
+                .unwrap();
+            assert_eq!(files.total, total);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_update_acl() {
+        let (_postgres, pool) = init().await;
+
+        let uploader = uuid::Uuid::new_v4().to_simple().to_string();
+        let mut files = vec![];
+        for i in 0..2 {
+            let mut file = File::new(&format!("test_acl_{}.txt", i), 64, &uploader, "fudan-pgx");
+            let hash = uuid::Uuid::new_v4().to_simple().to_string();
+            file.add(&pool, &hash, None, None).await.unwrap();
+            files.push(file);
+        }
+
+        let filter = QueryFilter::new("", "", "", "", &uploader, "", "", "", "", "");
+        let acl = "admin,/programs/pgx/projects/quartet";
+        assert_eq!(update_acl(&pool, &filter, Some(acl)).await.unwrap(), 2);
+        for file in files.iter() {
+            let queried_file = File::query_file(&pool, "guid", &file.guid).await.unwrap();
+            assert_eq!(queried_file.acl.as_deref(), Some(acl));
+            assert_eq!(queried_file.access, "private");
+            assert_ne!(queried_file.rev, file.rev);
+        }
+
+        // A resource path grants its children
+        for (groups, total) in [
+            ("/programs/pgx", 2),
+            ("/programs/pgx/projects/quartet", 2),
+            ("/", 2),
+            ("admin", 2),
+            ("/programs/pg,/programs/pgx/projects/quartet/a", 0),
+            ("", 0),
+        ] {
+            let filter = QueryFilter::new("", "", "", "", &uploader, "", "", "", "", "")
+                .with_auth_groups(Some(groups));
+            let (_, count) = fetch_guid_page(&pool, &filter, 1, 10).await.unwrap();
+            assert_eq!(count, total, "{}", groups);
+        }
+
+        assert_eq!(update_acl(&pool, &filter, None).await.unwrap(), 2);
+        let queried_file = File::query_file(&pool, "guid", &files[0].guid).await.unwrap();
+        assert_eq!(queried_file.access, "public");
+    }
+
+    #[tokio::test]
+    async fn test_resolve_file() {
+        let (_postgres, pool) = init().await;
+
+        let mut file = File::new("test_resolve.txt", 64, "test_user", "fudan-pgx");
+        let hash = uuid::Uuid::new_v4().to_simple().to_string();
+        let alias = format!("doi://10.1234/{}", hash);
+        file.add(&pool, &hash, None, Some(&alias)).await.unwrap();
+        let id = file.guid.split("/").last().unwrap().to_string();
+
+        for key in [&file.guid, &id, &hash, &alias] {
+            let resolved = File::resolve(&pool, key).await.unwrap();
+            assert_eq!(resolved.guid, file.guid);
+        }
+
+        // The hash lookup goes through the hash table
+        let resolved = File::get_file_with_hash(&pool, &hash).await.unwrap();
+        assert_eq!(resolved.guid, file.guid);
+
+        // An alias which looks like a crc32 hash
+        let hex_alias = hash[..8].to_string();
+        let uid = uuid::Uuid::parse_str(&id).unwrap();
+        File::add_alias(&pool, &uid, &hex_alias, None).await.unwrap();
+        let resolved = File::resolve(&pool, &hex_alias).await.unwrap();
+        assert_eq!(resolved.guid, file.guid);
+
+        assert!(File::resolve(&pool, "no-such-alias").await.is_err());
+        assert!(File::resolve(&pool, "").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_import_records() {
+        let (_postgres, pool) = init().await;
+
+        let mut records = vec![];
+        for i in 0..3 {
+            let file = File::new(&format!("test_import_{}.txt", i), 32, "test_user", "fudan-pgx");
+            let hash = uuid::Uuid::new_v4().to_simple().to_string();
+            records.push(ImportRecord {
+                source: "test_import.tsv".to_string(),
+                line: i + 2,
+                urls: vec![URL {
+                    id: 0,
+                    url: format!("minio://test-import/{}.txt", hash),
+                    created_at: file.created_at,
+                    status: "pending".to_string(),
+                    uploader: "".to_string(),
+                    file: None,
+                    reason: None,
+                    checked_at: None,
+                }],
+                hashes: vec![Hash {
+                    id: 0,
+                    hash_type: "md5".to_string(),
+                    hash: hash.clone(),
+                    file: None,
+                }],
+                aliases: vec![Alias {
+                    id: 0,
+                    name: format!("test_import\t{}", hash),
+                    file: None,
+                }],
+                tags: vec![],
+                file,
+            });
+        }
+        assert!(import::check_records(&records).is_empty());
+        assert!(import::check_registered(&pool, &records).await.unwrap().is_empty());
+
+        let stats = import::import_records(&pool, &records, 2).await.unwrap();
+        assert_eq!(stats.files, 3);
+        assert_eq!(stats.aliases, 3);
+
+        let file = File::resolve(&pool, &records[2].aliases[0].name).await.unwrap();
+        assert_eq!(file.guid, records[2].file.guid);
+        let urls: Vec<URL> = serde_json::from_value(file.urls.unwrap()).unwrap();
+        assert_eq!(urls[0].uploader, "test_user");
+
+        // All of them have been registered now, except the hashes which can be shared
+        let errors = import::check_registered(&pool, &records).await.unwrap();
+        assert_eq!(errors.len(), 9);
+        assert!(import::import_records(&pool, &records[..1], 2).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_purge_files() {
+        let (_postgres, pool) = init().await;
+
+        let uploader = format!("purge_{}", &uuid::Uuid::new_v4().to_simple().to_string()[..8]);
+        let mut guids = vec![];
+        for status in ["failed", "failed", "validated"] {
+            let mut file = File::new("test_purge.txt", 16, &uploader, "fudan-pgx");
+            file.status = status.to_string();
+            let hash = uuid::Uuid::new_v4().to_simple().to_string();
+            let url = format!("minio://test-purge/{}.txt", hash);
+            file.add(&pool, &hash, Some(&url), None).await.unwrap();
+            guids.push(file.guid);
+        }
+
+        let key = format!("{}/test_purge.txt", guids[0]);
+        let config = s3_config("http://127.0.0.1:9000");
+        let upload = Upload { protocol: "minio", config: &config, key: &key };
+        PendingUpload::new(&guids[0], &upload, None, &uploader).save(&pool).await.unwrap();
+
+        let filter = QueryFilter::new("", "", "", "failed", &uploader, "", "", "", "", "");
+        assert_eq!(purge_files(&pool, &filter).await.unwrap(), 2);
+        assert!(File::query_file(&pool, "guid", &guids[0]).await.is_err());
+        assert!(PendingUpload::get(&pool, &guids[0], &key, None).await.is_err());
+        assert!(File::query_file(&pool, "guid", &guids[2]).await.is_ok());
+        // No tombstone is left, so the guid can be registered again.
+        let id = uuid::Uuid::parse_str(guids[0].split("/").last().unwrap()).unwrap();
+        assert!(File::get_tombstone(&pool, &id).await.unwrap().is_none());
+
+        // The soft deleted files are removed with their tombstones and status history.
+        let id = uuid::Uuid::parse_str(guids[2].split("/").last().unwrap()).unwrap();
+        File::delete_file(&pool, &id, &uploader, None).await.unwrap();
+        let filter = QueryFilter::new("", "", "", "", &uploader, "", "", "", "", "");
+        assert_eq!(purge_files(&pool, &filter).await.unwrap(), 0);
+        assert_eq!(purge_files(&pool, &filter.with_deleted(true)).await.unwrap(), 1);
+        assert!(File::get_tombstone(&pool, &id).await.unwrap().is_none());
+        assert!(File::get_status_history(&pool, &id).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_add_bulk() {
+        let (_postgres, pool) = init().await;
+
+        let registered_hash = uuid::Uuid::new_v4().to_simple().to_string();
+        let mut file = File::new("test_bulk_0.txt", 128, "test_user", "fudan-pgx");
+        file.add(&pool, &registered_hash, None, None).await.unwrap();
+
+        let mut registrations = vec![];
+        for i in 1..4 {
+            let mut file = File::new(&format!("test_bulk_{}.txt", i), 128, "test_user", "fudan-pgx");
+            file.acl = Some("fudan-pgx".to_string());
+            let hash = uuid::Uuid::new_v4().to_simple().to_string();
+            let mut registration = FileRegistration::new(file, &hash);
+            registration.urls = vec![format!("node://test-bulk/{}/{}.txt", hash, i)];
+            registration.tags = vec![("project_name".to_string(), "Quartet".to_string())];
+            registrations.push(registration);
+        }
+        // The hash has been registered by another file
+        registrations[1].hash = registered_hash;
+        registrations[2].hashes = vec![(
+            "sha256".to_string(),
+            format!("{}{}", uuid::Uuid::new_v4().to_simple(), uuid::Uuid::new_v4().to_simple()),
+        )];
+
+        let results = FileRegistration::add_bulk(&pool, &registrations, 2, "error", &|_, _| true)
+            .await
+            .unwrap();
+        assert_eq!(results.len(), 3);
+        assert!(results[0].is_ok());
+        assert!(matches!(
+            results[1].as_ref().unwrap_err().downcast_ref::<FileError>(),
+            Some(FileError::Registered { .. })
+        ));
+        assert!(results[2].is_ok());
+
+        let queried_file = File::query_file(&pool, "guid", &registrations[2].file.guid)
+            .await
+            .unwrap();
+        assert_eq!(queried_file.access, "private");
+        let urls: Vec<URL> = serde_json::from_value(queried_file.urls.unwrap()).unwrap();
+        assert_eq!(urls[0].url, registrations[2].urls[0]);
+        let tags: Vec<Tag> = serde_json::from_value(queried_file.tags.unwrap()).unwrap();
+        assert_eq!(tags[0].field_value, "Quartet");
+        // The file can be found by any of its hashes
+        let (hash_type, hash) = &registrations[2].hashes[0];
+        let found = File::get_file_with_hash(&pool, hash).await.unwrap();
+        assert_eq!(found.guid, registrations[2].file.guid);
+        let hashes: Vec<Hash> = serde_json::from_value(found.hashes.unwrap()).unwrap();
+        assert!(hashes.iter().any(|h| &h.hash_type == hash_type && &h.hash == hash));
+        assert!(File::query_file(&pool, "guid", &registrations[1].file.guid)
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_add_bulk_registered_after_check() {
+        let (_postgres, pool) = init().await;
+
+        // Both urls pass the check, the second one is registered by the first file when it is inserted
+        let url = format!("node://test-bulk/{}.txt", uuid::Uuid::new_v4());
+        let mut registrations = vec![];
+        for i in 0..2 {
+            let file = File::new(&format!("test_bulk_race_{}.txt", i), 128, "test_user", "fudan-pgx");
+            let hash = uuid::Uuid::new_v4().to_simple().to_string();
+            let mut registration = FileRegistration::new(file, &hash);
+            registration.urls = vec![url.clone()];
+            registrations.push(registration);
+        }
+
+        let results = FileRegistration::add_bulk(&pool, &registrations, 2, "error", &|_, _| true)
+            .await
+            .unwrap();
+        assert!(results[0].is_ok());
+        assert!(matches!(
+            results[1].as_ref().unwrap_err().downcast_ref::<FileError>(),
+            Some(FileError::Registered { guid, .. }) if *guid == registrations[0].file.guid
+        ));
+        assert!(File::query_file(&pool, "guid", &registrations[1].file.guid)
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_add_bulk_same_content() {
+        let (_postgres, pool) = init().await;
+
+        // The files of the same content in one batch, the later one is left to the policy
+        let hash = uuid::Uuid::new_v4().to_simple().to_string();
+        let registrations: Vec<FileRegistration> = (0..2)
+            .map(|i| {
+                let file = File::new(&format!("test_bulk_same_{}.txt", i), 64, "test_user", "fudan-pgx");
+                FileRegistration::new(file, &hash)
+            })
+            .collect();
+        let results = FileRegistration::add_bulk(&pool, &registrations, 10, "error", &|_, _| true)
+            .await
+            .unwrap();
+        assert!(results[0].is_ok());
+        assert!(matches!(
+            results[1].as_ref().unwrap_err().downcast_ref::<FileError>(),
+            Some(FileError::Registered { guid, .. }) if *guid == registrations[0].file.guid
+        ));
+
+        // A bulk and a single registration of the same content, only one of them registers it
+        let hash = uuid::Uuid::new_v4().to_simple().to_string();
+        let bulk = vec![FileRegistration::new(
+            File::new("test_bulk_race.txt", 64, "test_user", "fudan-pgx"),
+            &hash,
+        )];
+        let mut single =
+            FileRegistration::new(File::new("test_single_race.txt", 64, "test_user", "fudan-pgx"), &hash);
+        let (bulk_results, single_result) = tokio::join!(
+            FileRegistration::add_bulk(&pool, &bulk, 10, "error", &|_, _| true),
+            single.add_with_policy(&pool, "error", &|_, _| true)
+        );
+        let created = [bulk_results.unwrap()[0].is_ok(), single_result.is_ok()];
+        assert_eq!(created.iter().filter(|ok| **ok).count(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_add_with_policy() {
+        let (_postgres, pool) = init().await;
+
+        let hash = uuid::Uuid::new_v4().to_simple().to_string();
+        let mut registration =
+            FileRegistration::new(File::new("test_policy.txt", 256, "test_user", "fudan-pgx"), &hash);
+        let (guid, created) = registration
+            .add_with_policy(&pool, "error", &|_, _| true)
+            .await
+            .unwrap();
+        assert!(created);
+
+        // The same content is rejected by default
+        let mut duplicated =
+            FileRegistration::new(File::new("test_policy.txt", 256, "test_user", "fudan-pgx"), &hash);
+        let err = duplicated.add_with_policy(&pool, "error", &|_, _| true).await.unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<FileError>(),
+            Some(FileError::Registered { guid: owner, .. }) if *owner == guid
+        ));
+
+        // The caller who cannot change the existing file gets neither the guid nor a new version
+        let url = format!("s3://test-policy/{}.txt", hash);
+        duplicated.urls = vec![url.clone()];
+        for policy in ON_CONFLICT_POLICIES {
+            let err = duplicated
+                .add_with_policy(&pool, policy, &|uploader, _| uploader != "test_user")
+                .await
+                .unwrap_err();
+            assert!(matches!(
+                err.downcast_ref::<FileError>(),
+                Some(FileError::Registered { guid: owner, .. }) if owner == HIDDEN_GUID
+            ));
+        }
+        let file = File::query_file(&pool, "guid", &guid).await.unwrap();
+        assert!(file.urls.is_none());
+        assert_eq!(File::get_versions(&pool, &file.baseid).await.unwrap().len(), 1);
+
+        // The existing guid is returned, and the new url is attached to it
+        let (existing, created) = duplicated
+            .add_with_policy(&pool, "return_existing", &|_, _| true)
+            .await
+            .unwrap();
+        assert_eq!((existing.as_str(), created), (guid.as_str(), false));
+        let file = File::query_file(&pool, "guid", &guid).await.unwrap();
+        let urls: Vec<URL> = serde_json::from_value(file.urls.unwrap()).unwrap();
+        assert_eq!(urls[0].url, url);
+
+        // A new version shares the hash with the existing file
+        duplicated.urls = vec![];
+        let (version, created) = duplicated
+            .add_with_policy(&pool, "new_version", &|_, _| true)
+            .await
+            .unwrap();
+        assert!(created);
+        assert_ne!(version, guid);
+        let versions = File::get_versions(&pool, &file.baseid).await.unwrap();
+        assert_eq!(versions.len(), 2);
+        assert_eq!(versions[1].guid, version);
+
+        // The lookup by hash returns the file registered first
+        let found = File::get_file_with_hash(&pool, &hash).await.unwrap();
+        assert_eq!(found.guid, guid);
+
+        // The concurrent registrations of the same content get different versions
+        let tasks = (0..4).map(|_| {
+            let pool = pool.clone();
+            let mut registration = FileRegistration::new(
+                File::new("test_policy.txt", 256, "test_user", "fudan-pgx"),
+                &hash,
+            );
+            tokio::spawn(async move {
+                registration.add_with_policy(&pool, "new_version", &|_, _| true).await.unwrap();
+                registration.file.version
+            })
+        });
+        let mut versions = vec![];
+        for task in tasks {
+            versions.push(task.await.unwrap());
+        }
+        versions.sort();
+        versions.dedup();
+        assert_eq!(versions.len(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_add_bulk_with_policy() {
+        let (_postgres, pool) = init().await;
+
+        let hash = uuid::Uuid::new_v4().to_simple().to_string();
+        let mut file = File::new("test_bulk_policy.txt", 64, "test_user", "fudan-pgx");
+        file.add(&pool, &hash, None, None).await.unwrap();
+
+        let registrations = vec![FileRegistration::new(
+            File::new("test_bulk_policy.txt", 64, "test_user", "fudan-pgx"),
+            &hash,
+        )];
+        let results =
+            FileRegistration::add_bulk(&pool, &registrations, 2, "return_existing", &|_, _| true)
+                .await
+                .unwrap();
+        assert_eq!(results[0].as_ref().unwrap(), &(file.guid.clone(), false));
+
+        let results =
+            FileRegistration::add_bulk(&pool, &registrations, 2, "new_version", &|_, _| true)
+                .await
+                .unwrap();
+        let (guid, created) = results[0].as_ref().unwrap();
+        assert!(created);
+        let version = File::query_file(&pool, "guid", guid).await.unwrap();
+        assert_eq!(version.version, 2);
+    }
+
+    #[test]
+    fn test_file_status_transitions() {
+        assert_eq!("validated".parse::<FileStatus>().unwrap(), FileStatus::Validated);
+        assert!("unknown".parse::<FileStatus>().is_err());
+        assert!(FileStatus::Pending.can_transition_to(FileStatus::Validated));
+        assert!(FileStatus::Failed.can_transition_to(FileStatus::Pending));
+        assert!(!FileStatus::Validated.can_transition_to(FileStatus::Pending));
+        assert!(!FileStatus::Pending.can_transition_to(FileStatus::Pending));
+        for status in FileStatus::ALL {
+            assert!(!FileStatus::Deleted.can_transition_to(status));
+            assert!(status == FileStatus::Deleted || status.can_transition_to(FileStatus::Deleted));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_update_status() {
+        let (_postgres, pool) = init().await;
+
+        let mut file = File::new("test_status.txt", 512, "test_user", "fudan-pgx");
+        let hash = uuid::Uuid::new_v4().to_simple().to_string();
+        file.add(&pool, &hash, None, None).await.unwrap();
+        let id = uuid::Uuid::parse_str(file.guid.split('/').next_back().unwrap()).unwrap();
+
+        let rev = File::update_status(&pool, &id, FileStatus::Processing, "qc_bot", None, None)
+            .await
+            .unwrap();
+        // A stale rev is rejected
+        let err =
+            File::update_status(&pool, &id, FileStatus::Failed, "qc_bot", None, Some(&file.rev))
+                .await
+                .unwrap_err();
+        assert!(matches!(err.downcast_ref::<FileError>(), Some(FileError::RevMismatch { .. })));
+        File::update_status(
+            &pool,
+            &id,
+            FileStatus::Validated,
+            "qc_user",
+            Some("QC passed"),
+            Some(&rev),
+        )
+        .await
+        .unwrap();
+
+        let err = File::update_status(&pool, &id, FileStatus::Pending, "qc_user", None, None)
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<FileError>(),
+            Some(FileError::InvalidTransition { .. })
+        ));
+
+        // Deleting the file by the status leaves a tombstone, and the deleted file is read-only
+        File::update_status(&pool, &id, FileStatus::Deleted, "admin", Some("duplicated"), None)
+            .await
+            .unwrap();
+        assert!(File::get_tombstone(&pool, &id).await.unwrap().is_some());
+        let err = File::update_status(&pool, &id, FileStatus::Pending, "admin", None, None)
+            .await
+            .unwrap_err();
+        assert!(matches!(err.downcast_ref::<FileError>(), Some(FileError::Deleted { .. })));
+
+        let history = File::get_status_history(&pool, &id).await.unwrap();
+        let changes: Vec<(&str, &str, &str)> = history
+            .iter()
+            .map(|h| (h.from_status.as_str(), h.to_status.as_str(), h.actor.as_str()))
+            .collect();
+        assert_eq!(
+            changes,
+            vec![
+                ("pending", "processing", "qc_bot"),
+                ("processing", "validated", "qc_user"),
+                ("validated", "deleted", "admin"),
+            ]
+        );
+        assert_eq!(history[1].reason.as_deref(), Some("QC passed"));
+    }
 }
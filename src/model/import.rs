@@ -0,0 +1,514 @@
+//! Import the datafiles (such as `datafile.tsv`) into the database with the `COPY` command.
+//!
+//! The files are checked before importing, so a batch only fails for the reasons which cannot be checked up front.
+
+use super::datafile::{Alias, Config, File, Hash, Tag, URL};
+use crate::repo_config;
+use crate::util;
+use anyhow::Error as AnyError;
+use log::{debug, info};
+use regex::Regex;
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+
+const FILE_STATUSES: [&str; 4] = ["pending", "processing", "validated", "failed"];
+
+/// The tables of the files, the referencing tables come first so they can be truncated in order.
+pub const FILE_TABLES: [&str; 5] = [
+    "biominer_indexd_url",
+    "biominer_indexd_hash",
+    "biominer_indexd_alias",
+    "biominer_indexd_tag",
+    "biominer_indexd_file",
+];
+
+/// The tables which keep the other records of the files, such as the tombstones and the uploads in progress.
+pub const FILE_HISTORY_TABLES: [&str; 4] = [
+    "biominer_indexd_tombstone",
+    "biominer_indexd_status_history",
+    "biominer_indexd_scrub",
+    "biominer_indexd_upload",
+];
+
+/// A file with its urls, hashes, aliases and tags, and where it comes from.
+#[derive(Debug, Clone)]
+pub struct ImportRecord {
+    pub source: String,
+    pub line: usize,
+    pub file: File,
+    pub urls: Vec<URL>,
+    pub hashes: Vec<Hash>,
+    pub aliases: Vec<Alias>,
+    pub tags: Vec<Tag>,
+}
+
+impl ImportRecord {
+    fn location(&self) -> String {
+        format!("{}:{}", self.source, self.line)
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ImportStats {
+    pub files: usize,
+    pub urls: usize,
+    pub hashes: usize,
+    pub aliases: usize,
+    pub tags: usize,
+}
+
+impl std::fmt::Display for ImportStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{} files, {} urls, {} hashes, {} aliases and {} tags",
+            self.files, self.urls, self.hashes, self.aliases, self.tags
+        )
+    }
+}
+
+/// List the datafiles to import, all the csv/tsv/txt files in a directory are imported in the order of their names.
+pub fn list_datafiles(filepath: &PathBuf) -> Result<Vec<PathBuf>, AnyError> {
+    if !filepath.is_dir() {
+        return Ok(vec![filepath.clone()]);
+    }
+
+    let mut filepaths = vec![];
+    for entry in std::fs::read_dir(filepath)? {
+        let path = entry?.path();
+        let ext = path.extension().and_then(|ext| ext.to_str()).unwrap_or("");
+        if path.is_file() && ["csv", "tsv", "txt"].contains(&ext) {
+            filepaths.push(path);
+        }
+    }
+    filepaths.sort();
+
+    if filepaths.is_empty() {
+        return Err(anyhow::anyhow!(
+            "No csv/tsv/txt file found in {}",
+            filepath.display()
+        ));
+    }
+
+    Ok(filepaths)
+}
+
+/// Load the datafile into records, the files are parsed by `File::from_file`.
+pub fn load_records(filepath: &PathBuf) -> Result<Vec<ImportRecord>, AnyError> {
+    let source = filepath.display().to_string();
+    let files = File::from_file(filepath)?;
+
+    let mut records = vec![];
+    for (i, mut file) in files.into_iter().enumerate() {
+        let urls: Vec<URL> = serde_json::from_value(file.urls.take().unwrap_or_default())?;
+        let hashes: Vec<Hash> = serde_json::from_value(file.hashes.take().unwrap_or_default())?;
+        let aliases: Vec<Alias> = serde_json::from_value(file.aliases.take().unwrap_or_default())?;
+        let tags: Vec<Tag> = serde_json::from_value(file.tags.take().unwrap_or_default())?;
+
+        // The timestamps in the datafiles may be in seconds, but they are milliseconds in the database.
+        for ts in [&mut file.created_at, &mut file.updated_at] {
+            if *ts < 100_000_000_000 {
+                *ts *= 1000;
+            }
+        }
+
+        records.push(ImportRecord {
+            source: source.clone(),
+            // The header is the first line.
+            line: i + 2,
+            file,
+            urls,
+            hashes,
+            aliases,
+            tags,
+        });
+    }
+
+    Ok(records)
+}
+
+/// Check the records, including the duplicated guids, urls and aliases among all records.
+///
+/// A hash may be shared by several files, so it is only unique in a record.
+pub fn check_records(records: &[ImportRecord]) -> Vec<String> {
+    let guid_regex = Regex::new(&format!(
+        r"^biominer\.{}/[0-9a-f]{{8}}-[0-9a-f]{{4}}-[0-9a-f]{{4}}-[0-9a-f]{{4}}-[0-9a-f]{{12}}$",
+        regex::escape(&Config::get_registry_id())
+    ))
+    .unwrap();
+
+    let mut errors = vec![];
+    for record in records {
+        let file = &record.file;
+        let location = record.location();
+        let mut error = |msg: String| errors.push(format!("{}: {}", location, msg));
+
+        if !guid_regex.is_match(&file.guid) {
+            error(format!(
+                "invalid guid {}, it should be biominer.{}/<uuid>",
+                file.guid,
+                Config::get_registry_id()
+            ));
+        }
+        if file.filename.is_empty() || file.filename.len() > 255 {
+            error("the filename should have 1-255 characters".to_string());
+        }
+        if file.size < 0 {
+            error(format!("invalid size {}", file.size));
+        }
+        if !FILE_STATUSES.contains(&file.status.as_str()) {
+            error(format!("invalid status {}", file.status));
+        }
+        if uuid::Uuid::parse_str(&file.baseid).is_err() {
+            error(format!(
+                "invalid baseid {}, it should be an uuid",
+                file.baseid
+            ));
+        }
+        if file.rev.is_empty() || file.rev.len() > 8 {
+            error(format!(
+                "invalid rev {}, it should have 1-8 characters",
+                file.rev
+            ));
+        }
+        if file.version < 1 {
+            error(format!("invalid version {}", file.version));
+        }
+        if file.uploader.len() > 64 {
+            error("the uploader should have at most 64 characters".to_string());
+        }
+
+        if record.hashes.is_empty() {
+            error("at least one hash is required".to_string());
+        }
+        let mut hashes = HashSet::new();
+        for hash in record.hashes.iter() {
+            if !util::validate_hash(&hash.hash, &hash.hash_type) {
+                error(format!("invalid {} hash {}", hash.hash_type, hash.hash));
+            }
+            if !hashes.insert(hash.hash.as_str()) {
+                error(format!("the hash {} is duplicated", hash.hash));
+            }
+        }
+        for url in record.urls.iter() {
+            if repo_config::which_protocol(&url.url).is_none() {
+                error(format!("invalid url {}", url.url));
+            }
+            if !FILE_STATUSES.contains(&url.status.as_str()) {
+                error(format!(
+                    "invalid status {} of the url {}",
+                    url.status, url.url
+                ));
+            }
+        }
+        for alias in record.aliases.iter() {
+            if alias.name.is_empty() || alias.name.len() > 255 {
+                error("the alias should have 1-255 characters".to_string());
+            }
+        }
+        let mut field_names = HashSet::new();
+        for tag in record.tags.iter() {
+            if !field_names.insert(tag.field_name.as_str()) {
+                error(format!("the tag {} is duplicated", tag.field_name));
+            }
+        }
+    }
+
+    let mut items: Vec<(&str, &str, String)> = vec![];
+    for record in records {
+        let location = record.location();
+        items.push(("guid", &record.file.guid, location.clone()));
+        items.extend(
+            record
+                .urls
+                .iter()
+                .map(|url| ("url", url.url.as_str(), location.clone())),
+        );
+        items.extend(
+            record
+                .aliases
+                .iter()
+                .map(|alias| ("alias", alias.name.as_str(), location.clone())),
+        );
+    }
+
+    let mut seen: HashMap<(&str, &str), String> = HashMap::new();
+    for (kind, value, location) in items {
+        match seen.get(&(kind, value)) {
+            Some(first) => errors.push(format!(
+                "{}: the {} {} is duplicated with {}",
+                location, kind, value, first
+            )),
+            None => {
+                seen.insert((kind, value), location);
+            }
+        }
+    }
+
+    errors
+}
+
+/// Check the records against the database, the guids (including the deleted ones), urls and aliases must not be registered.
+pub async fn check_registered(
+    pool: &sqlx::PgPool,
+    records: &[ImportRecord],
+) -> Result<Vec<String>, AnyError> {
+    let mut locations: HashMap<(&str, &str), String> = HashMap::new();
+    let mut values: HashMap<&str, Vec<String>> = HashMap::new();
+    for record in records {
+        let location = record.location();
+        let mut items = vec![("guid", record.file.guid.as_str())];
+        items.extend(record.urls.iter().map(|url| ("url", url.url.as_str())));
+        items.extend(
+            record
+                .aliases
+                .iter()
+                .map(|alias| ("alias", alias.name.as_str())),
+        );
+
+        for (kind, value) in items {
+            locations.insert((kind, value), location.clone());
+            values.entry(kind).or_default().push(value.to_string());
+        }
+    }
+
+    let queries = [
+        (
+            "guid",
+            "SELECT guid, guid FROM biominer_indexd_file WHERE guid = ANY($1)
+             UNION
+             SELECT guid, guid FROM biominer_indexd_tombstone WHERE guid = ANY($1)",
+        ),
+        (
+            "url",
+            "SELECT url, file FROM biominer_indexd_url WHERE url = ANY($1)",
+        ),
+        (
+            "alias",
+            "SELECT name, file FROM biominer_indexd_alias WHERE name = ANY($1)",
+        ),
+    ];
+
+    let mut errors = vec![];
+    for (kind, sql) in queries {
+        let values = match values.get(kind) {
+            Some(values) => values,
+            None => continue,
+        };
+
+        let registered = sqlx::query_as::<_, (String, String)>(sql)
+            .bind(values)
+            .fetch_all(pool)
+            .await?;
+
+        for (value, guid) in registered {
+            let location = locations
+                .get(&(kind, value.as_str()))
+                .cloned()
+                .unwrap_or_default();
+            if kind == "guid" {
+                errors.push(format!(
+                    "{}: the guid {} has been registered",
+                    location, value
+                ));
+            } else {
+                errors.push(format!(
+                    "{}: the {} {} has been registered by the file {}",
+                    location, kind, value, guid
+                ));
+            }
+        }
+    }
+
+    Ok(errors)
+}
+
+/// Empty all the tables of the files and their history, otherwise the stale tombstones reject the deleted guids in the data.
+pub async fn truncate_tables(pool: &sqlx::PgPool) -> Result<(), AnyError> {
+    let tables = FILE_TABLES
+        .iter()
+        .chain(FILE_HISTORY_TABLES.iter())
+        .cloned()
+        .collect::<Vec<_>>();
+    let sql = format!("TRUNCATE TABLE {}", tables.join(", "));
+    sqlx::query(&sql).execute(pool).await?;
+    Ok(())
+}
+
+/// Escape a value for the text format of the `COPY` command.
+fn copy_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('\t', "\\t")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+}
+
+fn copy_row(values: &[Option<String>]) -> String {
+    let values = values
+        .iter()
+        .map(|value| match value {
+            Some(value) => copy_value(value),
+            None => "\\N".to_string(),
+        })
+        .collect::<Vec<_>>();
+
+    format!("{}\n", values.join("\t"))
+}
+
+async fn copy_in(
+    tx: &mut sqlx::Transaction<'_, sqlx::Postgres>,
+    statement: &str,
+    rows: &[String],
+) -> Result<(), AnyError> {
+    if rows.is_empty() {
+        return Ok(());
+    }
+
+    let mut copy = tx.copy_in_raw(statement).await?;
+    for row in rows {
+        if let Err(e) = copy.send(row.as_bytes()).await {
+            copy.abort(e.to_string()).await?;
+            return Err(e.into());
+        }
+    }
+    copy.finish().await?;
+
+    Ok(())
+}
+
+/// Import the records with the `COPY` command, each batch is imported in its own transaction.
+///
+/// The batches before the failed one have been committed, the stats of them are kept in the error message.
+pub async fn import_records(
+    pool: &sqlx::PgPool,
+    records: &[ImportRecord],
+    batch_size: usize,
+) -> Result<ImportStats, AnyError> {
+    let mut stats = ImportStats::default();
+
+    for (i, batch) in records.chunks(batch_size.max(1)).enumerate() {
+        let mut files = vec![];
+        let mut urls = vec![];
+        let mut hashes = vec![];
+        let mut aliases = vec![];
+        let mut tags = vec![];
+        for record in batch {
+            let file = &record.file;
+            let guid = Some(file.guid.clone());
+            files.push(copy_row(&[
+                guid.clone(),
+                Some(file.filename.clone()),
+                Some(file.size.to_string()),
+                Some(file.created_at.to_string()),
+                Some(file.updated_at.to_string()),
+                Some(file.status.clone()),
+                Some(file.rev.clone()),
+                Some(file.baseid.clone()),
+                Some(file.version.to_string()),
+                Some(file.uploader.clone()),
+                file.acl.clone(),
+            ]));
+            for url in record.urls.iter() {
+                let uploader = if url.uploader.is_empty() {
+                    &file.uploader
+                } else {
+                    &url.uploader
+                };
+                urls.push(copy_row(&[
+                    Some(url.url.clone()),
+                    Some(file.created_at.to_string()),
+                    Some(url.status.clone()),
+                    Some(uploader.clone()),
+                    guid.clone(),
+                ]));
+            }
+            for hash in record.hashes.iter() {
+                hashes.push(copy_row(&[
+                    Some(hash.hash.clone()),
+                    Some(hash.hash_type.clone()),
+                    guid.clone(),
+                ]));
+            }
+            for alias in record.aliases.iter() {
+                aliases.push(copy_row(&[Some(alias.name.clone()), guid.clone()]));
+            }
+            for tag in record.tags.iter() {
+                tags.push(copy_row(&[
+                    Some(tag.field_name.clone()),
+                    Some(tag.field_value.clone()),
+                    guid.clone(),
+                ]));
+            }
+        }
+
+        let result: Result<(), AnyError> = async {
+            let mut tx = pool.begin().await?;
+            copy_in(&mut tx, "COPY biominer_indexd_file (guid, filename, size, created_at, updated_at, status, rev, baseid, version, uploader, acl) FROM STDIN", &files).await?;
+            copy_in(&mut tx, "COPY biominer_indexd_url (url, created_at, status, uploader, file) FROM STDIN", &urls).await?;
+            copy_in(&mut tx, "COPY biominer_indexd_hash (hash, hash_type, file) FROM STDIN", &hashes).await?;
+            copy_in(&mut tx, "COPY biominer_indexd_alias (name, file) FROM STDIN", &aliases).await?;
+            copy_in(&mut tx, "COPY biominer_indexd_tag (field_name, field_value, file) FROM STDIN", &tags).await?;
+            tx.commit().await?;
+            Ok(())
+        }
+        .await;
+
+        if let Err(e) = result {
+            return Err(anyhow::anyhow!(
+                "Failed to import the batch {} ({} to {}): {}, {} have been imported",
+                i + 1,
+                batch[0].location(),
+                batch[batch.len() - 1].location(),
+                e,
+                stats
+            ));
+        }
+
+        stats.files += files.len();
+        stats.urls += urls.len();
+        stats.hashes += hashes.len();
+        stats.aliases += aliases.len();
+        stats.tags += tags.len();
+        debug!("Imported the batch {}: {}", i + 1, stats);
+    }
+
+    info!("Imported {}", stats);
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    #[test]
+    fn test_check_records() {
+        let path = Path::new("examples/file_template.tsv").to_path_buf();
+        let mut records = load_records(&path).unwrap();
+        assert!(!records.is_empty());
+        assert!(records[0].file.acl.is_none());
+        assert!(records[0].file.created_at > 100_000_000_000);
+        assert_eq!(check_records(&records), Vec::<String>::new());
+
+        let mut duplicated = records[0].clone();
+        duplicated.line = records.len() + 2;
+        duplicated.file.status = "unknown".to_string();
+        records.push(duplicated);
+
+        let errors = check_records(&records);
+        // The hashes can be shared by the files
+        assert_eq!(errors.len(), 4);
+        assert!(errors[0].ends_with("invalid status unknown"));
+        assert!(errors[1].contains("the guid"));
+        assert!(errors
+            .iter()
+            .all(|e| e.contains("is duplicated") || e.contains("invalid status")));
+    }
+
+    #[test]
+    fn test_copy_row() {
+        let row = copy_row(&[Some("a\tb".to_string()), None, Some("c\\d\ne".to_string())]);
+        assert_eq!(row, "a\\tb\t\\N\tc\\\\d\\ne\n");
+    }
+}
@@ -4,4 +4,6 @@ pub mod datafile;
 pub mod dataset;
 pub mod dataset_metadata;
 pub mod duckdb_util;                
+pub mod import;
+pub mod upload;
 pub mod util;
@@ -0,0 +1,678 @@
+//! The presigned uploads to the s3 compatible repos, such as MinIO.
+//!
+//! The file is registered first, then indexd presigns the urls to upload its content (in one or more parts),
+//! and the client calls finalize after the upload, the object is checked against the registered size and md5.
+use crate::model::datafile::{File, FileStatus, Hash};
+use crate::repo_config::{S3Config, SignData};
+use anyhow::Ok as AnyOk;
+use custom_error::custom_error;
+use log::{info, warn};
+use openssl::hash::{hash, Hasher, MessageDigest};
+use reqwest::header::{CONTENT_LENGTH, ETAG};
+
+custom_error! {pub UploadError
+    Mismatch{url: String, detail: String} = "The uploaded object {url} does not match the file: {detail}",
+    Storage{detail: String} = "The object store responds an error: {detail}",
+    NotStarted{guid: String, detail: String} = "No such upload of the file {guid}: {detail}",
+}
+
+/// The upload in progress of a file, only the object and the upload id given by indexd can be finalized.
+#[derive(Debug, Clone, PartialEq, Eq, sqlx::FromRow)]
+pub struct PendingUpload {
+    pub file: String,
+    pub protocol: String,
+    pub bucket: String,
+    pub key: String,
+    pub upload_id: Option<String>,
+    pub created_by: String,
+    pub created_at: i64,
+}
+
+impl PendingUpload {
+    pub fn new(guid: &str, upload: &Upload<'_>, upload_id: Option<&str>, created_by: &str) -> Self {
+        PendingUpload {
+            file: guid.to_string(),
+            protocol: upload.protocol.to_string(),
+            bucket: upload.config.bucket.clone(),
+            key: upload.key.to_string(),
+            upload_id: upload_id.map(|id| id.to_string()),
+            created_by: created_by.to_string(),
+            created_at: chrono::Utc::now().timestamp_millis(),
+        }
+    }
+
+    /// Save the upload, it replaces the previous upload of the file.
+    pub async fn save(&self, pool: &sqlx::PgPool) -> Result<(), anyhow::Error> {
+        sqlx::query(
+            "
+                INSERT INTO biominer_indexd_upload (file, protocol, bucket, key, upload_id, created_by, created_at)
+                    VALUES ($1, $2, $3, $4, $5, $6, $7)
+                    ON CONFLICT (file) DO UPDATE SET protocol = EXCLUDED.protocol, bucket = EXCLUDED.bucket,
+                        key = EXCLUDED.key, upload_id = EXCLUDED.upload_id,
+                        created_by = EXCLUDED.created_by, created_at = EXCLUDED.created_at;
+            ",
+        )
+        .bind(&self.file)
+        .bind(&self.protocol)
+        .bind(&self.bucket)
+        .bind(&self.key)
+        .bind(&self.upload_id)
+        .bind(&self.created_by)
+        .bind(self.created_at)
+        .execute(pool)
+        .await?;
+        AnyOk(())
+    }
+
+    /// Get the upload of the file, and check that the key and the upload id are the ones given when it was started.
+    pub async fn get(
+        pool: &sqlx::PgPool,
+        guid: &str,
+        key: &str,
+        upload_id: Option<&str>,
+    ) -> Result<PendingUpload, anyhow::Error> {
+        let upload = sqlx::query_as::<_, PendingUpload>(
+            "SELECT * FROM biominer_indexd_upload WHERE file = $1",
+        )
+        .bind(guid)
+        .fetch_optional(pool)
+        .await?;
+
+        let detail = match upload {
+            None => "the upload is not started".to_string(),
+            Some(upload) if upload.key != key => format!("the key {} is not the uploaded object", key),
+            Some(upload) if upload.upload_id.as_deref() != upload_id => {
+                format!("the upload id {:?} does not match", upload_id)
+            }
+            Some(upload) => return AnyOk(upload),
+        };
+        Err(UploadError::NotStarted {
+            guid: guid.to_string(),
+            detail,
+        }
+        .into())
+    }
+
+    pub async fn remove(pool: &sqlx::PgPool, guid: &str) -> Result<(), anyhow::Error> {
+        sqlx::query("DELETE FROM biominer_indexd_upload WHERE file = $1")
+            .bind(guid)
+            .execute(pool)
+            .await?;
+        AnyOk(())
+    }
+}
+
+/// The object to upload in a bucket.
+#[derive(Debug, Clone)]
+pub struct Upload<'a> {
+    // s3 or minio
+    pub protocol: &'a str,
+    pub config: &'a S3Config,
+    pub key: &'a str,
+}
+
+impl<'a> Upload<'a> {
+    /// The url to register, such as minio://<bucket_name>/<object_name>.
+    pub fn url(&self) -> String {
+        format!("{}://{}/{}", self.protocol, self.config.bucket, self.key)
+    }
+
+    fn presign(
+        &self,
+        method: &str,
+        params: Vec<(String, String)>,
+    ) -> Result<SignData, anyhow::Error> {
+        self.config
+            .presign_at(method, self.key, params, chrono::Utc::now())
+            .map_err(|e| anyhow::anyhow!(e))
+    }
+
+    async fn send(
+        &self,
+        request: reqwest::RequestBuilder,
+    ) -> Result<reqwest::Response, anyhow::Error> {
+        let response = request.send().await?;
+        if !response.status().is_success() {
+            let status = response.status();
+            let body = response.text().await.unwrap_or_default();
+            return Err(UploadError::Storage {
+                detail: format!("{} {}", status, body),
+            }
+            .into());
+        }
+        AnyOk(response)
+    }
+
+    /// Presign the PUT of the whole object.
+    pub fn presign_put(&self) -> Result<SignData, anyhow::Error> {
+        self.presign("PUT", vec![])
+    }
+
+    /// Presign the PUT of a part, the part number starts from 1.
+    pub fn presign_part(
+        &self,
+        upload_id: &str,
+        part_number: u32,
+    ) -> Result<SignData, anyhow::Error> {
+        self.presign(
+            "PUT",
+            vec![
+                ("partNumber".to_string(), part_number.to_string()),
+                ("uploadId".to_string(), upload_id.to_string()),
+            ],
+        )
+    }
+
+    /// Start a multipart upload and return its upload id.
+    pub async fn create_multipart(
+        &self,
+        client: &reqwest::Client,
+    ) -> Result<String, anyhow::Error> {
+        let sign = self.presign("POST", vec![("uploads".to_string(), "".to_string())])?;
+        let body = self.send(sign.request(client)).await?.text().await?;
+
+        // <InitiateMultipartUploadResult>...<UploadId>VXBsb2FkIElE</UploadId></InitiateMultipartUploadResult>
+        match xml_value(&body, "UploadId") {
+            Some(upload_id) => AnyOk(upload_id),
+            None => Err(UploadError::Storage {
+                detail: format!("No upload id in the response: {}", body),
+            }
+            .into()),
+        }
+    }
+
+    /// Assemble the uploaded parts into the object.
+    pub async fn complete_multipart(
+        &self,
+        client: &reqwest::Client,
+        upload_id: &str,
+        parts: &[(u32, String)],
+    ) -> Result<(), anyhow::Error> {
+        let sign = self.presign(
+            "POST",
+            vec![("uploadId".to_string(), upload_id.to_string())],
+        )?;
+        let parts = parts
+            .iter()
+            .map(|(number, etag)| {
+                format!(
+                    "<Part><PartNumber>{}</PartNumber><ETag>\"{}\"</ETag></Part>",
+                    number,
+                    etag.trim_matches('"')
+                )
+            })
+            .collect::<String>();
+        let body = format!(
+            "<CompleteMultipartUpload>{}</CompleteMultipartUpload>",
+            parts
+        );
+
+        // The errors of the completion may come with the status 200, so check the body too.
+        let body = self
+            .send(sign.request(client).body(body))
+            .await?
+            .text()
+            .await?;
+        if body.contains("<Error>") {
+            return Err(UploadError::Storage { detail: body }.into());
+        }
+        AnyOk(())
+    }
+
+    /// The uploaded parts with their ETags, in the order of the part numbers. They are listed by the store, so
+    /// a client can't choose the parts which are assembled.
+    pub async fn list_parts(
+        &self,
+        client: &reqwest::Client,
+        upload_id: &str,
+    ) -> Result<Vec<(u32, String)>, anyhow::Error> {
+        let mut parts = vec![];
+        let mut marker = 0;
+        loop {
+            let sign = self.presign(
+                "GET",
+                vec![
+                    ("part-number-marker".to_string(), marker.to_string()),
+                    ("uploadId".to_string(), upload_id.to_string()),
+                ],
+            )?;
+            let body = self.send(sign.request(client)).await?.text().await?;
+
+            // <ListPartsResult><IsTruncated>false</IsTruncated><Part><PartNumber>1</PartNumber><ETag>"..."</ETag></Part>...</ListPartsResult>
+            for part in xml_values(&body, "Part") {
+                let number = xml_value(&part, "PartNumber").and_then(|n| n.parse::<u32>().ok());
+                // The quotes of the ETag may be escaped
+                let etag = xml_value(&part, "ETag").map(|etag| etag.replace("&quot;", "\""));
+                match number.zip(etag) {
+                    Some((number, etag)) => parts.push((number, etag.trim_matches('"').to_string())),
+                    None => {
+                        return Err(UploadError::Storage {
+                            detail: format!("Invalid part in the response: {}", part),
+                        }
+                        .into())
+                    }
+                }
+            }
+
+            let truncated = xml_value(&body, "IsTruncated").as_deref() == Some("true");
+            match xml_value(&body, "NextPartNumberMarker").and_then(|n| n.parse().ok()) {
+                Some(next) if truncated && next > marker => marker = next,
+                _ => break,
+            }
+        }
+        parts.sort();
+        AnyOk(parts)
+    }
+
+    /// The md5 of the content of the object, the content is streamed and never kept in memory as a whole.
+    pub async fn md5(&self, client: &reqwest::Client) -> Result<String, anyhow::Error> {
+        let sign = self.presign("GET", vec![])?;
+        let mut response = self.send(sign.request(client)).await?;
+        let mut hasher = Hasher::new(MessageDigest::md5())?;
+        while let Some(chunk) = response.chunk().await? {
+            hasher.update(&chunk)?;
+        }
+        AnyOk(hex::encode(hasher.finish()?))
+    }
+
+    /// The size and the ETag of the object.
+    pub async fn head(&self, client: &reqwest::Client) -> Result<(u64, String), anyhow::Error> {
+        let sign = self.presign("HEAD", vec![])?;
+        let response = self.send(sign.request(client)).await?;
+        let header = |name| {
+            response
+                .headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(|value| value.trim_matches('"').to_string())
+        };
+
+        let size = header(CONTENT_LENGTH).and_then(|size| size.parse::<u64>().ok());
+        match (size, header(ETAG)) {
+            (Some(size), Some(etag)) => AnyOk((size, etag)),
+            _ => Err(UploadError::Storage {
+                detail: "No size or ETag of the object".to_string(),
+            }
+            .into()),
+        }
+    }
+}
+
+fn xml_value(body: &str, tag: &str) -> Option<String> {
+    xml_values(body, tag).into_iter().next()
+}
+
+fn xml_values(body: &str, tag: &str) -> Vec<String> {
+    let (open, close) = (format!("<{}>", tag), format!("</{}>", tag));
+    let mut values = vec![];
+    let mut rest = body;
+    while let Some(start) = rest.find(&open).map(|start| start + open.len()) {
+        match rest[start..].find(&close) {
+            Some(end) => {
+                values.push(rest[start..start + end].to_string());
+                rest = &rest[start + end + close.len()..];
+            }
+            None => break,
+        }
+    }
+    values
+}
+
+/// The ETag of a multipart object is the md5 of the binary md5s of its parts, followed by the number of the parts.
+pub fn multipart_etag(etags: &[String]) -> Result<String, String> {
+    let mut md5s = vec![];
+    for etag in etags.iter() {
+        let md5 =
+            hex::decode(etag.trim_matches('"')).map_err(|_| format!("Invalid ETag {}", etag))?;
+        md5s.extend(md5);
+    }
+    let digest = hash(MessageDigest::md5(), &md5s).map_err(|e| e.to_string())?;
+    Ok(format!("{}-{}", hex::encode(digest), etags.len()))
+}
+
+/// Verify the uploaded object, then attach its url to the file as validated and mark the file as validated.
+///
+/// The parts of a multipart upload are listed by the store. The md5 of the object is checked against the md5 of
+/// the file, it is the ETag of an object uploaded at once and it is computed from the content of a multipart object.
+/// The url is returned with the new rev of the file. A multipart object keeps its ETag as a hash of the file.
+pub async fn finalize_upload(
+    pool: &sqlx::PgPool,
+    client: &reqwest::Client,
+    upload: &Upload<'_>,
+    id: &uuid::Uuid,
+    upload_id: Option<&str>,
+    actor: &str,
+) -> Result<(String, String), anyhow::Error> {
+    let file = File::get_file(pool, id).await?;
+    let url = upload.url();
+
+    let mut parts = vec![];
+    if let Some(upload_id) = upload_id {
+        parts = upload.list_parts(client, upload_id).await?;
+        if parts.is_empty() {
+            return Err(UploadError::Mismatch {
+                url,
+                detail: "no part is uploaded".to_string(),
+            }
+            .into());
+        }
+        upload.complete_multipart(client, upload_id, &parts).await?;
+    }
+
+    let (size, etag) = upload.head(client).await?;
+    if size as i64 != file.size {
+        return Err(UploadError::Mismatch {
+            url,
+            detail: format!("size {} != {}", size, file.size),
+        }
+        .into());
+    }
+
+    let hashes: Vec<Hash> = match &file.hashes {
+        Some(hashes) => serde_json::from_value(hashes.clone())?,
+        None => vec![],
+    };
+    if upload_id.is_some() {
+        let etags = parts
+            .iter()
+            .map(|(_, etag)| etag.clone())
+            .collect::<Vec<String>>();
+        let expected = multipart_etag(&etags).map_err(|e| anyhow::anyhow!(e))?;
+        if !expected.eq_ignore_ascii_case(&etag) {
+            return Err(UploadError::Mismatch {
+                url,
+                detail: format!("ETag {} != {}", etag, expected),
+            }
+            .into());
+        }
+    }
+
+    match hashes.iter().find(|h| h.hash_type == "md5") {
+        Some(md5) => {
+            // The ETag of an object uploaded at once is its md5.
+            let computed = match upload_id {
+                Some(_) => upload.md5(client).await?,
+                None => etag.clone(),
+            };
+            if !computed.eq_ignore_ascii_case(&md5.hash) {
+                return Err(UploadError::Mismatch {
+                    url,
+                    detail: format!("md5 {} != {}", computed, md5.hash),
+                }
+                .into());
+            }
+        }
+        None => warn!(
+            "The file {} has no md5, the content of {} is not checked.",
+            file.guid, url
+        ),
+    }
+
+    let mut rev =
+        File::add_url(pool, id, &url, actor, FileStatus::Validated.as_str(), None).await?;
+    if upload_id.is_some() {
+        rev = File::add_hash(pool, id, &etag, Some("etag"), None).await?;
+    }
+
+    // A validated file is left as it is.
+    let can_validate = match file.status.parse::<FileStatus>() {
+        Ok(status) => status.can_transition_to(FileStatus::Validated),
+        Err(_) => true,
+    };
+    if can_validate {
+        let reason = format!("Uploaded to {}", url);
+        rev = File::update_status(pool, id, FileStatus::Validated, actor, Some(&reason), None)
+            .await?;
+    }
+
+    info!("Finalize the upload of the file {} to {}", file.guid, url);
+    AnyOk((url, rev))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::model::datafile::tests::init;
+    use crate::model::datafile::URL;
+    use crate::repo_config::sigv4::tests::verify;
+    use chrono::Utc;
+    use poem::http::StatusCode;
+    use poem::web::Query;
+    use poem::{handler, listener::TcpListener, Response, Route, Server};
+    use std::collections::HashMap;
+
+    pub const CONTENT: &[u8] = b"hello indexd";
+
+    /// A stand-in of the object store, the object is always CONTENT and the parts are accepted as they are.
+    /// The object is uploaded in one part unless its key contains `multipart`, the part is the whole CONTENT.
+    /// The SigV4 signature of every request is checked with the secret of `s3_config`, as MinIO does.
+    #[handler]
+    fn mock_object(
+        req: &poem::Request,
+        method: poem::http::Method,
+        uri: &poem::http::Uri,
+        Query(params): Query<HashMap<String, String>>,
+    ) -> Response {
+        let host = req
+            .headers()
+            .get(poem::http::header::HOST)
+            .and_then(|host| host.to_str().ok())
+            .unwrap_or_default();
+        let query = uri.query().unwrap_or_default();
+        if let Err(e) = verify(method.as_str(), host, uri.path(), query, "minioadmin", Utc::now()) {
+            return Response::builder()
+                .status(StatusCode::FORBIDDEN)
+                .body(format!("<Error><Code>{}</Code></Error>", e));
+        }
+
+        let md5 = hex::encode(hash(MessageDigest::md5(), CONTENT).unwrap());
+        match method.as_str() {
+            "PUT" => Response::builder()
+                .header(poem::http::header::ETAG, format!("\"{}\"", md5))
+                .finish(),
+            "POST" if params.contains_key("uploads") => Response::builder()
+                .body("<InitiateMultipartUploadResult><UploadId>test-upload</UploadId></InitiateMultipartUploadResult>"),
+            "POST" if params.get("uploadId").map(|id| id.as_str()) == Some("test-upload") => {
+                Response::builder().body("<CompleteMultipartUploadResult/>")
+            }
+            "GET" if params.get("uploadId").map(|id| id.as_str()) == Some("test-upload") => {
+                Response::builder().body(format!(
+                    "<ListPartsResult><IsTruncated>false</IsTruncated><Part><PartNumber>1</PartNumber><ETag>&quot;{}&quot;</ETag></Part></ListPartsResult>",
+                    md5
+                ))
+            }
+            "GET" => Response::builder().body(CONTENT),
+            "HEAD" => {
+                let etag = if uri.path().contains("multipart") {
+                    multipart_etag(&[md5]).unwrap()
+                } else {
+                    md5
+                };
+                Response::builder()
+                    .header(poem::http::header::ETAG, format!("\"{}\"", etag))
+                    .header(poem::http::header::CONTENT_LENGTH, CONTENT.len())
+                    .finish()
+            }
+            _ => Response::builder().status(StatusCode::BAD_REQUEST).body("<Error></Error>"),
+        }
+    }
+
+    /// Serve the stand-in and return its endpoint.
+    pub async fn mock_s3() -> String {
+        let port = crate::get_free_port().unwrap();
+        let app = Route::new().at("/*path", mock_object);
+        tokio::spawn(Server::new(TcpListener::bind(format!("127.0.0.1:{}", port))).run(app));
+        tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+        format!("http://127.0.0.1:{}", port)
+    }
+
+    pub fn s3_config(endpoint: &str) -> S3Config {
+        serde_json::from_value(serde_json::json!({
+            "access_key": "minioadmin",
+            "access_secret": "minioadmin",
+            "endpoint": endpoint,
+            "bucket": "test",
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_multipart_etag() {
+        // The md5 of two parts, "hello " and "indexd"
+        let etags = vec![
+            "\"f814893777bcc2295fff05f00e508da6\"".to_string(),
+            "23ce9fba05bb53a755294ec32ce26767".to_string(),
+        ];
+        assert_eq!(
+            multipart_etag(&etags).unwrap(),
+            "07d8db1bce6bf14c684f13ac8a4d374e-2"
+        );
+        assert!(multipart_etag(&["not-hex".to_string()]).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_upload() {
+        let endpoint = mock_s3().await;
+        let config = s3_config(&endpoint);
+        let upload = Upload {
+            protocol: "minio",
+            config: &config,
+            key: "a/data.txt",
+        };
+        let client = reqwest::Client::new();
+
+        let sign = upload.presign_put().unwrap();
+        assert_eq!(sign.method, "PUT");
+        assert_eq!(sign.baseurl, format!("{}/test/a/data.txt", endpoint));
+        let response = sign.request(&client).body(CONTENT).send().await.unwrap();
+        assert_eq!(response.status(), 200);
+        // The stand-in rejects the url signed for another object or with another secret
+        let mut forged = sign.clone();
+        forged.baseurl = format!("{}/test/b/data.txt", endpoint);
+        let response = forged.request(&client).body(CONTENT).send().await.unwrap();
+        assert_eq!(response.status(), 403);
+        let mut config = s3_config(&endpoint);
+        config.access_secret = "wrong".to_string();
+        let forged = Upload { config: &config, ..upload.clone() }.presign_put().unwrap();
+        let response = forged.request(&client).body(CONTENT).send().await.unwrap();
+        assert_eq!(response.status(), 403);
+
+        let sign = upload.presign_part("test-upload", 2).unwrap();
+        assert!(sign.params.contains(&"partNumber=2".to_string()));
+        let response = sign.request(&client).body(CONTENT).send().await.unwrap();
+        assert_eq!(response.status(), 200);
+
+        assert_eq!(
+            upload.create_multipart(&client).await.unwrap(),
+            "test-upload"
+        );
+        let parts = vec![(1, "f814893777bcc2295fff05f00e508da6".to_string())];
+        upload
+            .complete_multipart(&client, "test-upload", &parts)
+            .await
+            .unwrap();
+        assert!(upload
+            .complete_multipart(&client, "unknown", &parts)
+            .await
+            .is_err());
+
+        assert_eq!(
+            upload.list_parts(&client, "test-upload").await.unwrap(),
+            vec![(1, "7ef38920ff55ffe509c424d265c1df65".to_string())]
+        );
+        assert_eq!(upload.md5(&client).await.unwrap(), "7ef38920ff55ffe509c424d265c1df65");
+
+        assert_eq!(upload.url(), "minio://test/a/data.txt");
+        let (size, etag) = upload.head(&client).await.unwrap();
+        assert_eq!((size, etag.len()), (12, 32));
+    }
+
+    #[tokio::test]
+    async fn test_finalize_upload() {
+        let (_postgres, pool) = init().await;
+        let endpoint = mock_s3().await;
+        let config = s3_config(&endpoint);
+        let client = reqwest::Client::new();
+        let md5 = "7ef38920ff55ffe509c424d265c1df65";
+
+        let other = uuid::Uuid::new_v4().to_simple().to_string();
+
+        let mut ids = vec![];
+        for (size, hash) in [(12, md5), (12, md5), (13, md5), (12, other.as_str())] {
+            let mut file = File::new("test_upload.txt", size, "test_upload", "fudan-pgx");
+            file.add(&pool, hash, None, None).await.unwrap();
+            ids.push(uuid::Uuid::parse_str(file.guid.rsplit('/').next().unwrap()).unwrap());
+        }
+
+        // Uploaded at once, the ETag is the md5
+        let key = format!("{}/test_upload.txt", ids[0]);
+        let upload = Upload { protocol: "minio", config: &config, key: &key };
+        let (url, _) =
+            finalize_upload(&pool, &client, &upload, &ids[0], None, "tester").await.unwrap();
+        assert_eq!(url, format!("minio://test/{}", key));
+        let file = File::get_file(&pool, &ids[0]).await.unwrap();
+        assert_eq!(file.status, "validated");
+        let urls: Vec<URL> = serde_json::from_value(file.urls.unwrap()).unwrap();
+        assert!(urls.iter().any(|u| u.url == url && u.status == "validated"));
+
+        // Uploaded in parts, the parts are listed by the store and the md5 is computed from the content
+        let key = format!("multipart/{}", ids[1]);
+        let upload = Upload { protocol: "minio", config: &config, key: &key };
+        finalize_upload(&pool, &client, &upload, &ids[1], Some("test-upload"), "tester")
+            .await
+            .unwrap();
+        let file = File::get_file(&pool, &ids[1]).await.unwrap();
+        let hashes: Vec<Hash> = serde_json::from_value(file.hashes.unwrap()).unwrap();
+        assert!(hashes.iter().any(|h| h.hash_type == "etag"));
+
+        // The size of the object is not the registered one
+        let key = format!("{}/test_upload.txt", ids[2]);
+        let upload = Upload { protocol: "minio", config: &config, key: &key };
+        let err =
+            finalize_upload(&pool, &client, &upload, &ids[2], None, "tester").await.unwrap_err();
+        assert!(matches!(err.downcast_ref::<UploadError>(), Some(UploadError::Mismatch { .. })));
+        let file = File::get_file(&pool, &ids[2]).await.unwrap();
+        assert_ne!(file.status, "validated");
+
+        // The ETag of the parts matches, but the content is not the registered one
+        let key = format!("multipart/{}", ids[3]);
+        let upload = Upload { protocol: "minio", config: &config, key: &key };
+        let err = finalize_upload(&pool, &client, &upload, &ids[3], Some("test-upload"), "tester")
+            .await
+            .unwrap_err();
+        assert!(matches!(err.downcast_ref::<UploadError>(), Some(UploadError::Mismatch { .. })));
+        let file = File::get_file(&pool, &ids[3]).await.unwrap();
+        assert_ne!(file.status, "validated");
+    }
+
+    #[tokio::test]
+    async fn test_pending_upload() {
+        let (_postgres, pool) = init().await;
+        let config = s3_config("http://127.0.0.1:9000");
+        let guid = File::new("test_upload.txt", 12, "tester", "fudan-pgx").guid;
+        let key = format!("{}/test_upload.txt", guid);
+        let upload = Upload { protocol: "minio", config: &config, key: &key };
+
+        let not_started = |result: Result<PendingUpload, anyhow::Error>| {
+            matches!(
+                result.unwrap_err().downcast_ref::<UploadError>(),
+                Some(UploadError::NotStarted { .. })
+            )
+        };
+        assert!(not_started(PendingUpload::get(&pool, &guid, &key, None).await));
+
+        PendingUpload::new(&guid, &upload, Some("test-upload"), "tester")
+            .save(&pool)
+            .await
+            .unwrap();
+        let pending = PendingUpload::get(&pool, &guid, &key, Some("test-upload")).await.unwrap();
+        assert_eq!((pending.bucket.as_str(), pending.created_by.as_str()), ("test", "tester"));
+        // Another object or upload can't be finalized for the file
+        assert!(not_started(PendingUpload::get(&pool, &guid, "other/data.txt", Some("test-upload")).await));
+        assert!(not_started(PendingUpload::get(&pool, &guid, &key, Some("other")).await));
+        assert!(not_started(PendingUpload::get(&pool, &guid, &key, None).await));
+
+        PendingUpload::remove(&pool, &guid).await.unwrap();
+        assert!(not_started(PendingUpload::get(&pool, &guid, &key, Some("test-upload")).await));
+    }
+}
@@ -1,4 +1,5 @@
 use super::datafile::{Alias, File, Hash, Tag, URL};
+use crate::util::{get_delimiter, parse_csv_error};
 use anyhow::{Context, Error, Result};
 use regex::Regex;
 use serde_json::{json, Value};
@@ -90,6 +91,8 @@ fn parse_record(record: &HashMap<String, String>) -> Result<File> {
                 uploader: group.get("uploader").cloned().unwrap_or_default(),
                 created_at: now_ts(),
                 file: Some(guid.clone()),
+                reason: None,
+                checked_at: None,
             })
         })
         .collect();
@@ -171,7 +174,8 @@ fn parse_record(record: &HashMap<String, String>) -> Result<File> {
             .get("access")
             .unwrap_or(&"private".to_string())
             .clone(),
-        acl: record.get("acl").cloned(),
+        // An empty acl means the file is public.
+        acl: record.get("acl").filter(|acl| !acl.is_empty()).cloned(),
 
         urls: Some(json!(urls)),
         hashes: Some(json!(hashes)),
@@ -227,6 +231,44 @@ pub fn load_tsv(filepath: &PathBuf) -> Result<Vec<File>, Error> {
     Ok(result)
 }
 
+/// Loads and parses a delimited file (csv, tsv or txt) into a list of `File` objects.
+///
+/// Unlike `load_tsv()`, the values may be quoted, so the delimiter can be a part of the value.
+///
+/// # Arguments
+/// * `filepath` - Path to the file to load, the delimiter is decided by the file extension.
+///
+/// # Returns
+/// A vector of parsed `File` objects.
+///
+/// # Errors
+/// Returns an error if the file cannot be read or parsed, the error contains the line number.
+pub fn load_csv(filepath: &PathBuf) -> Result<Vec<File>, Error> {
+    let delimiter = get_delimiter(filepath).map_err(|e| anyhow::anyhow!("{}", e))?;
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(delimiter)
+        .from_path(filepath)
+        .with_context(|| format!("Failed to open file {}", filepath.display()))?;
+
+    let headers = reader.headers()?.clone();
+    let mut result = Vec::new();
+
+    for (i, record) in reader.records().enumerate() {
+        let record = record.map_err(|e| anyhow::anyhow!(parse_csv_error(&e)))?;
+        let map: HashMap<String, String> = headers
+            .iter()
+            .zip(record.iter())
+            .map(|(h, v)| (h.to_string(), v.to_string()))
+            .collect();
+
+        let file_struct =
+            parse_record(&map).with_context(|| format!("Failed to parse line {}", i + 2))?;
+        result.push(file_struct);
+    }
+
+    Ok(result)
+}
+
 /// Flattens a `File` struct into a list of (key, value) string pairs suitable for TSV output.
 ///
 /// This function decomposes the `File`'s main fields and serializes associated
@@ -348,6 +390,135 @@ pub fn to_hashmap(files: &[File]) -> Result<Vec<HashMap<String, Value>>, Error>
     Ok(normalized_rows)
 }
 
+/// The columns of the flattened rows, the file fields come first and then the grouped fields, such as `url_0_url`.
+fn export_columns(rows: &[HashMap<String, Value>]) -> Vec<String> {
+    let file_columns = [
+        "guid",
+        "filename",
+        "size",
+        "created_at",
+        "updated_at",
+        "status",
+        "baseid",
+        "rev",
+        "version",
+        "uploader",
+        "access",
+        "acl",
+    ];
+    let group_regex = Regex::new(r"^([a-z]+)_(\d+)_([a-zA-Z0-9_]+)$").unwrap();
+
+    let mut columns: Vec<String> = file_columns
+        .iter()
+        .filter(|c| **c != "acl" || rows.iter().any(|row| row.contains_key("acl")))
+        .map(|c| c.to_string())
+        .collect();
+
+    let mut grouped: Vec<(String, usize, String)> = rows
+        .first()
+        .map(|row| row.keys().collect::<Vec<_>>())
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|key| {
+            let caps = group_regex.captures(key)?;
+            Some((
+                caps[1].to_string(),
+                caps[2].parse().ok()?,
+                caps[3].to_string(),
+            ))
+        })
+        .collect();
+    grouped.sort();
+
+    columns.extend(
+        grouped
+            .into_iter()
+            .map(|(prefix, idx, field)| format!("{}_{}_{}", prefix, idx, field)),
+    );
+    columns
+}
+
+fn value_to_string(value: &Value) -> String {
+    match value {
+        Value::Null => "".to_string(),
+        Value::String(s) => s.clone(),
+        v => v.to_string(),
+    }
+}
+
+/// Writes the files as a `datafile.tsv`, which can be loaded by `load_tsv()`.
+///
+/// The tabs and line breaks in the values are replaced by spaces, because the tsv has no quoting.
+pub fn write_tsv<W: Write>(files: &[File], writer: W) -> Result<(), Error> {
+    let rows = to_hashmap(files)?;
+    let columns = export_columns(&rows);
+    let mut writer = BufWriter::new(writer);
+
+    writeln!(writer, "{}", columns.join("\t"))?;
+    for row in rows.iter() {
+        let values: Vec<String> = columns
+            .iter()
+            .map(|c| {
+                row.get(c)
+                    .map(value_to_string)
+                    .unwrap_or_default()
+                    .replace(['\t', '\n', '\r'], " ")
+            })
+            .collect();
+        writeln!(writer, "{}", values.join("\t"))?;
+    }
+    writer.flush()?;
+
+    Ok(())
+}
+
+/// Writes the files as newline delimited json, one `File` per line with the nested urls, hashes, aliases and tags.
+pub fn write_ndjson<W: Write>(files: &[File], writer: W) -> Result<(), Error> {
+    let mut writer = BufWriter::new(writer);
+    for file in files {
+        serde_json::to_writer(&mut writer, file)?;
+        writeln!(writer)?;
+    }
+    writer.flush()?;
+
+    Ok(())
+}
+
+/// Writes the flattened files as a parquet file, the columns only with integers are stored as Int64 and the others as strings.
+pub fn write_parquet(files: &[File], filepath: &PathBuf) -> Result<(), Error> {
+    use polars::prelude::{DataFrame, NamedFrom, ParquetWriter, Series};
+
+    let rows = to_hashmap(files)?;
+    let columns = export_columns(&rows);
+
+    let mut series = vec![];
+    for column in columns.iter() {
+        let values: Vec<&Value> = rows
+            .iter()
+            .map(|row| row.get(column).unwrap_or(&Value::Null))
+            .collect();
+
+        let is_integer = values.iter().all(|v| v.is_null() || v.is_i64());
+        if is_integer && values.iter().any(|v| v.is_i64()) {
+            let values: Vec<Option<i64>> = values.iter().map(|v| v.as_i64()).collect();
+            series.push(Series::new(column, values));
+        } else {
+            let values: Vec<Option<String>> = values
+                .iter()
+                .map(|v| if v.is_null() { None } else { Some(value_to_string(v)) })
+                .collect();
+            series.push(Series::new(column, values));
+        }
+    }
+
+    let mut df = DataFrame::new(series)?;
+    let file = FsFile::create(filepath)
+        .with_context(|| format!("Failed to create file {}", filepath.display()))?;
+    ParquetWriter::new(file).finish(&mut df)?;
+
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -525,6 +696,42 @@ mod tests {
         assert_eq!(map["alias_0_name"], "ALIAS001");
     }
 
+    #[test]
+    fn test_write_tsv_roundtrip() {
+        let path = Path::new("examples/file_template.tsv").to_path_buf();
+        let files = load_tsv(&path).unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let out = dir.path().join("datafile.tsv");
+        write_tsv(&files, FsFile::create(&out).unwrap()).unwrap();
+        let loaded = load_tsv(&out).unwrap();
+
+        assert_eq!(loaded.len(), files.len());
+        for (a, b) in files.iter().zip(loaded.iter()) {
+            assert_eq!(a.guid, b.guid);
+            assert_eq!(a.size, b.size);
+            assert_eq!(a.acl, b.acl);
+            assert_eq!(a.hashes, b.hashes);
+            assert_eq!(a.aliases, b.aliases);
+            let urls = |f: &File| -> Vec<String> {
+                serde_json::from_value::<Vec<URL>>(f.urls.clone().unwrap())
+                    .unwrap()
+                    .into_iter()
+                    .map(|u| u.url)
+                    .collect()
+            };
+            assert_eq!(urls(a), urls(b));
+        }
+
+        let parquet = dir.path().join("datafile.parquet");
+        write_parquet(&files, &parquet).unwrap();
+        assert!(parquet.metadata().unwrap().len() > 0);
+
+        let mut ndjson = vec![];
+        write_ndjson(&files, &mut ndjson).unwrap();
+        assert_eq!(String::from_utf8(ndjson).unwrap().lines().count(), files.len());
+    }
+
     #[test]
     fn test_export_tsv_basic() {
         let files = vec![File {
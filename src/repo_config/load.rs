@@ -0,0 +1,373 @@
+//! Load the repo config from a JSON, TOML or YAML file and check every entry of it.
+//!
+//! The secrets can be kept out of the file, a secret such as `"access_secret": "env:OSS_SECRET"` is read from the
+//! environment variable and `"file:/run/secrets/oss"` is read from the file.
+use super::{
+  registry, GSAConfig, HttpConfig, LocalConfig, NodeConfig, OSSConfig, RepoConfig, S3Config,
+};
+use log::warn;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use std::path::Path;
+
+/// The fields which may be given as the references of the secrets.
+pub const SECRET_FIELDS: [&str; 6] = [
+  "access_key",
+  "access_secret",
+  "password",
+  "secret",
+  "security_token",
+  "session_token",
+];
+
+/// The longest time a signed url can be valid, the limit of the S3 presigned urls.
+const MAX_EXPIRES_IN: i64 = 7 * 24 * 60 * 60;
+
+/// Parse the text by the extension of the path, the files without a known extension are JSON.
+pub fn parse(path: &str, text: &str) -> Result<Value, String> {
+  let extension = Path::new(path)
+    .extension()
+    .and_then(|extension| extension.to_str())
+    .unwrap_or_default()
+    .to_lowercase();
+
+  match extension.as_str() {
+    "toml" => toml::from_str::<Value>(text).map_err(|e| e.to_string()),
+    "yaml" | "yml" => serde_yaml::from_str::<Value>(text).map_err(|e| e.to_string()),
+    _ => serde_json::from_str::<Value>(text).map_err(|e| e.to_string()),
+  }
+}
+
+/// Resolve a reference of a secret, the other values are kept as they are.
+fn resolve_secret(value: &str) -> Result<String, String> {
+  if let Some(name) = value.strip_prefix("env:") {
+    std::env::var(name).map_err(|_| format!("the environment variable {} is not set", name))
+  } else if let Some(path) = value.strip_prefix("file:") {
+    std::fs::read_to_string(path)
+      .map(|secret| secret.trim_end_matches(['\r', '\n']).to_string())
+      .map_err(|e| format!("cannot read the secret from {}: {}", path, e))
+  } else {
+    Ok(value.to_string())
+  }
+}
+
+/// Replace the references of the secrets with the secrets, the problems are reported with the paths of the fields.
+pub fn resolve_secrets(value: &mut Value, path: &str, problems: &mut Vec<String>) {
+  match value {
+    Value::Object(map) => {
+      for (key, value) in map.iter_mut() {
+        let path = if path.is_empty() {
+          key.clone()
+        } else {
+          format!("{}.{}", path, key)
+        };
+        match value {
+          Value::String(secret) if SECRET_FIELDS.contains(&key.as_str()) => {
+            match resolve_secret(secret) {
+              Ok(resolved) => *secret = resolved,
+              Err(e) => problems.push(format!("{}: {}", path, e)),
+            }
+          }
+          _ => resolve_secrets(value, &path, problems),
+        }
+      }
+    }
+    Value::Array(values) => {
+      for (i, value) in values.iter_mut().enumerate() {
+        resolve_secrets(value, &format!("{}[{}]", path, i), problems);
+      }
+    }
+    _ => {}
+  }
+}
+
+/// The problems of an entry of the config, such as an empty field or an invalid url.
+pub trait Check {
+  fn check(&self) -> Vec<String>;
+
+  /// The problems of the environment, such as a missing directory, they are logged and don't reject the config,
+  /// so a repo which is not ready yet doesn't block the startup or a reload.
+  fn warnings(&self) -> Vec<String> {
+    vec![]
+  }
+}
+
+fn required(field: &str, value: &str, problems: &mut Vec<String>) {
+  if value.trim().is_empty() {
+    problems.push(format!("{} is required", field));
+  }
+}
+
+fn http_url(field: &str, value: &str, problems: &mut Vec<String>) {
+  match url::Url::parse(value) {
+    Ok(url) if url.scheme() == "http" || url.scheme() == "https" => {}
+    _ => problems.push(format!("{} should be a http(s) url, but it is {:?}", field, value)),
+  }
+}
+
+fn expires_in(value: i64, problems: &mut Vec<String>) {
+  if !(1..=MAX_EXPIRES_IN).contains(&value) {
+    problems.push(format!("expires_in should be 1-{} seconds", MAX_EXPIRES_IN));
+  }
+}
+
+impl Check for NodeConfig {
+  fn check(&self) -> Vec<String> {
+    let mut problems = vec![];
+    http_url("download_url", &self.download_url, &mut problems);
+    required("account_name", &self.account_name, &mut problems);
+    required("member_id", &self.member_id, &mut problems);
+    required("project_id", &self.project_id, &mut problems);
+    problems
+  }
+}
+
+impl Check for GSAConfig {
+  fn check(&self) -> Vec<String> {
+    let mut problems = vec![];
+    http_url("download_url", &self.download_url, &mut problems);
+    required("account_name", &self.account_name, &mut problems);
+    required("shared_id", &self.shared_id, &mut problems);
+    required("project_id", &self.project_id, &mut problems);
+    problems
+  }
+}
+
+impl Check for HttpConfig {
+  fn check(&self) -> Vec<String> {
+    let mut problems = vec![];
+    http_url("base_url", &self.base_url, &mut problems);
+    problems
+  }
+}
+
+impl Check for OSSConfig {
+  fn check(&self) -> Vec<String> {
+    let mut problems = vec![];
+    required("access_key", &self.access_key, &mut problems);
+    required("access_secret", &self.access_secret, &mut problems);
+    required("endpoint", &self.endpoint, &mut problems);
+    required("bucket", &self.bucket, &mut problems);
+    expires_in(self.expires_in, &mut problems);
+    match self.signature_version.as_str() {
+      "v1" => {}
+      "v4" if super::Sign::region(self).is_none() => problems.push(
+        "region is required by the v4 signature, it cannot be derived from the endpoint".to_string(),
+      ),
+      "v4" => {}
+      version => problems.push(format!("signature_version should be v1 or v4, but it is {}", version)),
+    }
+    problems
+  }
+}
+
+impl Check for S3Config {
+  fn check(&self) -> Vec<String> {
+    let mut problems = vec![];
+    required("access_key", &self.access_key, &mut problems);
+    required("access_secret", &self.access_secret, &mut problems);
+    http_url("endpoint", &self.endpoint, &mut problems);
+    required("bucket", &self.bucket, &mut problems);
+    required("region", &self.region, &mut problems);
+    expires_in(self.expires_in, &mut problems);
+    problems
+  }
+}
+
+impl Check for LocalConfig {
+  fn check(&self) -> Vec<String> {
+    let mut problems = vec![];
+    if !Path::new(&self.root).is_absolute() {
+      problems.push(format!("root should be an absolute path, but it is {:?}", self.root));
+    }
+    if self.secret.len() < 16 {
+      problems.push("secret should have at least 16 characters".to_string());
+    }
+    http_url("download_url", &self.download_url, &mut problems);
+    expires_in(self.expires_in, &mut problems);
+    problems
+  }
+
+  fn warnings(&self) -> Vec<String> {
+    let root = Path::new(&self.root);
+    if root.is_absolute() && !root.is_dir() {
+      vec![format!("root {} is not a directory", self.root)]
+    } else {
+      vec![]
+    }
+  }
+}
+
+/// Check every entry of a section, an entry which cannot be read is reported instead of the whole config.
+fn check_section<T: DeserializeOwned + Check>(value: &Value, section: &str, problems: &mut Vec<String>) {
+  let entries = match value.get(section) {
+    None | Some(Value::Null) => return,
+    Some(Value::Array(entries)) => entries,
+    Some(_) => {
+      problems.push(format!("{}: should be a list of the repos", section));
+      return;
+    }
+  };
+
+  for (i, entry) in entries.iter().enumerate() {
+    match serde_json::from_value::<T>(entry.clone()) {
+      Ok(config) => {
+        for problem in config.check() {
+          problems.push(format!("{}[{}]: {}", section, i, problem));
+        }
+        for warning in config.warnings() {
+          warn!("{}[{}]: {}", section, i, warning);
+        }
+      }
+      Err(e) => problems.push(format!("{}[{}]: {}", section, i, e)),
+    }
+  }
+}
+
+/// Read the config from the value whose secrets have been resolved, all the problems are reported at once.
+pub fn check_value(value: &Value) -> Result<RepoConfig, Vec<String>> {
+  let mut problems = vec![];
+  check_section::<NodeConfig>(value, "node", &mut problems);
+  check_section::<GSAConfig>(value, "gsa", &mut problems);
+  check_section::<OSSConfig>(value, "oss", &mut problems);
+  check_section::<S3Config>(value, "s3", &mut problems);
+  check_section::<S3Config>(value, "minio", &mut problems);
+  check_section::<HttpConfig>(value, "http", &mut problems);
+  check_section::<LocalConfig>(value, "local", &mut problems);
+  if !problems.is_empty() {
+    return Err(problems);
+  }
+
+  let config = serde_json::from_value::<RepoConfig>(value.clone()).map_err(|e| vec![e.to_string()])?;
+  let protocols = registry().protocols();
+  for protocol in config.policy.protocols.iter() {
+    if !protocols.contains(&protocol.as_str()) {
+      problems.push(format!("policy.protocols: unknown protocol {}", protocol));
+    }
+  }
+  for section in config.others.keys() {
+    if !protocols.contains(&section.as_str()) {
+      problems.push(format!("{}: no repo provider is registered for it", section));
+    }
+  }
+
+  if problems.is_empty() {
+    Ok(config)
+  } else {
+    Err(problems)
+  }
+}
+
+/// Read, resolve and check the config file.
+pub fn load(path: &str) -> Result<RepoConfig, Vec<String>> {
+  let text = std::fs::read_to_string(path).map_err(|e| vec![format!("{}: {}", path, e)])?;
+  let mut value = parse(path, &text).map_err(|e| vec![format!("{}: {}", path, e)])?;
+
+  let mut problems = vec![];
+  resolve_secrets(&mut value, "", &mut problems);
+  match check_value(&value) {
+    Ok(config) if problems.is_empty() => Ok(config),
+    Ok(_) => Err(problems),
+    Err(others) => {
+      problems.extend(others);
+      Err(problems)
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_parse() {
+    let toml = r#"
+      [[s3]]
+      access_key = "s3admin"
+      access_secret = "env:INDEXD_TEST_S3_SECRET"
+      endpoint = "http://127.0.0.1:9000"
+      bucket = "test"
+    "#;
+    let yaml = r#"
+      s3:
+        - access_key: s3admin
+          access_secret: env:INDEXD_TEST_S3_SECRET
+          endpoint: http://127.0.0.1:9000
+          bucket: test
+    "#;
+    let expected = parse("config.toml", toml).unwrap();
+    assert_eq!(parse("config.yaml", yaml).unwrap(), expected);
+    assert_eq!(parse("config.json", &expected.to_string()).unwrap(), expected);
+    assert!(parse("config.toml", yaml).is_err());
+  }
+
+  #[test]
+  fn test_resolve_secrets() {
+    let dir = tempfile::tempdir().unwrap();
+    let secret_file = dir.path().join("secret");
+    std::fs::write(&secret_file, "from-file\n").unwrap();
+    std::env::set_var("INDEXD_TEST_OSS_SECRET", "from-env");
+
+    let mut value = serde_json::json!({
+      "oss": [{"access_key": "env:INDEXD_TEST_OSS_SECRET", "access_secret": format!("file:{}", secret_file.display())}],
+      "local": [{"root": "file:/not/a/secret", "secret": "env:INDEXD_TEST_NOT_SET"}],
+    });
+    let mut problems = vec![];
+    resolve_secrets(&mut value, "", &mut problems);
+    assert_eq!(value["oss"][0]["access_key"], "from-env");
+    assert_eq!(value["oss"][0]["access_secret"], "from-file");
+    assert_eq!(value["local"][0]["root"], "file:/not/a/secret");
+    assert_eq!(
+      problems,
+      vec!["local[0].secret: the environment variable INDEXD_TEST_NOT_SET is not set"]
+    );
+  }
+
+  #[test]
+  fn test_check_value() {
+    let root = tempfile::tempdir().unwrap();
+    let value = serde_json::json!({
+      "gsa": [
+        {"download_url": "https://share.cncb.ac.cn/", "account_name": "a", "shared_id": "s", "project_id": "HRA0001"},
+        {"download_url": "https://share.cncb.ac.cn/", "account_name": "a", "project_id": "HRA0002"},
+      ],
+      "oss": [{
+        "access_key": "a", "access_secret": "s", "endpoint": "http://127.0.0.1:9000", "bucket": "b"
+      }],
+      "local": [{
+        "root": root.path(), "secret": "short", "download_url": "localhost", "expires_in": 0
+      }],
+    });
+    let problems = check_value(&value).unwrap_err();
+    assert_eq!(problems.len(), 5);
+    assert_eq!(problems[0], "gsa[1]: missing field `shared_id`");
+    assert!(problems[1].starts_with("oss[0]: region is required"));
+    assert!(problems[2].starts_with("local[0]: secret"));
+    assert!(problems[3].starts_with("local[0]: download_url"));
+    assert!(problems[4].starts_with("local[0]: expires_in"));
+
+    let value = serde_json::json!({
+      "gsa": [value["gsa"][0].clone()],
+      "policy": {"protocols": ["gsa", "ftp"]},
+      "gas": [],
+    });
+    assert_eq!(
+      check_value(&value).unwrap_err(),
+      vec![
+        "policy.protocols: unknown protocol ftp",
+        "gas: no repo provider is registered for it",
+      ]
+    );
+
+    // A missing root is only a warning, the other repos are still served
+    let missing = root.path().join("missing");
+    let value = serde_json::json!({
+      "local": [{
+        "root": missing, "secret": "please-change-the-secret", "download_url": "http://localhost:3000/api/v1/download/local"
+      }],
+    });
+    assert!(check_value(&value).is_ok());
+
+    assert!(load("examples/config.json").is_ok());
+  }
+}
@@ -0,0 +1,256 @@
+//! The signed urls of Alibaba Cloud OSS, both the V1 (HMAC-SHA1) and the V4 (OSS4-HMAC-SHA256) signatures are supported, see
+//! https://www.alibabacloud.com/help/en/oss/developer-reference/ddd-signatures-to-urls
+use super::sigv4::uri_encode;
+use super::{hmac, SignData};
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use openssl::hash::MessageDigest;
+
+const ALGORITHM: &str = "OSS4-HMAC-SHA256";
+
+/// The region of the public endpoints, such as oss-cn-hangzhou.aliyuncs.com -> cn-hangzhou.
+pub fn region_of(host: &str) -> Option<String> {
+  let name = host.split('.').next()?;
+  let region = name.strip_prefix("oss-")?;
+  let region = region.strip_suffix("-internal").unwrap_or(region);
+  Some(region.to_string())
+}
+
+/// Let the downloaded file be named as the filename.
+pub fn content_disposition(filename: &str) -> String {
+  format!("attachment; filename=\"{}\"", filename.replace(['"', '\\'], "_"))
+}
+
+/// A GET request to sign, the signature is carried by the query string.
+#[derive(Debug, Clone)]
+pub struct SignedUrl<'a> {
+  pub endpoint: &'a str, // "https://oss-cn-hangzhou.aliyuncs.com", the scheme is https if omitted
+  pub bucket: &'a str,
+  pub key: &'a str,
+  pub access_key: &'a str,
+  pub access_secret: &'a str,
+  // The security token of the STS temporary credentials
+  pub security_token: Option<&'a str>,
+  pub expires_in: i64,
+  pub filename: Option<&'a str>,
+}
+
+impl<'a> SignedUrl<'a> {
+  /// The scheme and the host of the endpoint.
+  fn base(&self) -> Result<(String, String), String> {
+    let endpoint = if self.endpoint.contains("://") {
+      self.endpoint.to_string()
+    } else {
+      format!("https://{}", self.endpoint)
+    };
+    let endpoint = url::Url::parse(&endpoint)
+      .map_err(|e| format!("Invalid endpoint {}: {}", self.endpoint, e))?;
+    let host = match (endpoint.host_str(), endpoint.port()) {
+      (Some(host), Some(port)) => format!("{}:{}", host, port),
+      (Some(host), None) => host.to_string(),
+      (None, _) => return Err(format!("Invalid endpoint {}: no host", self.endpoint)),
+    };
+    Ok((endpoint.scheme().to_string(), host))
+  }
+
+  /// The sub-resources to sign, the values are not encoded.
+  fn resources(&self) -> Vec<(String, String)> {
+    let mut resources = vec![];
+    if let Some(filename) = self.filename {
+      let disposition = content_disposition(filename);
+      resources.push(("response-content-disposition".to_string(), disposition));
+    }
+    resources
+  }
+
+  /// The buckets of OSS are always addressed as <bucket>.<endpoint host>.
+  fn sign_data(
+    &self,
+    scheme: &str,
+    host: &str,
+    params: Vec<(String, String)>,
+    expires_at: i64,
+  ) -> SignData {
+    SignData {
+      headers: vec![],
+      form: vec![],
+      baseurl: format!("{}://{}.{}/{}", scheme, self.bucket, host, uri_encode(self.key, true)),
+      method: "GET".to_string(),
+      params: params
+        .iter()
+        .map(|(key, value)| {
+          format!("{}={}", uri_encode(key, false), uri_encode(value, false))
+        })
+        .collect(),
+      expires_at: Some(expires_at),
+    }
+  }
+
+  /// Sign with the V1 signature, the url expires at `now + expires_in`.
+  pub fn sign_v1_at(&self, now: DateTime<Utc>) -> Result<SignData, String> {
+    let (scheme, host) = self.base()?;
+    let expires = now.timestamp() + self.expires_in;
+
+    let mut resources = self.resources();
+    if let Some(token) = self.security_token {
+      resources.push(("security-token".to_string(), token.to_string()));
+    }
+    resources.sort();
+
+    let mut canonical_resource = format!("/{}/{}", self.bucket, self.key);
+    if !resources.is_empty() {
+      let resources = resources
+        .iter()
+        .map(|(key, value)| format!("{}={}", key, value))
+        .collect::<Vec<String>>();
+      canonical_resource = format!("{}?{}", canonical_resource, resources.join("&"));
+    }
+    let string_to_sign = format!("GET\n\n\n{}\n{}", expires, canonical_resource);
+    let secret = self.access_secret.as_bytes();
+    let signature = hmac(MessageDigest::sha1(), secret, string_to_sign.as_bytes());
+
+    let mut params = vec![
+      ("OSSAccessKeyId".to_string(), self.access_key.to_string()),
+      ("Expires".to_string(), expires.to_string()),
+      (
+        "Signature".to_string(),
+        base64::engine::general_purpose::STANDARD.encode(signature),
+      ),
+    ];
+    params.extend(resources);
+    Ok(self.sign_data(&scheme, &host, params, expires))
+  }
+
+  /// Sign with the V4 signature, the region is derived from the endpoint when it is not given.
+  pub fn sign_v4_at(&self, region: Option<&str>, now: DateTime<Utc>) -> Result<SignData, String> {
+    let (scheme, host) = self.base()?;
+    let region = match region {
+      Some(region) => region.to_string(),
+      None => region_of(&host)
+        .ok_or_else(|| format!("Cannot derive the region from the endpoint {}", self.endpoint))?,
+    };
+
+    let datetime = now.format("%Y%m%dT%H%M%SZ").to_string();
+    let date = now.format("%Y%m%d").to_string();
+    let scope = format!("{}/{}/oss/aliyun_v4_request", date, region);
+
+    let mut params = vec![
+      ("x-oss-credential".to_string(), format!("{}/{}", self.access_key, scope)),
+      ("x-oss-date".to_string(), datetime.clone()),
+      ("x-oss-expires".to_string(), self.expires_in.to_string()),
+      ("x-oss-signature-version".to_string(), ALGORITHM.to_string()),
+    ];
+    if let Some(token) = self.security_token {
+      params.push(("x-oss-security-token".to_string(), token.to_string()));
+    }
+    params.extend(self.resources());
+
+    let mut query = params
+      .iter()
+      .map(|(key, value)| (uri_encode(key, false), uri_encode(value, false)))
+      .collect::<Vec<(String, String)>>();
+    query.sort();
+    let query = query
+      .iter()
+      .map(|(key, value)| format!("{}={}", key, value))
+      .collect::<Vec<String>>();
+
+    // No header is signed, so the canonical headers and the additional headers are empty.
+    let canonical_request = format!(
+      "GET\n{}\n{}\n\n\nUNSIGNED-PAYLOAD",
+      uri_encode(&format!("/{}/{}", self.bucket, self.key), true),
+      query.join("&")
+    );
+    let string_to_sign = format!(
+      "{}\n{}\n{}\n{}",
+      ALGORITHM,
+      datetime,
+      scope,
+      hex::encode(openssl::sha::sha256(canonical_request.as_bytes()))
+    );
+
+    let sha256 = MessageDigest::sha256;
+    let secret = format!("aliyun_v4{}", self.access_secret);
+    let key = hmac(sha256(), secret.as_bytes(), date.as_bytes());
+    let key = hmac(sha256(), &key, region.as_bytes());
+    let key = hmac(sha256(), &key, b"oss");
+    let key = hmac(sha256(), &key, b"aliyun_v4_request");
+    let signature = hex::encode(hmac(sha256(), &key, string_to_sign.as_bytes()));
+
+    params.push(("x-oss-signature".to_string(), signature));
+    Ok(self.sign_data(&scheme, &host, params, now.timestamp() + self.expires_in))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use chrono::TimeZone;
+
+  fn example() -> SignedUrl<'static> {
+    SignedUrl {
+      endpoint: "oss-cn-hangzhou.aliyuncs.com",
+      bucket: "examplebucket",
+      key: "exampledir/example object.txt",
+      access_key: "LTAI5tExampleKeyId",
+      access_secret: "ExampleAccessKeySecret",
+      security_token: None,
+      expires_in: 3600,
+      filename: Some("example.txt"),
+    }
+  }
+
+  #[test]
+  fn test_region_of() {
+    assert_eq!(region_of("oss-cn-hangzhou.aliyuncs.com"), Some("cn-hangzhou".to_string()));
+    assert_eq!(region_of("oss-cn-beijing-internal.aliyuncs.com"), Some("cn-beijing".to_string()));
+    assert_eq!(region_of("127.0.0.1:9000"), None);
+  }
+
+  #[test]
+  fn test_sign_v1() {
+    let now = Utc.with_ymd_and_hms(2023, 12, 3, 12, 0, 0).unwrap();
+    let signed = SignedUrl {
+      security_token: Some("sts-token"),
+      ..example()
+    }
+    .sign_v1_at(now)
+    .unwrap();
+
+    assert_eq!(
+      signed.baseurl,
+      "https://examplebucket.oss-cn-hangzhou.aliyuncs.com/exampledir/example%20object.txt"
+    );
+    assert_eq!(
+      signed.params,
+      vec![
+        "OSSAccessKeyId=LTAI5tExampleKeyId",
+        "Expires=1701608400",
+        "Signature=piyxXrp2yy0HhpMSLtDxeLx4r6k%3D",
+        "response-content-disposition=attachment%3B%20filename%3D%22example.txt%22",
+        "security-token=sts-token",
+      ]
+    );
+  }
+
+  #[test]
+  fn test_sign_v4() {
+    let now = Utc.with_ymd_and_hms(2023, 12, 3, 12, 0, 0).unwrap();
+    let signed = example().sign_v4_at(None, now).unwrap();
+    assert_eq!(
+      signed.params.last().unwrap(),
+      "x-oss-signature=1e5b46fc757211c359895ccee81ad361e190e05aba80b5a6f8e1f967e9838fc4"
+    );
+    let credential = "LTAI5tExampleKeyId%2F20231203%2Fcn-hangzhou%2Foss%2Faliyun_v4_request";
+    assert!(signed.params.contains(&format!("x-oss-credential={}", credential)));
+
+    // The region of a private endpoint must be given
+    let private = SignedUrl {
+      endpoint: "http://127.0.0.1:9000",
+      ..example()
+    };
+    assert!(private.sign_v4_at(None, now).is_err());
+    let signed = private.sign_v4_at(Some("cn-hangzhou"), now).unwrap();
+    assert!(signed.baseurl.starts_with("http://examplebucket.127.0.0.1:9000/"));
+  }
+}
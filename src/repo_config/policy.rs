@@ -0,0 +1,253 @@
+//! Which url of a file to sign when the file has been released on several repos.
+use super::{which_protocol, RepoConfig, Sign};
+use crate::model::datafile::URL;
+use custom_error::custom_error;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+custom_error! {pub SelectionError
+  NotOnRepo{repo: String} = "The data has not been released on {repo} repo, please contact the administrator to add it.",
+  NotReleased{detail: String} = "The data has not been released, please contact the administrator for more details. ({detail})",
+}
+
+fn default_protocols() -> Vec<String> {
+  ["node", "gsa", "oss", "s3", "minio", "file", "http"]
+    .iter()
+    .map(|protocol| protocol.to_string())
+    .collect()
+}
+
+fn default_exclude_failed() -> bool {
+  true
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SelectionPolicy {
+  // The protocols in the order of preference, the urls of the other protocols are only signed when they are requested
+  #[serde(default = "default_protocols")]
+  pub protocols: Vec<String>,
+  // Never sign the urls which are marked as failed, such as by the url checker or the scrubber
+  #[serde(default = "default_exclude_failed")]
+  pub exclude_failed: bool,
+  // The regions of the repos which have no region in their configs, such as {"node": "cn-shanghai"}
+  #[serde(default)]
+  pub regions: HashMap<String, String>,
+}
+
+impl Default for SelectionPolicy {
+  fn default() -> Self {
+    SelectionPolicy {
+      protocols: default_protocols(),
+      exclude_failed: default_exclude_failed(),
+      regions: HashMap::new(),
+    }
+  }
+}
+
+/// The chosen url, the config to sign it and why it is chosen.
+pub struct Selection {
+  pub url: String,
+  pub protocol: String,
+  pub reason: String,
+  pub config: Box<dyn Sign>,
+}
+
+impl RepoConfig {
+  /// Choose the url to sign, the urls are ranked by the region hint and then by the preference of the protocols.
+  ///
+  /// Only the urls of `which_repo` are considered if it is given. A url whose repo has no credentials is skipped,
+  /// so the next repo is tried.
+  pub fn select(
+    &self,
+    urls: &[URL],
+    which_repo: Option<&str>,
+    region: Option<&str>,
+  ) -> Result<Selection, SelectionError> {
+    let policy = &self.policy;
+    let mut skipped = vec![];
+    let mut no_credentials = false;
+    let mut candidates = vec![];
+    for url in urls {
+      let protocol = which_protocol(&url.url).unwrap_or_default();
+      let rank = match which_repo {
+        Some(which_repo) if which_repo == protocol => 0,
+        Some(_) => continue,
+        None => match policy.protocols.iter().position(|p| p == protocol) {
+          Some(rank) => rank,
+          None => continue,
+        },
+      };
+
+      if policy.exclude_failed && url.status == "failed" {
+        skipped.push(format!("{} is failed", url.url));
+        continue;
+      }
+
+      match self.fetch_config(protocol, &url.get_identity()) {
+        Some(config) => {
+          let url_region = config.region().or_else(|| policy.regions.get(protocol).cloned());
+          let in_region = region.is_some() && url_region.as_deref() == region;
+          candidates.push((!in_region, rank, url, protocol, config));
+        }
+        None => {
+          no_credentials = true;
+          skipped.push(format!("{} has no credentials", url.url));
+        }
+      }
+    }
+
+    // The sort is stable, so the urls of the same rank are kept in the order they were added.
+    candidates.sort_by_key(|(out_of_region, rank, ..)| (*out_of_region, *rank));
+    let (out_of_region, rank, url, protocol, config) = match candidates.into_iter().next() {
+      Some(candidate) => candidate,
+      None if no_credentials => {
+        return Err(SelectionError::NotReleased {
+          detail: skipped.join(", "),
+        })
+      }
+      None => {
+        return Err(SelectionError::NotOnRepo {
+          repo: match which_repo {
+            Some(which_repo) => which_repo.to_string(),
+            None => policy.protocols.join("/"),
+          },
+        })
+      }
+    };
+
+    let mut reasons = vec![match which_repo {
+      Some(_) => format!("{} is requested", protocol),
+      None => format!("{} is the preferred protocol #{}", protocol, rank + 1),
+    }];
+    if let Some(region) = region {
+      if out_of_region {
+        reasons.push(format!("no repo is in the region {}", region));
+      } else {
+        reasons.push(format!("the repo is in the region {}", region));
+      }
+    }
+    if !skipped.is_empty() {
+      reasons.push(format!("skipped {}", skipped.join(", ")));
+    }
+
+    Ok(Selection {
+      url: url.url.clone(),
+      protocol: protocol.to_string(),
+      reason: reasons.join("; "),
+      config,
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn url(url: &str, status: &str) -> URL {
+    URL {
+      id: 0,
+      url: url.to_string(),
+      created_at: 0,
+      status: status.to_string(),
+      uploader: "test".to_string(),
+      file: None,
+      reason: None,
+      checked_at: None,
+    }
+  }
+
+  fn config() -> RepoConfig {
+    RepoConfig::read_config_data(
+      r#"
+      {
+        "node": [{
+          "download_url": "https://www.biosino.org/download/downloadByMember",
+          "account_name": "yjcyxky@163.com",
+          "member_id": "P34MVSHVGBDSLOQ6BGSAL4SFEN",
+          "project_id": "OEP003178"
+        }],
+        "oss": [{
+          "access_key": "ossadmin",
+          "access_secret": "ossadmin",
+          "endpoint": "https://oss-cn-hangzhou.aliyuncs.com",
+          "bucket": "test"
+        }],
+        "s3": [{
+          "access_key": "s3admin",
+          "access_secret": "s3admin",
+          "endpoint": "https://s3.amazonaws.com",
+          "bucket": "test",
+          "region": "us-west-2"
+        }],
+        "policy": {
+          "protocols": ["node", "oss", "s3"],
+          "regions": {"node": "cn-shanghai"}
+        }
+      }
+    "#,
+    )
+    .unwrap()
+  }
+
+  #[test]
+  fn test_select() {
+    let config = config();
+    let urls = vec![
+      url("s3://test/a.txt", "validated"),
+      url("oss://test/a.txt", "pending"),
+      url("node://yjcyxky@163.com/OEP003178/OEX1/OES1/OER1/OED1", "validated"),
+    ];
+
+    let selection = config.select(&urls, None, None).unwrap();
+    assert_eq!(selection.protocol, "node");
+    assert_eq!(selection.reason, "node is the preferred protocol #1");
+
+    // The region hint goes before the preference
+    let selection = config.select(&urls, None, Some("us-west-2")).unwrap();
+    assert_eq!(selection.url, "s3://test/a.txt");
+    let selection = config.select(&urls, None, Some("cn-hangzhou")).unwrap();
+    assert_eq!(selection.protocol, "oss");
+    let selection = config.select(&urls, None, Some("mars")).unwrap();
+    assert_eq!(selection.protocol, "node");
+    assert!(selection.reason.contains("no repo is in the region mars"));
+
+    // Not a substring of the url, but the protocol
+    let selection = config.select(&urls, Some("s3"), None).unwrap();
+    assert_eq!(selection.reason, "s3 is requested");
+    assert!(matches!(
+      config.select(&urls[..2], Some("node"), None),
+      Err(SelectionError::NotOnRepo { .. })
+    ));
+  }
+
+  #[test]
+  fn test_select_fallback() {
+    let config = config();
+    let urls = vec![
+      url("node://yjcyxky@163.com/OEP000000/OEX1/OES1/OER1/OED1", "validated"),
+      url("oss://test/a.txt", "failed"),
+      url("s3://test/a.txt", "validated"),
+      url("gsa://yjcyxky@163.com/HRA0001/a.txt", "validated"),
+    ];
+
+    let selection = config.select(&urls, None, None).unwrap();
+    assert_eq!(selection.protocol, "s3");
+    assert!(selection.reason.contains("OEP000000/OEX1/OES1/OER1/OED1 has no credentials"));
+    assert!(selection.reason.contains("oss://test/a.txt is failed"));
+
+    // Not in the preferred protocols
+    assert!(matches!(
+      config.select(&urls[3..], None, None),
+      Err(SelectionError::NotOnRepo { .. })
+    ));
+    assert!(matches!(
+      config.select(&urls[..1], None, None),
+      Err(SelectionError::NotReleased { .. })
+    ));
+
+    // The failed urls can be signed if the policy allows
+    let mut config = config;
+    config.policy.exclude_failed = false;
+    assert_eq!(config.select(&urls, None, None).unwrap().protocol, "oss");
+  }
+}
@@ -0,0 +1,288 @@
+//! The kinds of repositories which the files are archived in, every kind is a provider registered by its protocol.
+//!
+//! A provider knows which urls belong to it, which repo a url is in and where the credentials of the repo are.
+//! To support a new archive, implement `RepoProvider` (or describe it with a `PatternProvider`) and call
+//! `register_provider` at startup, before the repo config is loaded. Its credentials are read from the section
+//! of its protocol in the config, see `find_section`, so neither `Registry::default` nor `RepoConfig` is changed.
+use super::{RepoConfig, Sign};
+use arc_swap::ArcSwap;
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde::de::DeserializeOwned;
+use std::sync::Arc;
+
+pub trait RepoProvider: Send + Sync {
+  /// The protocol of the urls, such as node in node://...
+  fn protocol(&self) -> &'static str;
+
+  /// Whether the url is a valid url of the provider.
+  fn validate(&self, url: &str) -> bool;
+
+  /// The identity of the repo which the url belongs to, such as the bucket or the project, it is used to find the credentials.
+  fn identity(&self, url: &str) -> String;
+
+  /// The credentials of the repo in the config, which signs the urls of the repo.
+  fn signer(&self, config: &RepoConfig, identity: &str) -> Option<Box<dyn Sign>>;
+}
+
+/// A provider whose urls are matched by a regex, the identity of the repo is one of the capture groups.
+pub struct PatternProvider {
+  pub protocol: &'static str,
+  pub pattern: Regex,
+  // The capture group of the identity, the identity is empty if it is not set
+  pub identity_group: Option<usize>,
+  pub signer: fn(&RepoConfig, &str) -> Option<Box<dyn Sign>>,
+}
+
+impl PatternProvider {
+  pub fn new(
+    protocol: &'static str,
+    pattern: &str,
+    identity_group: Option<usize>,
+    signer: fn(&RepoConfig, &str) -> Option<Box<dyn Sign>>,
+  ) -> Self {
+    PatternProvider {
+      protocol,
+      pattern: Regex::new(pattern).expect("Invalid url pattern"),
+      identity_group,
+      signer,
+    }
+  }
+}
+
+impl RepoProvider for PatternProvider {
+  fn protocol(&self) -> &'static str {
+    self.protocol
+  }
+
+  fn validate(&self, url: &str) -> bool {
+    self.pattern.is_match(url)
+  }
+
+  fn identity(&self, url: &str) -> String {
+    let captures = self.identity_group.zip(self.pattern.captures(url));
+    match captures.and_then(|(group, captures)| captures.get(group)) {
+      Some(identity) => identity.as_str().to_string(),
+      None => "".to_string(),
+    }
+  }
+
+  fn signer(&self, config: &RepoConfig, identity: &str) -> Option<Box<dyn Sign>> {
+    (self.signer)(config, identity)
+  }
+}
+
+/// The first config which the predicate accepts.
+fn find<T, F>(configs: &Option<Vec<T>>, predicate: F) -> Option<Box<dyn Sign>>
+where
+  T: Sign + Clone + 'static,
+  F: Fn(&T) -> bool,
+{
+  configs
+    .iter()
+    .flatten()
+    .find(|config| predicate(config))
+    .map(|config| Box::new(config.clone()) as Box<dyn Sign>)
+}
+
+/// The first config in the section of the protocol which the predicate accepts, for the providers which are not built in.
+/// An invalid section is reported when the config is loaded, so it is treated as empty here.
+pub fn find_section<T, F>(config: &RepoConfig, protocol: &str, predicate: F) -> Option<Box<dyn Sign>>
+where
+  T: Sign + DeserializeOwned + 'static,
+  F: Fn(&T) -> bool,
+{
+  let configs = config.section::<T>(protocol)?.ok()?;
+  configs
+    .into_iter()
+    .find(|config| predicate(config))
+    .map(|config| Box::new(config) as Box<dyn Sign>)
+}
+
+/// The providers by their protocols, a provider registered later replaces the one of the same protocol.
+#[derive(Clone)]
+pub struct Registry {
+  providers: Vec<Arc<dyn RepoProvider>>,
+}
+
+impl Registry {
+  pub fn new() -> Self {
+    Registry { providers: vec![] }
+  }
+
+  pub fn register(&mut self, provider: impl RepoProvider + 'static) {
+    self.insert(Arc::new(provider));
+  }
+
+  fn insert(&mut self, provider: Arc<dyn RepoProvider>) {
+    self.providers.retain(|p| p.protocol() != provider.protocol());
+    self.providers.push(provider);
+  }
+
+  pub fn get(&self, protocol: &str) -> Option<&dyn RepoProvider> {
+    self.providers.iter().find(|p| p.protocol() == protocol).map(|p| p.as_ref())
+  }
+
+  /// The provider which the url belongs to.
+  pub fn provider_of(&self, url: &str) -> Option<&dyn RepoProvider> {
+    self.providers.iter().find(|p| p.validate(url)).map(|p| p.as_ref())
+  }
+
+  pub fn protocols(&self) -> Vec<&'static str> {
+    self.providers.iter().map(|p| p.protocol()).collect()
+  }
+}
+
+impl Default for Registry {
+  /// The built-in providers.
+  fn default() -> Self {
+    let mut registry = Registry::new();
+    registry.register(PatternProvider::new(
+      "node",
+      r"^node://([a-zA-Z0-9@\-_.]+)/(OEP[0-9]+)/(OEX[0-9]+)/(OES[0-9]+)/(OER[0-9]+)/(OED[0-9]+)$",
+      Some(2),
+      |config, identity| find(&config.node, |c| c.project_id == identity),
+    ));
+    registry.register(PatternProvider::new(
+      "gsa",
+      r"^gsa://([a-zA-Z0-9@\-_.]+)/(HRA[0-9]+)/(HRS[0-9]+)/(HRX[0-9]+)/(HRR[0-9]+)/(HRR[0-9]+.*)$",
+      Some(2),
+      |config, identity| find(&config.gsa, |c| c.project_id == identity),
+    ));
+    registry.register(PatternProvider::new(
+      "oss",
+      r"^oss://([a-zA-Z0-9@\-_.]+)/(.*)$",
+      Some(1),
+      |config, identity| find(&config.oss, |c| c.bucket == identity),
+    ));
+    registry.register(PatternProvider::new(
+      "s3",
+      r"^s3://([a-zA-Z0-9@\-_.]+)/(.*)$",
+      Some(1),
+      |config, identity| find(&config.s3, |c| c.bucket == identity),
+    ));
+    registry.register(PatternProvider::new(
+      "minio",
+      r"^minio://([a-zA-Z0-9@\-_.]+)/(.*)$",
+      Some(1),
+      |config, identity| find(&config.minio, |c| c.bucket == identity),
+    ));
+    registry.register(PatternProvider::new(
+      "http",
+      r"^https?://([a-zA-Z0-9\-_.:]+)/(.*)$",
+      None,
+      |config, _| find(&config.http, |_| true),
+    ));
+    registry.register(PatternProvider::new(
+      "file",
+      r"^file://(/.+)$",
+      Some(1),
+      |config, identity| {
+        config
+          .fetch_local(identity)
+          .map(|config| Box::new(config.clone()) as Box<dyn Sign>)
+      },
+    ));
+    registry
+  }
+}
+
+lazy_static! {
+  static ref REGISTRY: ArcSwap<Registry> = ArcSwap::from_pointee(Registry::default());
+}
+
+/// The providers of the process, the built-in ones and the ones registered by `register_provider`.
+pub fn registry() -> Arc<Registry> {
+  REGISTRY.load_full()
+}
+
+/// Register a provider for the whole process, it replaces the provider of the same protocol.
+///
+/// Call it at startup before the repo config is loaded, the sections of the config are checked against the registered protocols.
+pub fn register_provider(provider: impl RepoProvider + 'static) {
+  let provider: Arc<dyn RepoProvider> = Arc::new(provider);
+  REGISTRY.rcu(|current| {
+    let mut registry = Registry::clone(current);
+    registry.insert(provider.clone());
+    registry
+  });
+}
+
+/// The protocol of the url, none if no provider accepts it.
+pub fn which_protocol(url: &str) -> Option<&'static str> {
+  registry().provider_of(url).map(|p| p.protocol())
+}
+
+/// The identity of the repo which the url belongs to, it is empty if no provider accepts the url.
+pub fn identity_of(url: &str) -> String {
+  match registry().provider_of(url) {
+    Some(provider) => provider.identity(url),
+    None => "".to_string(),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::repo_config::HttpConfig;
+
+  #[test]
+  fn test_which_protocol() {
+    let node = "node://yjcyxky@163.com/OEP003178/OEX015832/OES135568/OER249692/OED718095";
+    assert_eq!(which_protocol(node), Some("node"));
+    assert_eq!(which_protocol("node://yjcyxky@163.com/OEP003178"), None);
+    assert_eq!(which_protocol("https://example.com/a.txt"), Some("http"));
+    assert_eq!(which_protocol("minio://test/a.txt"), Some("minio"));
+    assert_eq!(which_protocol("ftp://example.com/a.txt"), None);
+
+    assert_eq!(identity_of(node), "OEP003178");
+    assert_eq!(identity_of("s3://test/a/b.txt"), "test");
+    assert_eq!(identity_of("file:///data/a.txt"), "/data/a.txt");
+    assert_eq!(identity_of("http://example.com/a.txt"), "");
+  }
+
+  #[test]
+  fn test_register() {
+    let mut registry = Registry::default();
+    let count = registry.protocols().len();
+    registry.register(PatternProvider::new(
+      "ena",
+      r"^ena://(PRJ[A-Z]+[0-9]+)/(.+)$",
+      Some(1),
+      |config, _| find(&config.http, |_| true),
+    ));
+    assert_eq!(registry.protocols().len(), count + 1);
+    assert_eq!(registry.get("ena").unwrap().identity("ena://PRJEB1234/a.fq.gz"), "PRJEB1234");
+
+    // Replace the built-in provider
+    registry.register(PatternProvider::new("s3", r"^s3://(.+)$", None, |_, _| None));
+    assert_eq!(registry.protocols().len(), count + 1);
+    assert!(registry.provider_of("s3://a").is_some());
+  }
+
+  #[test]
+  fn test_register_provider() {
+    // The archive is registered at startup, its credentials are in its own section of the config
+    register_provider(PatternProvider::new(
+      "ega",
+      r"^ega://(EGAD[0-9]+)/(.+)$",
+      Some(1),
+      |config, identity| find_section::<HttpConfig, _>(config, "ega", |c| c.project_id == identity),
+    ));
+    assert_eq!(which_protocol("ega://EGAD00001/a.bam"), Some("ega"));
+    assert_eq!(identity_of("ega://EGAD00001/a.bam"), "EGAD00001");
+
+    let config = RepoConfig::read_config_data(
+      r#"{"ega": [{
+        "base_url": "https://ega-archive.org",
+        "account_name": "indexd",
+        "password": "password",
+        "project_id": "EGAD00001"
+      }]}"#,
+    )
+    .unwrap();
+    assert!(config.fetch_config("ega", "EGAD00001").is_some());
+    assert!(config.fetch_config("ega", "EGAD00002").is_none());
+    assert!(RepoConfig::read_config_data("{}").unwrap().fetch_config("ega", "EGAD00001").is_none());
+  }
+}
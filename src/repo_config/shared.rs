@@ -0,0 +1,125 @@
+//! The repo config of the running server, it is reloaded on SIGHUP or when the file changes.
+//!
+//! A request keeps the config it started with, so a reload never drops the requests in flight. A config which
+//! cannot be loaded is reported and the current one is kept.
+use super::{load, RepoConfig};
+use arc_swap::ArcSwap;
+use log::{error, info};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+#[derive(Clone)]
+pub struct SharedConfig {
+  path: String,
+  current: Arc<ArcSwap<RepoConfig>>,
+  modified: Arc<Mutex<Option<SystemTime>>>,
+}
+
+fn modified_at(path: &str) -> Option<SystemTime> {
+  std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+impl SharedConfig {
+  /// Load the config file, the problems of all the entries are reported at once.
+  pub fn load_from(path: &str) -> Result<SharedConfig, Vec<String>> {
+    let modified = modified_at(path);
+    let config = load::load(path)?;
+    Ok(SharedConfig {
+      path: path.to_string(),
+      current: Arc::new(ArcSwap::from_pointee(config)),
+      modified: Arc::new(Mutex::new(modified)),
+    })
+  }
+
+  /// The current config, it is not changed by the later reloads.
+  pub fn load(&self) -> Arc<RepoConfig> {
+    self.current.load_full()
+  }
+
+  /// Read the file again and replace the current config if the new one is valid.
+  pub fn reload(&self) -> Result<(), Vec<String>> {
+    let modified = modified_at(&self.path);
+    *self.modified.lock().unwrap() = modified;
+    let config = load::load(&self.path)?;
+    self.current.store(Arc::new(config));
+    Ok(())
+  }
+
+  /// Whether the file has been modified since it was read.
+  fn changed(&self) -> bool {
+    *self.modified.lock().unwrap() != modified_at(&self.path)
+  }
+
+  fn reload_and_report(&self, why: &str) {
+    match self.reload() {
+      Ok(_) => info!("Reloaded the repo config {} ({}).", self.path, why),
+      Err(problems) => error!(
+        "Cannot reload the repo config {} ({}), the current one is kept: {}",
+        self.path,
+        why,
+        problems.join("; ")
+      ),
+    }
+  }
+
+  /// Reload the config on SIGHUP, and when the file is modified if the interval is given.
+  pub async fn watch(self, interval: Option<Duration>) {
+    let mut hangup =
+      match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup()) {
+        Ok(hangup) => hangup,
+        Err(e) => {
+          error!("Cannot listen to SIGHUP, the repo config is not reloaded by it: {}", e);
+          return;
+        }
+      };
+    let mut ticker = tokio::time::interval(interval.unwrap_or(Duration::from_secs(3600)));
+
+    loop {
+      tokio::select! {
+        _ = hangup.recv() => self.reload_and_report("SIGHUP"),
+        _ = ticker.tick() => {
+          if interval.is_some() && self.changed() {
+            self.reload_and_report("the file is modified");
+          }
+        }
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_reload() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("config.yaml");
+    let path = path.to_str().unwrap();
+    let s3 = |bucket: &str| {
+      format!(
+        "s3:\n  - access_key: a\n    access_secret: s\n    endpoint: http://127.0.0.1:9000\n    bucket: {}\n",
+        bucket
+      )
+    };
+    std::fs::write(path, s3("first")).unwrap();
+
+    let shared = SharedConfig::load_from(path).unwrap();
+    let before = shared.load();
+    assert!(!shared.changed());
+
+    std::fs::write(path, s3("second")).unwrap();
+    // The mtime of some filesystems is in seconds
+    *shared.modified.lock().unwrap() = None;
+    assert!(shared.changed());
+    shared.reload().unwrap();
+    assert_eq!(shared.load().s3.as_ref().unwrap()[0].bucket, "second");
+    // The config of a request in flight is not changed
+    assert_eq!(before.s3.as_ref().unwrap()[0].bucket, "first");
+
+    // An invalid config is not loaded
+    std::fs::write(path, s3("")).unwrap();
+    assert!(shared.reload().is_err());
+    assert_eq!(shared.load().s3.as_ref().unwrap()[0].bucket, "second");
+  }
+}
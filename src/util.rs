@@ -14,10 +14,18 @@ lazy_static! {
     m.insert("crc32", Regex::new(r"^[0-9a-f]{8}$").unwrap());
     m.insert("etag", Regex::new(r"^[0-9a-f]{32}(-\d+)?$").unwrap());
     m.insert("crc64", Regex::new(r"^[0-9a-f]{16}$").unwrap());
+    // The checksums of S3 are base64 encoded, such as x-amz-checksum-crc32c.
+    m.insert("crc32c", Regex::new(r"^([0-9a-f]{8}|[A-Za-z0-9+/]{6}==)$").unwrap());
+    m.insert("crc64nvme", Regex::new(r"^([0-9a-f]{16}|[A-Za-z0-9+/]{11}=)$").unwrap());
     m
   };
 }
 
+/// The supported hash types, the ambiguous hashes are detected as the first matched type. e.g. a hash with 32 hex digits is md5 rather than etag.
+pub const HASH_TYPES: [&str; 9] = [
+  "md5", "sha1", "sha256", "sha512", "crc32", "crc32c", "crc64", "crc64nvme", "etag",
+];
+
 /// Custom Error type for the graph module
 #[derive(Debug)]
 pub struct ValidationError {
@@ -59,50 +67,100 @@ pub fn validate_hash(hash: &str, algorithm: &str) -> bool {
 }
 
 pub fn which_hash_type(hash: &str) -> Option<&'static str> {
-  for (algorithm, regex) in ACCEPTABLE_HASHES.iter() {
-    if regex.is_match(hash) {
-      return Some(algorithm);
-    }
+  HASH_TYPES
+    .iter()
+    .find(|algorithm| validate_hash(hash, algorithm))
+    .copied()
+}
+
+/// Check the hash against the hash type, the hash type is detected when it is not given.
+pub fn check_hash(hash: &str, hash_type: Option<&str>) -> Result<&'static str, String> {
+  match hash_type {
+    Some(hash_type) => match HASH_TYPES.iter().find(|t| **t == hash_type) {
+      Some(t) if validate_hash(hash, t) => Ok(t),
+      Some(t) => Err(format!("Invalid {} hash: {}.", t, hash)),
+      None => Err(format!(
+        "Unsupported hash type: {}. Only support {}.",
+        hash_type,
+        HASH_TYPES.join(", ")
+      )),
+    },
+    None => which_hash_type(hash).ok_or_else(|| {
+      format!(
+        "Unsupported hash: {}. Only support {}.",
+        hash,
+        HASH_TYPES.join(", ")
+      )
+    }),
   }
-  None
 }
 
-lazy_static! {
-  static ref ACCEPTABLE_URLS: HashMap<&'static str, Regex> = {
-    let mut m = HashMap::new();
-    m.insert("node", Regex::new(r"^node://([a-zA-Z0-9@\-_.]+)/(OEP[0-9]+)/(OEX[0-9]+)/(OES[0-9]+)/(OER[0-9]+)/(OED[0-9]+)$").unwrap());
-    m.insert("oss", Regex::new(r"^oss://([a-zA-Z0-9@\-_.]+)/(.*)$").unwrap());
-    m.insert("s3", Regex::new(r"^s3://([a-zA-Z0-9@\-_.]+)/(.*)$").unwrap());
-    m.insert("gsa", Regex::new(r"^gsa://([a-zA-Z0-9@\-_.]+)/(HRA[0-9]+)/(HRS[0-9]+)/(HRX[0-9]+)/(HRR[0-9]+)/(HRR[0-9]+.*)$").unwrap());
-    m.insert("minio", Regex::new(r"^minio://([a-zA-Z0-9@\-_.]+)/(.*)$").unwrap());
-    m
-  };
+fn split_groups(groups: &str) -> Vec<&str> {
+  groups.split(',').map(|x| x.trim()).filter(|x| !x.is_empty()).collect()
 }
 
-pub fn validate_url(url: &str, protocol: &str) -> bool {
-  match ACCEPTABLE_URLS.get(protocol) {
-    Some(regex) => regex.is_match(url),
-    None => false,
+/// Whether one of the groups grants an item of the acl, both are comma separated.
+///
+/// A resource path grants itself and its children, e.g. /programs/pgx grants /programs/pgx/projects/quartet.
+pub fn has_permission(auth_groups: &str, acl: &str) -> bool {
+  let acl = split_groups(acl);
+  split_groups(auth_groups).iter().any(|group| {
+    acl.iter().any(|item| {
+      if group == item {
+        return true;
+      }
+
+      let parent = group.trim_end_matches('/');
+      is_resource_path(group) && item.starts_with(parent) && item[parent.len()..].starts_with('/')
+    })
+  })
+}
+
+/// A resource path is an item of the acl which starts with a slash, such as /programs/pgx/projects/quartet.
+pub fn is_resource_path(item: &str) -> bool {
+  item.starts_with('/')
+}
+
+/// The resource path must be like /programs/pgx/projects/quartet, without empty parts or commas.
+pub fn check_resource_path(path: &str) -> Result<(), String> {
+  let parts: Vec<&str> = path.split('/').skip(1).collect();
+  if !is_resource_path(path)
+    || parts.iter().any(|part| part.is_empty() || part.contains(',') || part.trim() != *part)
+  {
+    return Err(format!("Invalid resource path: {:?}.", path));
   }
+
+  Ok(())
 }
 
-pub fn which_protocol(url: &str) -> Option<&'static str> {
-  for (protocol, regex) in ACCEPTABLE_URLS.iter() {
-    if regex.is_match(url) {
-      return Some(protocol);
+/// Join the groups and the resource paths into an acl, None means a public file.
+pub fn join_acl(groups: &[String], resource_paths: &[String]) -> Result<Option<String>, String> {
+  for group in groups {
+    if group.trim().is_empty() || group.contains(',') || is_resource_path(group) {
+      return Err(format!("Invalid group: {:?}.", group));
     }
   }
-  None
+
+  for path in resource_paths {
+    check_resource_path(path)?;
+  }
+
+  let items: Vec<&str> = groups.iter().chain(resource_paths).map(|x| x.trim()).collect();
+  if items.is_empty() {
+    Ok(None)
+  } else {
+    Ok(Some(items.join(",")))
+  }
 }
 
-pub fn has_permission(auth_groups: &str, acl: &str) -> bool {
-  let mut auth_group_vec: Vec<&str> = auth_groups.split(',').collect::<Vec<_>>().iter().map(|x| x.trim()).collect::<Vec<_>>();
-  let mut acl_vec: Vec<&str> = acl.split(',').collect::<Vec<_>>().iter().map(|x| x.trim()).collect::<Vec<_>>();
-  auth_group_vec.append(&mut acl_vec);
-  let length = auth_group_vec.len();
-  auth_group_vec.sort_unstable();
-  auth_group_vec.dedup();
-  return auth_group_vec.len() != length;
+/// Split the acl into the groups and the resource paths.
+pub fn split_acl(acl: &str) -> (Vec<String>, Vec<String>) {
+  let (paths, groups): (Vec<&str>, Vec<&str>) =
+    split_groups(acl).into_iter().partition(|item| is_resource_path(item));
+  (
+    groups.into_iter().map(String::from).collect(),
+    paths.into_iter().map(String::from).collect(),
+  )
 }
 
 pub fn get_delimiter(filepath: &PathBuf) -> Result<u8, Box<dyn Error>> {
@@ -141,3 +199,138 @@ pub fn parse_csv_error(e: &csv::Error) -> String {
         }
     }
 }
+
+/// Parse the `Range` header against the size of the content, return the first and the last byte of the range.
+///
+/// Only a single byte range is supported, the invalid or multiple ranges are ignored and the whole content is served.
+/// An error means the range is not satisfiable.
+pub fn parse_range(range: &str, size: u64) -> Result<Option<(u64, u64)>, String> {
+  let spec = match range.trim().strip_prefix("bytes=") {
+    Some(spec) if !spec.contains(',') => spec.trim(),
+    _ => return Ok(None),
+  };
+  let (start, end) = match spec.split_once('-') {
+    Some(v) => v,
+    None => return Ok(None),
+  };
+  let unsatisfiable = || Err(format!("The range {} is not satisfiable, the size is {}", range, size));
+
+  match (start.parse::<u64>(), end.parse::<u64>()) {
+    // bytes=-500, the last 500 bytes
+    (Err(_), Ok(suffix)) if start.is_empty() => {
+      if suffix == 0 || size == 0 {
+        return unsatisfiable();
+      }
+      Ok(Some((size.saturating_sub(suffix), size - 1)))
+    }
+    // bytes=500-
+    (Ok(start), Err(_)) if end.is_empty() => {
+      if start >= size {
+        return unsatisfiable();
+      }
+      Ok(Some((start, size - 1)))
+    }
+    // bytes=0-499
+    (Ok(start), Ok(end)) if start <= end => {
+      if start >= size {
+        return unsatisfiable();
+      }
+      Ok(Some((start, end.min(size - 1))))
+    }
+    _ => Ok(None),
+  }
+}
+
+/// Format the milliseconds since epoch as a RFC 3339 time in UTC, such as 2024-01-01T00:00:00Z.
+pub fn to_rfc3339(timestamp_ms: i64) -> String {
+  chrono::DateTime::<chrono::Utc>::from_timestamp_millis(timestamp_ms)
+    .map(|t| t.to_rfc3339_opts(chrono::SecondsFormat::Secs, true))
+    .unwrap_or_default()
+}
+
+/// Decode a path param, the path params are not percent-decoded by the router, such as the slash of a guid in %2F.
+pub fn decode_path_param(param: &str) -> String {
+  percent_encoding::percent_decode_str(param).decode_utf8_lossy().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_which_hash_type() {
+    assert_eq!(which_hash_type("d41d8cd98f00b204e9800998ecf8427e"), Some("md5"));
+    assert_eq!(which_hash_type("d41d8cd98f00b204e9800998ecf8427e-3"), Some("etag"));
+    assert_eq!(which_hash_type("a9421b04"), Some("crc32"));
+    assert_eq!(which_hash_type("yZRlqg=="), Some("crc32c"));
+    assert_eq!(which_hash_type("AAAAAAAAAAA="), Some("crc64nvme"));
+    assert_eq!(which_hash_type("not-a-hash"), None);
+  }
+
+  #[test]
+  fn test_check_hash() {
+    assert_eq!(check_hash("yZRlqg==", Some("crc32c")), Ok("crc32c"));
+    assert_eq!(check_hash("d41d8cd98f00b204e9800998ecf8427e", Some("etag")), Ok("etag"));
+    assert_eq!(check_hash("d41d8cd98f00b204e9800998ecf8427e", None), Ok("md5"));
+    assert!(check_hash("yZRlqg==", Some("md5")).is_err());
+    assert!(check_hash("d41d8cd98f00b204e9800998ecf8427e", Some("blake3")).is_err());
+    assert!(check_hash("not-a-hash", None).is_err());
+  }
+
+  #[test]
+  fn test_parse_range() {
+    assert_eq!(parse_range("bytes=0-4", 12), Ok(Some((0, 4))));
+    assert_eq!(parse_range("bytes=6-", 12), Ok(Some((6, 11))));
+    assert_eq!(parse_range("bytes=-5", 12), Ok(Some((7, 11))));
+    assert_eq!(parse_range("bytes=-50", 12), Ok(Some((0, 11))));
+    assert_eq!(parse_range("bytes=10-100", 12), Ok(Some((10, 11))));
+    assert_eq!(parse_range("bytes=0-1,4-5", 12), Ok(None));
+    assert_eq!(parse_range("items=0-4", 12), Ok(None));
+    assert_eq!(parse_range("bytes=4-1", 12), Ok(None));
+    assert!(parse_range("bytes=12-", 12).is_err());
+    assert!(parse_range("bytes=-0", 12).is_err());
+  }
+
+  #[test]
+  fn test_decode_path_param() {
+    let guid = "biominer.fudan-pgx/3ec4d151-061b-4bcb-ad3a-425c712bfc88";
+    let encoded = "biominer.fudan-pgx%2F3ec4d151-061b-4bcb-ad3a-425c712bfc88";
+    assert_eq!(decode_path_param(encoded), guid);
+    assert_eq!(decode_path_param(guid), guid);
+    assert_eq!(decode_path_param("a%20b+c"), "a b+c");
+  }
+
+  #[test]
+  fn test_has_permission() {
+    assert!(has_permission("admin, fudan-pgx", "fudan-pgx,test"));
+    assert!(!has_permission("admin", "fudan-pgx"));
+    assert!(!has_permission("", ""));
+    assert!(!has_permission("admin,", ",test"));
+
+    // A resource path grants itself and its children
+    let acl = "test,/programs/pgx/projects/quartet";
+    assert!(has_permission("/programs/pgx/projects/quartet", acl));
+    assert!(has_permission("/programs/pgx", acl));
+    assert!(has_permission("/programs/pgx/", acl));
+    assert!(has_permission("/", acl));
+    assert!(!has_permission("/programs/pg", acl));
+    assert!(!has_permission("/programs/pgx/projects/quartet/samples", acl));
+    assert!(!has_permission("programs", "programs/pgx"));
+  }
+
+  #[test]
+  fn test_join_acl() {
+    let groups = vec!["admin".to_string()];
+    let paths = vec!["/programs/pgx".to_string()];
+    assert_eq!(join_acl(&groups, &paths), Ok(Some("admin,/programs/pgx".to_string())));
+    assert_eq!(join_acl(&[], &[]), Ok(None));
+    assert_eq!(split_acl("admin, /programs/pgx"), (groups, paths));
+
+    assert!(join_acl(&["/programs".to_string()], &[]).is_err());
+    assert!(join_acl(&["a,b".to_string()], &[]).is_err());
+    assert!(join_acl(&[], &["programs/pgx".to_string()]).is_err());
+    assert!(join_acl(&[], &["/programs//pgx".to_string()]).is_err());
+    assert!(join_acl(&[], &["/programs/pgx/".to_string()]).is_err());
+    assert!(join_acl(&[], &["/".to_string()]).is_err());
+  }
+}
@@ -117,20 +117,12 @@ const FileList: React.FC = () => {
   const downloadFile = (apiSignData: API.SignData, filename: string, repo: string) => {
     // console.log("Download selected file: ", apiSignData);
 
-    const customHeaders: Object[] = map(apiSignData.header, (item: string) => {
-      const m: Object = {}
-      const itemLst = item.split(":")
-      // @ts-ignore
-      m[itemLst[0]] = itemLst[1].trim()
-      return m
+    const customHeaders: Object[] = map(apiSignData.headers, (item: API.SignField) => {
+      return { [item.name]: item.value }
     });
 
-    const data: Object[] = map(apiSignData.data, (item: string) => {
-      const m: Object = {}
-      const itemLst = item.split("=")
-      // @ts-ignore
-      m[itemLst[0]] = itemLst[1].trim()
-      return m
+    const data: Object[] = map(apiSignData.form, (item: API.SignField) => {
+      return { [item.name]: item.value }
     });
 
     const params: Object[] = map(apiSignData.params, (item: string) => {
@@ -223,12 +223,18 @@ declare namespace API {
     page_size: number;
   };
 
+  type SignField = {
+    name: string;
+    value: string;
+  };
+
   type SignData = {
-    header: string[];
-    data: string[];
+    headers: SignField[];
+    form: SignField[];
     baseurl: string;
     method: string;
     params: string[];
+    expires_at?: number;
   };
 
   type signFileParams = {
@@ -246,5 +252,7 @@ declare namespace API {
     size: number;
     hashes: Hash[];
     filename: string;
+    which_repo: string;
+    reason: string;
   };
 }